@@ -74,4 +74,46 @@ pub enum ErrorCode {
     InvalidVault,
     #[msg("Collateral mint does not match margin account")]
     InvalidCollateralMint,
-}
\ No newline at end of file
+    #[msg("Orders do not cross")]
+    OrdersDoNotCross,
+    #[msg("Bracket legs are not active until the entry order has filled")]
+    BracketNotActive,
+    #[msg("Trigger price has not been reached")]
+    TriggerNotReached,
+    #[msg("Order would exceed the market's open interest cap")]
+    OpenInterestCapExceeded,
+    #[msg("Market has no bad debt to cover")]
+    NoBadDebt,
+    #[msg("Position is not eligible for auto-deleveraging")]
+    AdlCandidateNotEligible,
+    #[msg("Position is too large for direct liquidation and must be auctioned")]
+    LiquidationAuctionRequired,
+    #[msg("Position is below the liquidation auction size threshold")]
+    LiquidationAuctionNotRequired,
+    #[msg("Liquidation auction has ended")]
+    LiquidationAuctionEnded,
+    #[msg("Liquidation size exceeds what is needed to restore the position")]
+    LiquidationSizeExceeded,
+    #[msg("Trade exceeds the vAMM's liquidity")]
+    InsufficientAmmLiquidity,
+    #[msg("vAMM adjustment cost exceeds its fee pool budget")]
+    AmmAdjustmentBudgetExceeded,
+    #[msg("LP withdrawal cooldown has not passed")]
+    LpWithdrawalCooldown,
+    #[msg("LP pool has no value left to back its shares")]
+    LpPoolInsolvent,
+    #[msg("Insufficient LP shares")]
+    InsufficientLpShares,
+    #[msg("Fill side does not match the existing position")]
+    PositionSideMismatch,
+    #[msg("Leverage does not match the existing position")]
+    LeverageMismatch,
+    #[msg("Position still has open orders")]
+    PositionHasOpenOrders,
+    #[msg("vAMM fill price deviates too far from the oracle price")]
+    OracleDeviationExceeded,
+    #[msg("LP withdrawal request has expired")]
+    LpWithdrawalExpired,
+    #[msg("Deleveraging candidates are not in ranking order")]
+    AdlCandidatesNotRanked,
+}
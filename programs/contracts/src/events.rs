@@ -1,5 +1,5 @@
 use anchor_lang::prelude::*;
-use crate::{Side, OrderType, MarginType};
+use crate::{Side, OrderType, MarginTier, MarginType, SelfTradePrevention};
 
 // Market Events
 #[event]
@@ -13,6 +13,13 @@ pub struct MarketInitializedEvent {
     pub initial_margin_ratio: u64,
     pub max_leverage: u64,
     pub liquidation_fee_ratio: u64,
+    pub min_order_size: u64,
+    pub order_step_size: u64,
+    pub max_position_size: u64,
+    pub max_open_interest: u64,
+    pub base_asset_reserve: u64,
+    pub quote_asset_reserve: u64,
+    pub peg_multiplier: u64,
 }
 
 #[event]
@@ -23,6 +30,44 @@ pub struct MarketParamsUpdatedEvent {
     pub initial_margin_ratio: u64,
     pub funding_interval: i64,
     pub max_leverage: u64,
+    pub min_order_size: u64,
+    pub order_step_size: u64,
+    pub max_position_size: u64,
+    pub max_open_interest: u64,
+}
+
+#[event]
+pub struct LiquidationAuctionParamsUpdatedEvent {
+    pub market: Pubkey,
+    pub authority: Pubkey,
+    pub size_threshold: u64,
+    pub duration_slots: u64,
+    pub start_discount: u64,
+    pub max_discount: u64,
+}
+
+#[event]
+pub struct MarginTiersUpdatedEvent {
+    pub market: Pubkey,
+    pub authority: Pubkey,
+    pub tiers: Vec<MarginTier>,
+}
+
+#[event]
+pub struct MarketMigratedEvent {
+    pub market: Pubkey,
+    pub authority: Pubkey,
+    pub old_space: u64,
+    pub new_space: u64,
+}
+
+#[event]
+pub struct PositionMigratedEvent {
+    pub market: Pubkey,
+    pub position: Pubkey,
+    pub trader: Pubkey,
+    pub old_space: u64,
+    pub new_space: u64,
 }
 
 #[event]
@@ -54,6 +99,16 @@ pub struct FundingUpdatedEvent {
     pub timestamp: i64,
 }
 
+#[event]
+pub struct FundingSettledEvent {
+    pub market: Pubkey,
+    pub position: Pubkey,
+    pub trader: Pubkey,
+    pub payment: i64,
+    pub cumulative_funding_rate: i64,
+    pub timestamp: i64,
+}
+
 // Position Events
 #[event]
 pub struct PositionOpenedEvent {
@@ -69,6 +124,36 @@ pub struct PositionOpenedEvent {
     pub margin_type: MarginType,
 }
 
+#[event]
+pub struct PositionIncreasedEvent {
+    pub market: Pubkey,
+    pub position: Pubkey,
+    pub trader: Pubkey,
+    pub side: Side,
+    pub size_added: u64,
+    pub new_size: u64,
+    pub collateral_added: u64,
+    pub new_collateral: u64,
+    pub fill_price: u64,
+    pub entry_price: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PositionReducedEvent {
+    pub market: Pubkey,
+    pub position: Pubkey,
+    pub trader: Pubkey,
+    pub side: Side,
+    pub size_reduced: u64,
+    pub remaining_size: u64,
+    pub released_collateral: u64,
+    pub entry_price: u64,
+    pub exit_price: u64,
+    pub realized_pnl: i64,
+    pub margin_type: MarginType,
+}
+
 #[event]
 pub struct PositionClosedEvent {
     pub market: Pubkey,
@@ -90,6 +175,7 @@ pub struct PositionLiquidatedEvent {
     pub trader: Pubkey,
     pub side: Side,
     pub size: u64,
+    pub remaining_size: u64,
     pub collateral: u64,
     pub entry_price: u64,
     pub exit_price: u64,
@@ -106,6 +192,111 @@ pub struct MarginAdjustedEvent {
     pub trader: Pubkey,
     pub margin_change: i64,
     pub new_collateral: u64,
+    pub new_leverage: u64,
+    pub liquidation_price: u64,
+}
+
+#[event]
+pub struct PositionLeverageUpdatedEvent {
+    pub market: Pubkey,
+    pub position: Pubkey,
+    pub trader: Pubkey,
+    pub old_leverage: u64,
+    pub new_leverage: u64,
+    pub old_collateral: u64,
+    pub new_collateral: u64,
+    pub liquidation_price: u64,
+    pub margin_type: MarginType,
+}
+
+#[event]
+pub struct PositionTransferredEvent {
+    pub market: Pubkey,
+    pub position: Pubkey,
+    pub trader: Pubkey,
+    pub from_margin_account: Pubkey,
+    pub to_margin_account: Pubkey,
+    pub from_margin_type: MarginType,
+    pub to_margin_type: MarginType,
+    pub collateral: u64,
+    pub liquidation_price: u64,
+}
+
+#[event]
+pub struct PositionDeleveragedEvent {
+    pub market: Pubkey,
+    pub position: Pubkey,
+    pub trader: Pubkey,
+    pub side: Side,
+    pub size_reduced: u64,
+    pub remaining_size: u64,
+    pub mark_price: u64,
+    pub bad_debt_covered: u64,
+    pub remaining_bad_debt: u64,
+    pub fill_price: u64,
+}
+
+#[event]
+pub struct InsuranceFundUsedEvent {
+    pub market: Pubkey,
+    pub amount: u64,
+    pub insurance_fund: u64,
+}
+
+#[event]
+pub struct BadDebtRecordedEvent {
+    pub market: Pubkey,
+    pub position: Pubkey,
+    pub deficit: u64,
+    pub insurance_fund_covered: u64,
+    pub uncovered: u64,
+    pub bad_debt: u64,
+    pub adl_side: Side,
+    pub bankruptcy_price: u64,
+}
+
+#[event]
+pub struct LiquidationAuctionStartedEvent {
+    pub market: Pubkey,
+    pub position: Pubkey,
+    pub trader: Pubkey,
+    pub auction: Pubkey,
+    pub size: u64,
+    pub start_slot: u64,
+    pub end_slot: u64,
+}
+
+#[event]
+pub struct LiquidationAuctionTakenEvent {
+    pub market: Pubkey,
+    pub position: Pubkey,
+    pub trader: Pubkey,
+    pub liquidator: Pubkey,
+    pub liquidator_position: Pubkey,
+    pub size: u64,
+    pub oracle_price: u64,
+    pub execution_price: u64,
+    pub discount: u64,
+}
+
+#[event]
+pub struct PositionTakenOverEvent {
+    pub market: Pubkey,
+    pub position: Pubkey,
+    pub trader: Pubkey,
+    pub liquidator: Pubkey,
+    pub liquidator_position: Pubkey,
+    pub size: u64,
+    pub remaining_size: u64,
+    pub oracle_price: u64,
+    pub execution_price: u64,
+}
+
+#[event]
+pub struct LiquidationAuctionCancelledEvent {
+    pub market: Pubkey,
+    pub position: Pubkey,
+    pub auction: Pubkey,
 }
 
 // Order Events
@@ -114,6 +305,7 @@ pub struct OrderPlacedEvent {
     pub market: Pubkey,
     pub position: Pubkey,
     pub trader: Pubkey,
+    pub client_order_id: u64,
     pub side: Side,
     pub order_type: OrderType,
     pub price: u64,
@@ -127,6 +319,7 @@ pub struct OrderFilledEvent {
     pub market: Pubkey,
     pub position: Pubkey,
     pub trader: Pubkey,
+    pub client_order_id: u64,
     pub side: Side,
     pub price: u64,
     pub size: u64,
@@ -134,11 +327,28 @@ pub struct OrderFilledEvent {
     pub timestamp: i64,
 }
 
+#[event]
+pub struct SelfTradePreventedEvent {
+    pub market: Pubkey,
+    pub maker_order: Pubkey,
+    pub taker_order: Pubkey,
+    pub maker: Pubkey,
+    pub taker: Pubkey,
+    pub maker_client_order_id: u64,
+    pub taker_client_order_id: u64,
+    pub stp_mode: SelfTradePrevention,
+    pub price: u64,
+    pub size: u64,
+    pub timestamp: i64,
+}
+
 #[event]
 pub struct OrderCancelledEvent {
     pub market: Pubkey,
     pub order: Pubkey,
     pub trader: Pubkey,
+    pub client_order_id: u64,
+    pub released_collateral: u64,
 }
 
 // Collateral Events
@@ -164,4 +374,119 @@ pub struct MarginAccountCreated {
     pub margin_account: Pubkey,
     pub margin_type: MarginType,
     pub timestamp: i64,
-}
\ No newline at end of file
+}
+
+#[event]
+pub struct MarginAccountMigrated {
+    pub owner: Pubkey,
+    pub margin_account: Pubkey,
+    pub collateral_market: Pubkey,
+    pub old_space: u64,
+    pub new_space: u64,
+}
+
+#[event]
+pub struct AmmRepeggedEvent {
+    pub market: Pubkey,
+    pub keeper: Pubkey,
+    pub oracle_price: u64,
+    pub old_peg_multiplier: u64,
+    pub new_peg_multiplier: u64,
+    pub cost: i64,                // Charged to fee_pool when positive, credited when negative
+    pub fee_pool: u64,
+}
+
+#[event]
+pub struct AmmKUpdatedEvent {
+    pub market: Pubkey,
+    pub authority: Pubkey,
+    pub old_base_asset_reserve: u64,
+    pub old_quote_asset_reserve: u64,
+    pub new_base_asset_reserve: u64,
+    pub new_quote_asset_reserve: u64,
+    pub cost: i64,                // Charged to fee_pool when positive, credited when negative
+    pub fee_pool: u64,
+}
+
+#[event]
+pub struct AmmBudgetUpdatedEvent {
+    pub market: Pubkey,
+    pub authority: Pubkey,
+    pub max_adjustment_cost: u64,
+    pub fee_pool_share: u64,
+}
+
+#[event]
+pub struct SpreadChargedEvent {
+    pub market: Pubkey,
+    pub side: Side,
+    pub size: u64,
+    pub reference_price: u64,     // vAMM price before the spread
+    pub execution_price: u64,
+    pub half_spread: u64,         // In basis points
+    pub revenue: u64,             // Credited to fee_pool
+    pub fee_pool: u64,
+}
+
+#[event]
+pub struct SpreadParamsUpdatedEvent {
+    pub market: Pubkey,
+    pub authority: Pubkey,
+    pub base_spread: u64,
+    pub max_spread: u64,
+    pub skew_spread: u64,
+}
+
+#[event]
+pub struct OracleBandUpdatedEvent {
+    pub market: Pubkey,
+    pub authority: Pubkey,
+    pub max_oracle_deviation: u64,  // In basis points, 0 disables the band
+}
+
+#[event]
+pub struct LpPoolInitializedEvent {
+    pub market: Pubkey,
+    pub pool: Pubkey,
+    pub share_mint: Pubkey,
+    pub withdrawal_cooldown: i64,
+    pub withdrawal_window: i64,
+    pub vault: Pubkey,
+}
+
+#[event]
+pub struct LpPoolSettledEvent {
+    pub pool: Pubkey,
+    pub pnl: i64,                 // Counterparty PnL moved into (positive) or out of the pool vault
+    pub fees: u64,                // Fee pool growth moved into the pool vault
+    pub lp_balance: u64,          // Pool vault balance after the settlement
+}
+
+#[event]
+pub struct LpDepositedEvent {
+    pub pool: Pubkey,
+    pub owner: Pubkey,
+    pub amount: u64,
+    pub shares: u64,
+    pub nav: i64,                 // Pool value before the deposit
+    pub share_supply: u64,        // Share supply after the deposit
+}
+
+#[event]
+pub struct LpWithdrawalRequestedEvent {
+    pub pool: Pubkey,
+    pub owner: Pubkey,
+    pub shares: u64,
+    pub available_at: i64,
+    pub expires_at: i64,
+}
+
+#[event]
+pub struct LpWithdrawnEvent {
+    pub pool: Pubkey,
+    pub owner: Pubkey,
+    pub shares: u64,
+    pub amount: u64,
+    pub nav: i64,                 // Pool value before the withdrawal
+    pub share_supply: u64,        // Share supply after the withdrawal
+}
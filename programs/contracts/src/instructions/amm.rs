@@ -0,0 +1,199 @@
+use anchor_lang::prelude::*;
+use crate::{
+    errors::ErrorCode,
+    events::*,
+    risk::{load_oracle, oracle_price},
+    vamm::{adjustment_cost, mark_price, quote_trade_with_spread, settle_adjustment_cost, AmmQuote},
+    Market, Side,
+};
+
+#[derive(Accounts)]
+pub struct GetExecutionPrice<'info> {
+    pub market: Account<'info, Market>,
+    /// CHECK: Oracle account - validated in instruction
+    #[account(address = market.oracle @ ErrorCode::InvalidOracleAccount)]
+    pub price_update: UncheckedAccount<'info>,
+}
+
+/// Report, through return data, the price a market order of `size` on `side` would fill at,
+/// including the half spread it would pay
+pub fn get_execution_price(ctx: Context<GetExecutionPrice>, side: Side, size: u64) -> Result<AmmQuote> {
+    let oracle = load_oracle(&ctx.accounts.price_update)?;
+    quote_trade_with_spread(&ctx.accounts.market, side, size, &oracle)
+}
+
+#[derive(Accounts)]
+pub struct RepegAmm<'info> {
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+    /// Permissionless keeper that realigns the vAMM with the oracle
+    pub keeper: Signer<'info>,
+    /// CHECK: Oracle account - validated in instruction
+    #[account(address = market.oracle @ ErrorCode::InvalidOracleAccount)]
+    pub price_update: UncheckedAccount<'info>,
+}
+
+/// Move the peg multiplier so the vAMM's mark price matches the oracle price. Reserves are
+/// unchanged, so the cost is the change in what users' net position is worth on the curve.
+pub fn repeg_amm(ctx: Context<RepegAmm>) -> Result<()> {
+    let market = &mut ctx.accounts.market;
+    let current_price = oracle_price(&ctx.accounts.price_update)?;
+
+    let new_peg_multiplier = (current_price as u128)
+        .checked_mul(market.base_asset_reserve as u128)
+        .and_then(|value| value.checked_div(market.quote_asset_reserve as u128))
+        .and_then(|peg| u64::try_from(peg).ok())
+        .ok_or(ErrorCode::MathOverflow)?;
+    require!(new_peg_multiplier > 0, ErrorCode::InvalidParameter);
+
+    let old_peg_multiplier = market.peg_multiplier;
+    let cost = adjustment_cost(market, market.base_asset_reserve, market.quote_asset_reserve, new_peg_multiplier)?;
+    settle_adjustment_cost(market, cost)?;
+    market.peg_multiplier = new_peg_multiplier;
+    msg!(
+        "Repegged vAMM from {} to {}, mark price: {}, oracle price: {}",
+        old_peg_multiplier,
+        new_peg_multiplier,
+        mark_price(market)?,
+        current_price
+    );
+
+    emit!(AmmRepeggedEvent {
+        market: market.key(),
+        keeper: ctx.accounts.keeper.key(),
+        oracle_price: current_price,
+        old_peg_multiplier,
+        new_peg_multiplier,
+        cost,
+        fee_pool: market.fee_pool,
+    });
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct UpdateAmmK<'info> {
+    #[account(mut, has_one = authority)]
+    pub market: Account<'info, Market>,
+    pub authority: Signer<'info>,
+}
+
+/// Change the vAMM's depth by rescaling both reserves to a new base reserve. The quote reserve
+/// scales by the same ratio, which keeps the mark price. Deeper curves cost the fee pool when
+/// users hold a net position; shallower ones pay it.
+pub fn update_amm_k(ctx: Context<UpdateAmmK>, base_asset_reserve: u64) -> Result<()> {
+    let market = &mut ctx.accounts.market;
+    require!(base_asset_reserve > 0, ErrorCode::InvalidParameter);
+
+    let quote_asset_reserve = (market.quote_asset_reserve as u128)
+        .checked_mul(base_asset_reserve as u128)
+        .and_then(|value| value.checked_div(market.base_asset_reserve as u128))
+        .and_then(|reserve| u64::try_from(reserve).ok())
+        .ok_or(ErrorCode::MathOverflow)?;
+    require!(quote_asset_reserve > 0, ErrorCode::InvalidParameter);
+
+    let old_base_asset_reserve = market.base_asset_reserve;
+    let old_quote_asset_reserve = market.quote_asset_reserve;
+    let cost = adjustment_cost(market, base_asset_reserve, quote_asset_reserve, market.peg_multiplier)?;
+    settle_adjustment_cost(market, cost)?;
+    market.base_asset_reserve = base_asset_reserve;
+    market.quote_asset_reserve = quote_asset_reserve;
+
+    emit!(AmmKUpdatedEvent {
+        market: market.key(),
+        authority: ctx.accounts.authority.key(),
+        old_base_asset_reserve,
+        old_quote_asset_reserve,
+        new_base_asset_reserve: base_asset_reserve,
+        new_quote_asset_reserve: quote_asset_reserve,
+        cost,
+        fee_pool: market.fee_pool,
+    });
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct UpdateAmmBudget<'info> {
+    #[account(mut, has_one = authority)]
+    pub market: Account<'info, Market>,
+    pub authority: Signer<'info>,
+}
+
+/// Limit what a single repeg or k adjustment may spend: at most `max_adjustment_cost`, and at
+/// most `fee_pool_share` basis points of the fee pool
+pub fn update_amm_budget(ctx: Context<UpdateAmmBudget>, max_adjustment_cost: u64, fee_pool_share: u64) -> Result<()> {
+    let market = &mut ctx.accounts.market;
+    require!(fee_pool_share <= 10000, ErrorCode::InvalidParameter);
+
+    market.amm_max_adjustment_cost = max_adjustment_cost;
+    market.amm_fee_pool_share = fee_pool_share;
+
+    emit!(AmmBudgetUpdatedEvent {
+        market: market.key(),
+        authority: ctx.accounts.authority.key(),
+        max_adjustment_cost,
+        fee_pool_share,
+    });
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct UpdateSpreadParams<'info> {
+    #[account(mut, has_one = authority)]
+    pub market: Account<'info, Market>,
+    pub authority: Signer<'info>,
+}
+
+/// Configure the dynamic half spread, in basis points. Volatility and oracle confidence are added
+/// to `base_spread`, and `skew_spread` is charged in proportion to open interest imbalance on
+/// trades that add to it. The total never exceeds `max_spread`; a `max_spread` of 0 disables it.
+pub fn update_spread_params(
+    ctx: Context<UpdateSpreadParams>,
+    base_spread: u64,
+    max_spread: u64,
+    skew_spread: u64,
+) -> Result<()> {
+    let market = &mut ctx.accounts.market;
+    require!(max_spread < 10000, ErrorCode::InvalidParameter);
+    require!(base_spread <= max_spread && skew_spread <= max_spread, ErrorCode::InvalidParameter);
+
+    market.base_spread = base_spread;
+    market.max_spread = max_spread;
+    market.skew_spread = skew_spread;
+
+    emit!(SpreadParamsUpdatedEvent {
+        market: market.key(),
+        authority: ctx.accounts.authority.key(),
+        base_spread,
+        max_spread,
+        skew_spread,
+    });
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct UpdateOracleBand<'info> {
+    #[account(mut, has_one = authority)]
+    pub market: Account<'info, Market>,
+    pub authority: Signer<'info>,
+}
+
+/// Reject vAMM fills whose curve price is more than `max_oracle_deviation` basis points away
+/// from the oracle price. A value of 0 disables the band.
+pub fn update_oracle_band(ctx: Context<UpdateOracleBand>, max_oracle_deviation: u64) -> Result<()> {
+    let market = &mut ctx.accounts.market;
+    require!(max_oracle_deviation < 10000, ErrorCode::InvalidParameter);
+
+    market.max_oracle_deviation = max_oracle_deviation;
+
+    emit!(OracleBandUpdatedEvent {
+        market: market.key(),
+        authority: ctx.accounts.authority.key(),
+        max_oracle_deviation,
+    });
+
+    Ok(())
+}
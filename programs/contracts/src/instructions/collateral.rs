@@ -1,6 +1,11 @@
 use anchor_lang::prelude::*;
+use anchor_lang::system_program;
+use anchor_lang::Discriminator;
 use anchor_spl::token::{self, Mint, Token, TokenAccount, Transfer};
-use crate::{Market, MarginAccount, Position, MarginType, errors::ErrorCode, events::*};
+use crate::{
+    Market, MarginAccount, MarginType, errors::ErrorCode, events::*,
+    risk::{account_health, load_position_health, AccountHealth},
+};
 
 /// Deposit collateral into a margin account
 pub fn deposit_collateral(ctx: Context<DepositCollateral>, amount: u64) -> Result<()> {
@@ -19,8 +24,14 @@ pub fn deposit_collateral(ctx: Context<DepositCollateral>, amount: u64) -> Resul
     );
     token::transfer(transfer_ctx, amount)?;
 
-    // Update margin account collateral
+    // Collateral is held in a single vault, so every deposit goes to the same market. Accounts
+    // that already hold collateral without a recorded market are pinned by `migrate_margin_account`.
     let margin_account = &mut ctx.accounts.margin_account;
+    if margin_account.collateral_market == Pubkey::default() {
+        margin_account.collateral_market = ctx.accounts.market.key();
+    }
+
+    // Update margin account collateral
     margin_account.collateral = margin_account.collateral
         .checked_add(amount)
         .ok_or(ErrorCode::MathOverflow)?;
@@ -49,43 +60,24 @@ pub fn withdraw_collateral<'info>(
 
     let market = &ctx.accounts.market;
     
-    // Get positions from remaining accounts
-    let positions = ctx.remaining_accounts
-        .iter()
-        .map(|account| Account::<Position>::try_from(account))
-        .collect::<Result<Vec<_>>>()?;
-
-    // Validate that all positions in margin_account.positions are provided
-    for position_key in &margin_account.positions {
-        if !positions.iter().any(|p| p.key() == *position_key) {
-            return Err(ErrorCode::InvalidPosition.into());
-        }
-    }
+    // Positions are passed via remaining accounts as (position, market, oracle) triples
+    let positions = load_position_health(margin_account, ctx.remaining_accounts)?;
+    let health = account_health(margin_account, positions)?;
 
-    // Calculate required margin based on margin type
-    let required_margin = match margin_account.margin_type {
+    // Isolated accounts can only withdraw unallocated collateral, cross accounts
+    // can only withdraw equity above the initial margin of their open positions
+    match margin_account.margin_type {
         MarginType::Isolated => {
-            // For isolated margin, we need to ensure we don't withdraw allocated margin
-            let allocated_margin = margin_account.allocated_margin;
-            let available_margin = margin_account.available_margin()?;
-            
-            // Ensure we're not withdrawing more than available margin
-            require!(amount <= available_margin, ErrorCode::WithdrawalExceedsAvailableMargin);
-            
-            // Return allocated margin as required margin
-            allocated_margin
+            require!(amount <= health.free_collateral, ErrorCode::WithdrawalExceedsAvailableMargin);
         },
         MarginType::Cross => {
-            // For cross margin, calculate required margin based on all positions
-            calculate_required_margin(margin_account, &positions, market)?
+            require!(amount <= health.free_collateral, ErrorCode::WithdrawalBelowMaintenanceMargin);
         }
-    };
+    }
 
-    // Check if withdrawal would leave enough margin
     let remaining_collateral = margin_account.collateral
         .checked_sub(amount)
         .ok_or(ErrorCode::MathOverflow)?;
-    require!(remaining_collateral >= required_margin, ErrorCode::WithdrawalBelowMaintenanceMargin);
 
     // Update margin account collateral
     margin_account.collateral = remaining_collateral;
@@ -131,6 +123,7 @@ pub fn create_margin_account(ctx: Context<CreateMarginAccount>, margin_type: Mar
     margin_account.allocated_margin = 0;
     margin_account.positions = Vec::new();
     margin_account.bump = bump;
+    margin_account.collateral_market = Pubkey::default();
 
     emit!(MarginAccountCreated {
         owner: ctx.accounts.owner.key(),
@@ -142,31 +135,98 @@ pub fn create_margin_account(ctx: Context<CreateMarginAccount>, margin_type: Mar
     Ok(())
 }
 
-/// Helper function to calculate required margin
-fn calculate_required_margin(
-    margin_account: &MarginAccount,
-    positions: &[Account<'_, Position>],
-    market: &Market,
-) -> Result<u64> {
-    let mut required_margin: u64 = 0;
-    for position_key in &margin_account.positions {
-        if let Some(position) = positions.iter().find(|p| p.key() == *position_key) {
-            let position_value = position.size
-                .checked_mul(position.entry_price)
-                .ok_or(ErrorCode::MathOverflow)?;
-            let position_margin = position_value
-                .checked_mul(market.initial_margin_ratio)
-                .ok_or(ErrorCode::MathOverflow)?
-                .checked_div(10000)
-                .ok_or(ErrorCode::MathOverflow)?
-                .checked_div(position.leverage)
-                .ok_or(ErrorCode::MathOverflow)?;
-            required_margin = required_margin
-                .checked_add(position_margin)
-                .ok_or(ErrorCode::MathOverflow)?;
+/// Create an additional margin account for the same owner and collateral mint,
+/// distinguished by `sub_account_id`
+pub fn create_sub_margin_account(
+    ctx: Context<CreateSubMarginAccount>,
+    _sub_account_id: u16,
+    margin_type: MarginType,
+) -> Result<()> {
+    let margin_account = &mut ctx.accounts.margin_account;
+    margin_account.owner = ctx.accounts.owner.key();
+    margin_account.margin_type = margin_type;
+    margin_account.collateral_mint = ctx.accounts.collateral_mint.key();
+    margin_account.collateral = 0;
+    margin_account.allocated_margin = 0;
+    margin_account.positions = Vec::new();
+    margin_account.bump = ctx.bumps.margin_account;
+    margin_account.collateral_market = Pubkey::default();
+
+    emit!(MarginAccountCreated {
+        owner: ctx.accounts.owner.key(),
+        margin_account: margin_account.key(),
+        margin_type,
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+/// Record the market whose vault holds the collateral of a margin account created before
+/// `collateral_market` was tracked, growing it to `MarginAccount::SPACE` if needed. Only that
+/// market's authority, who can reconcile the vault's deposits, may pin an account to it.
+pub fn migrate_margin_account(ctx: Context<MigrateMarginAccount>) -> Result<()> {
+    let margin_account_info = ctx.accounts.margin_account.to_account_info();
+    let old_space = margin_account_info.data_len();
+    {
+        let data = margin_account_info.try_borrow_data()?;
+        require!(
+            data.len() >= 8 && data[..8] == MarginAccount::DISCRIMINATOR,
+            ErrorCode::InvalidParameter
+        );
+    }
+
+    if old_space < MarginAccount::SPACE {
+        let rent_due = Rent::get()?
+            .minimum_balance(MarginAccount::SPACE)
+            .saturating_sub(margin_account_info.lamports());
+        if rent_due > 0 {
+            system_program::transfer(
+                CpiContext::new(
+                    ctx.accounts.system_program.to_account_info(),
+                    system_program::Transfer {
+                        from: ctx.accounts.authority.to_account_info(),
+                        to: margin_account_info.clone(),
+                    },
+                ),
+                rent_due,
+            )?;
         }
+        margin_account_info.realloc(MarginAccount::SPACE, true)?;
     }
-    Ok(required_margin)
+
+    let mut margin_account =
+        MarginAccount::try_deserialize(&mut &margin_account_info.try_borrow_data()?[..])?;
+    require!(
+        margin_account.collateral_market == Pubkey::default(),
+        ErrorCode::InvalidParameter
+    );
+    require!(
+        margin_account.collateral_mint == ctx.accounts.market_vault.mint,
+        ErrorCode::InvalidCollateralMint
+    );
+
+    margin_account.collateral_market = ctx.accounts.collateral_market.key();
+    margin_account.try_serialize(&mut &mut margin_account_info.try_borrow_mut_data()?[..])?;
+
+    emit!(MarginAccountMigrated {
+        owner: margin_account.owner,
+        margin_account: margin_account_info.key(),
+        collateral_market: margin_account.collateral_market,
+        old_space: old_space as u64,
+        new_space: margin_account_info.data_len() as u64,
+    });
+
+    Ok(())
+}
+
+/// Report the health of a margin account through return data
+pub fn get_account_health<'info>(
+    ctx: Context<'_, '_, 'info, 'info, GetAccountHealth<'info>>,
+) -> Result<AccountHealth> {
+    let margin_account = &ctx.accounts.margin_account;
+    let positions = load_position_health(margin_account, ctx.remaining_accounts)?;
+    account_health(margin_account, positions)
 }
 
 #[derive(Accounts)]
@@ -176,6 +236,8 @@ pub struct DepositCollateral<'info> {
     #[account(
         mut,
         constraint = margin_account.owner == owner.key() @ ErrorCode::Unauthorized,
+        constraint = (margin_account.collateral_market == Pubkey::default() && margin_account.collateral == 0)
+            || margin_account.collateral_market == market.key() @ ErrorCode::InvalidVault,
     )]
     pub margin_account: Account<'info, MarginAccount>,
     #[account(constraint = market.is_active @ ErrorCode::MarketInactive)]
@@ -201,7 +263,8 @@ pub struct WithdrawCollateral<'info> {
     pub owner: Signer<'info>,
     #[account(
         mut,
-        constraint = margin_account.owner == owner.key() @ ErrorCode::Unauthorized, 
+        constraint = margin_account.owner == owner.key() @ ErrorCode::Unauthorized,
+        constraint = margin_account.collateral_market == market.key() @ ErrorCode::InvalidVault,
     )]
     pub margin_account: Account<'info, MarginAccount>,
     #[account(constraint = market.is_active @ ErrorCode::MarketInactive)]
@@ -218,7 +281,30 @@ pub struct WithdrawCollateral<'info> {
     pub market_vault: Account<'info, TokenAccount>,
     pub mint: Account<'info, Mint>,
     pub token_program: Program<'info, Token>,
-    // Positions are passed via remaining_accounts
+    // Positions are passed via remaining_accounts as (position, market, oracle) triples
+}
+
+#[derive(Accounts)]
+pub struct MigrateMarginAccount<'info> {
+    /// CHECK: Margin account in a pre-upgrade layout, which may no longer deserialize as
+    /// `MarginAccount`. Ownership and discriminator are checked in the instruction.
+    #[account(mut, owner = crate::ID)]
+    pub margin_account: UncheckedAccount<'info>,
+    /// Market whose vault holds the account's collateral
+    #[account(has_one = authority)]
+    pub collateral_market: Account<'info, Market>,
+    #[account(constraint = market_vault.key() == collateral_market.vault @ ErrorCode::InvalidVault)]
+    pub market_vault: Account<'info, TokenAccount>,
+    /// Pays the rent for the larger account
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct GetAccountHealth<'info> {
+    pub margin_account: Account<'info, MarginAccount>,
+    // Positions are passed via remaining_accounts as (position, market, oracle) triples
 }
 
 #[derive(Accounts)]
@@ -237,4 +323,22 @@ pub struct CreateMarginAccount<'info> {
     pub collateral_mint: Account<'info, Mint>,
 
     pub system_program: Program<'info, System>,
-}
\ No newline at end of file
+}
+
+#[derive(Accounts)]
+#[instruction(sub_account_id: u16)]
+pub struct CreateSubMarginAccount<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    #[account(
+        init,
+        payer = owner,
+        space = MarginAccount::SPACE,
+        seeds = [b"margin_account", owner.key().as_ref(), collateral_mint.key().as_ref(), &sub_account_id.to_le_bytes()],
+        bump,
+    )]
+    pub margin_account: Account<'info, MarginAccount>,
+    pub collateral_mint: Account<'info, Mint>,
+
+    pub system_program: Program<'info, System>,
+}
@@ -1,5 +1,6 @@
 use anchor_lang::prelude::*;
-use crate::{errors::ErrorCode, events::*, Market};
+use crate::{errors::ErrorCode, events::*, MarginAccount, MarginType, Market, Position, Side};
+use super::{liquidation::record_bad_debt, order::record_trader_pnl};
 
 #[derive(Accounts)]
 pub struct UpdateFundingRate<'info> {
@@ -61,13 +62,13 @@ pub fn update_funding_payments(ctx: Context<UpdateFundingPayments>) -> Result<()
         .checked_add(intervals.checked_mul(market.funding_interval).ok_or(ErrorCode::MathOverflow)?)
         .ok_or(ErrorCode::MathOverflow)?;
 
-    // TODO: In production, iterate over positions to:
-    // 1. Calculate funding payments based on position size and funding_rate
-    // 2. Update position.last_cumulative_funding and realized_pnl
-    // 3. Transfer funds between long/short traders or to/from insurance fund
-    let _funding_increment = market.funding_rate
+    // Positions settle against the cumulative index lazily (see `settle_funding`)
+    let funding_increment = market.funding_rate
         .checked_mul(intervals)
         .ok_or(ErrorCode::MathOverflow)?;
+    market.cumulative_funding_rate = market.cumulative_funding_rate
+        .checked_add(funding_increment)
+        .ok_or(ErrorCode::MathOverflow)?;
 
     emit!(FundingUpdatedEvent {
         market: market.key(),
@@ -77,4 +78,93 @@ pub fn update_funding_payments(ctx: Context<UpdateFundingPayments>) -> Result<()
     });
 
     Ok(())
-}
\ No newline at end of file
+}
+/// Settle the funding a position has accrued since its last update.
+/// Longs pay shorts while the funding rate is positive and the reverse when it is negative.
+/// A payment larger than the collateral backing the position is capped at that collateral and
+/// the shortfall is recorded as bad debt, so underwater positions can still be closed.
+/// The LP pool takes the other side of every payment here, when it is settled; the part of a
+/// payment nobody can collect is marked against the pool through the bad debt.
+/// Returns the amount credited to (positive) or debited from (negative) the position.
+pub fn settle_funding(
+    market: &mut Account<Market>,
+    position: &mut Account<Position>,
+    margin_account: &mut MarginAccount,
+) -> Result<i64> {
+    let funding_delta = market.cumulative_funding_rate
+        .checked_sub(position.last_cumulative_funding)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    let notional = (position.size as i128)
+        .checked_mul(position.entry_price as i128)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let owed = notional
+        .checked_mul(funding_delta as i128)
+        .ok_or(ErrorCode::MathOverflow)?
+        .checked_div(10000)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let mut payment = match position.side {
+        Side::Long => owed.checked_neg(),
+        Side::Short => Some(owed),
+    }
+    .and_then(|p| i64::try_from(p).ok())
+    .ok_or(ErrorCode::MathOverflow)?;
+    payment = record_trader_pnl(market, payment)?;
+
+    if payment > 0 {
+        let amount = payment.unsigned_abs();
+        margin_account.collateral = margin_account.collateral
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        if margin_account.margin_type == MarginType::Isolated {
+            position.collateral = position.collateral
+                .checked_add(amount)
+                .ok_or(ErrorCode::MathOverflow)?;
+            margin_account.allocated_margin = margin_account.allocated_margin
+                .checked_add(amount)
+                .ok_or(ErrorCode::MathOverflow)?;
+        }
+    } else if payment < 0 {
+        // Isolated positions pay funding out of their own collateral
+        let available = match margin_account.margin_type {
+            MarginType::Isolated => position.collateral.min(margin_account.collateral),
+            MarginType::Cross => margin_account.collateral,
+        };
+        let owed = payment.unsigned_abs();
+        let amount = owed.min(available);
+        margin_account.collateral -= amount;
+        if margin_account.margin_type == MarginType::Isolated {
+            position.collateral -= amount;
+            margin_account.allocated_margin = margin_account.allocated_margin
+                .checked_sub(amount)
+                .ok_or(ErrorCode::MathOverflow)?;
+        }
+
+        let shortfall = owed - amount;
+        if shortfall > 0 {
+            // No fill closes anything here, so the last oracle price the market saw is the exit price
+            record_bad_debt(market, position, shortfall, position.size, market.last_oracle_price)?;
+            payment = -(amount as i64);
+        }
+    }
+
+    let current_time = Clock::get()?.unix_timestamp;
+    position.realized_pnl = position.realized_pnl
+        .checked_add(payment)
+        .ok_or(ErrorCode::MathOverflow)?;
+    position.last_cumulative_funding = market.cumulative_funding_rate;
+    position.last_funding_payment_time = current_time;
+
+    if payment != 0 {
+        emit!(FundingSettledEvent {
+            market: position.market,
+            position: position.key(),
+            trader: position.trader,
+            payment,
+            cumulative_funding_rate: market.cumulative_funding_rate,
+            timestamp: current_time,
+        });
+    }
+
+    Ok(payment)
+}
@@ -0,0 +1,1038 @@
+use anchor_lang::prelude::*;
+use std::cmp::Reverse;
+use anchor_spl::token::{self, Token, TokenAccount, Transfer};
+use mock_oracle::Oracle;
+use crate::{
+    errors::ErrorCode,
+    events::*,
+    instructions::{
+        funding::settle_funding,
+        order::{
+            calculate_required_collateral, close_position, decrease_open_interest,
+            increase_open_interest, realize_pnl, record_trader_pnl, release_margin, reserve_margin,
+            validate_leverage, validate_order_size, validate_position_size, validate_remaining_size,
+        },
+        position::{check_initial_margin, reduce_open_position},
+    },
+    risk::{
+        account_health, bankruptcy_price, calculate_pnl, cross_liquidation_equity, div_ceil, liquidation_price, load_oracle,
+        load_position_health, margin_requirement, oracle_price, partial_liquidation_size, position_equity, position_health,
+    },
+    vamm::execute_trade,
+    LiquidationAuction, MarginAccount, MarginType, Market, OrderType, Position, Side,
+};
+
+#[derive(Accounts)]
+pub struct AutoDeleverage<'info> {
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+    /// Permissionless keeper that runs the deleveraging
+    pub keeper: Signer<'info>,
+    /// CHECK: Oracle account - validated in instruction
+    #[account(address = market.oracle @ ErrorCode::InvalidOracleAccount)]
+    pub price_update: UncheckedAccount<'info>,
+    // Profitable positions on the deleveraged side are passed via remaining_accounts as
+    // (position, margin_account, trader) triples, highest ranked first
+}
+
+/// Cover the bad debt left by bankrupt positions by force-reducing profitable positions on the
+/// side that gained from the bankruptcies. Candidates are ranked by PnL percentage times
+/// leverage and must be passed in that order, so large sides can be deleveraged over several
+/// passes. Each candidate is reduced at the bankruptcy price, kept between its entry price and
+/// the oracle price, and the profit it gives up against the oracle price pays off the bad debt.
+pub fn auto_deleverage<'info>(
+    ctx: Context<'_, '_, 'info, 'info, AutoDeleverage<'info>>,
+) -> Result<()> {
+    let market = &mut ctx.accounts.market;
+
+    require!(
+        !ctx.remaining_accounts.is_empty() && ctx.remaining_accounts.len() % 3 == 0,
+        ErrorCode::InvalidPosition
+    );
+
+    // The first candidate decides which side this pass deleverages
+    let adl_side = Account::<Position>::try_from(&ctx.remaining_accounts[0])?.side;
+    require!(adl_debt(market, adl_side).0 > 0, ErrorCode::NoBadDebt);
+
+    // Whatever the insurance fund has accumulated since the bankruptcy is used first
+    let covered = use_insurance_fund(market, adl_debt(market, adl_side).0)?;
+    cover_bad_debt(market, adl_side, covered);
+    let (bad_debt, bankruptcy_price) = adl_debt(market, adl_side);
+    if bad_debt == 0 {
+        return Ok(());
+    }
+
+    // Get current price from oracle
+    let oracle_data = ctx.accounts.price_update.try_borrow_data()?;
+    let oracle = Oracle::try_deserialize(&mut oracle_data.as_ref())?;
+    let current_price = oracle.price;
+
+    // Margin accounts are deserialized once each, so positions in the same account share its state
+    let mut margin_accounts: Vec<Account<'info, MarginAccount>> = Vec::new();
+    let mut candidates = Vec::with_capacity(ctx.remaining_accounts.len() / 3);
+    let mut position_keys = Vec::with_capacity(ctx.remaining_accounts.len() / 3);
+    let mut last_score = u128::MAX;
+    for accounts in ctx.remaining_accounts.chunks(3) {
+        let position = Account::<Position>::try_from(&accounts[0])?;
+
+        require_keys_eq!(position.market, market.key(), ErrorCode::InvalidPosition);
+        require!(position.is_open, ErrorCode::PositionClosed);
+        require!(position.side == adl_side, ErrorCode::AdlCandidateNotEligible);
+        require!(!position_keys.contains(&position.key()), ErrorCode::InvalidPosition);
+        require_keys_eq!(accounts[2].key(), position.trader, ErrorCode::Unauthorized);
+        position_keys.push(position.key());
+
+        let margin_index = match margin_accounts.iter().position(|m| m.key() == accounts[1].key()) {
+            Some(index) => index,
+            None => {
+                margin_accounts.push(Account::<MarginAccount>::try_from(&accounts[1])?);
+                margin_accounts.len() - 1
+            }
+        };
+        let margin_account = &margin_accounts[margin_index];
+        require_keys_eq!(margin_account.owner, position.trader, ErrorCode::Unauthorized);
+        require!(
+            margin_account.positions.contains(&position.key()),
+            ErrorCode::InvalidPosition
+        );
+
+        // Only positions that give up profit by filling at the bankruptcy price can be deleveraged
+        let pnl = calculate_pnl(position.side, position.entry_price, current_price, position.size)?;
+        let fill_price = adl_fill_price(position.side, position.entry_price, current_price, bankruptcy_price);
+        require!(pnl > 0 && fill_price != current_price, ErrorCode::AdlCandidateNotEligible);
+
+        // Rank by PnL as a share of collateral, weighted by leverage
+        let score = (pnl as u128)
+            .checked_mul(10000)
+            .and_then(|value| value.checked_div(position.collateral.max(1) as u128))
+            .and_then(|value| value.checked_mul(position.leverage as u128))
+            .ok_or(ErrorCode::MathOverflow)?;
+        require!(score <= last_score, ErrorCode::AdlCandidatesNotRanked);
+        last_score = score;
+
+        candidates.push((position, margin_index, &accounts[2], fill_price));
+    }
+
+    for (mut position, margin_index, trader, fill_price) in candidates {
+        let bad_debt = adl_debt(market, adl_side).0;
+        if bad_debt == 0 {
+            break;
+        }
+        let margin_account = &mut margin_accounts[margin_index];
+
+        // Smallest whole number of lots whose given up profit covers the remaining bad debt,
+        // or the whole position if what would be left is dust
+        let given_up_per_unit = current_price.abs_diff(fill_price);
+        let step = market.order_step_size.max(1) as u128;
+        let lots = div_ceil(div_ceil(bad_debt as u128, given_up_per_unit as u128)?, step)?;
+        let mut size = lots
+            .checked_mul(step)
+            .ok_or(ErrorCode::MathOverflow)?
+            .min(position.size as u128) as u64;
+        if position.size - size < market.min_order_size {
+            size = position.size;
+        }
+        let given_up = size
+            .checked_mul(given_up_per_unit)
+            .ok_or(ErrorCode::MathOverflow)?;
+        let covered = given_up.min(bad_debt);
+
+        let position_key = position.key();
+        let trader_key = position.trader;
+        let remaining_size = position.size - size;
+
+        if size == position.size {
+            close_position(market, &mut position, margin_account, fill_price)?;
+        } else {
+            reduce_open_position(market, &mut position, margin_account, size, fill_price)?;
+        }
+
+        // Rounding up to whole lots gives up more than the bad debt left; the trader keeps the excess
+        let excess = record_trader_pnl(market, (given_up - covered) as i64)?;
+        if excess > 0 {
+            realize_pnl(margin_account, excess)?;
+        }
+
+        if size == position.size {
+            position.close(trader.clone())?;
+        } else {
+            position.realized_pnl = position.realized_pnl
+                .checked_add(excess)
+                .ok_or(ErrorCode::MathOverflow)?;
+            position.exit(ctx.program_id)?;
+        }
+
+        cover_bad_debt(market, adl_side, covered);
+
+        emit!(PositionDeleveragedEvent {
+            market: market.key(),
+            position: position_key,
+            trader: trader_key,
+            side: adl_side,
+            size_reduced: size,
+            remaining_size,
+            mark_price: current_price,
+            bad_debt_covered: covered,
+            remaining_bad_debt: adl_debt(market, adl_side).0,
+            fill_price,
+        });
+    }
+
+    for margin_account in &margin_accounts {
+        margin_account.exit(ctx.program_id)?;
+    }
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct LiquidateAccount<'info> {
+    #[account(
+        mut,
+        constraint = margin_account.margin_type == MarginType::Cross @ ErrorCode::InvalidParameter,
+    )]
+    pub margin_account: Account<'info, MarginAccount>,
+    /// Receives the liquidator fee and the rent of closed positions
+    #[account(mut)]
+    pub liquidator: Signer<'info>,
+    #[account(
+        mut,
+        constraint = liquidator_token_account.owner == liquidator.key() @ ErrorCode::Unauthorized,
+        constraint = liquidator_token_account.mint == margin_account.collateral_mint @ ErrorCode::InvalidCollateralMint,
+    )]
+    pub liquidator_token_account: Account<'info, TokenAccount>,
+    /// Market whose vault holds the account's collateral and pays the liquidator fee
+    #[account(address = margin_account.collateral_market @ ErrorCode::InvalidVault)]
+    pub collateral_market: Account<'info, Market>,
+    #[account(
+        mut,
+        constraint = market_vault.key() == collateral_market.vault @ ErrorCode::InvalidVault,
+        constraint = market_vault.mint == margin_account.collateral_mint @ ErrorCode::InvalidCollateralMint,
+    )]
+    pub market_vault: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+    // Every position of the account is passed via remaining_accounts as (position, market, oracle) triples
+}
+
+/// Liquidate a cross margin account across all of its markets. Positions are closed against
+/// the vAMM, riskiest (lowest margin ratio at the oracle price) first, until total equity
+/// covers the total maintenance margin of the positions that are left.
+pub fn liquidate_account<'info>(
+    ctx: Context<'_, '_, 'info, 'info, LiquidateAccount<'info>>,
+) -> Result<()> {
+    let margin_account = &mut ctx.accounts.margin_account;
+    let liquidator = &ctx.accounts.liquidator;
+
+    require!(ctx.remaining_accounts.len() % 3 == 0, ErrorCode::InvalidPosition);
+    require!(
+        ctx.remaining_accounts.len() / 3 == margin_account.positions.len(),
+        ErrorCode::InvalidPosition
+    );
+
+    // Markets are deserialized once each, so positions in the same market share its state
+    let mut markets: Vec<Account<'info, Market>> = Vec::new();
+    let mut positions: Vec<(Account<'info, Position>, usize, u64)> =
+        Vec::with_capacity(margin_account.positions.len());
+    for accounts in ctx.remaining_accounts.chunks(3) {
+        let mut position = Account::<Position>::try_from(&accounts[0])?;
+        require!(
+            margin_account.positions.contains(&position.key()),
+            ErrorCode::InvalidPosition
+        );
+        require!(
+            !positions.iter().any(|(p, _, _)| p.key() == position.key()),
+            ErrorCode::InvalidPosition
+        );
+        require_keys_eq!(position.market, accounts[1].key(), ErrorCode::InvalidPosition);
+
+        let market_index = match markets.iter().position(|m| m.key() == accounts[1].key()) {
+            Some(index) => index,
+            None => {
+                markets.push(Account::<Market>::try_from(&accounts[1])?);
+                markets.len() - 1
+            }
+        };
+        let market = &mut markets[market_index];
+        require_keys_eq!(accounts[2].key(), market.oracle, ErrorCode::InvalidOracleAccount);
+        let price = oracle_price(&accounts[2])?;
+
+        settle_funding(market, &mut position, margin_account)?;
+        positions.push((position, market_index, price));
+    }
+
+    let mut healths = Vec::with_capacity(positions.len());
+    for (position, market_index, price) in &positions {
+        healths.push(position_health(&markets[*market_index], position, *price)?);
+    }
+    let health = account_health(margin_account, healths)?;
+    require!(health.is_liquidatable, ErrorCode::PositionNotLiquidatable);
+
+    // Riskiest first: lowest margin ratio, then largest maintenance margin
+    let mut order: Vec<usize> = (0..positions.len()).collect();
+    order.sort_by_key(|&i| (health.positions[i].margin_ratio, Reverse(health.positions[i].maintenance_margin)));
+
+    let mut equity = health.equity;
+    let mut maintenance_margin = health.maintenance_margin;
+    let mut total_liquidator_fee: u64 = 0;
+
+    for i in order {
+        if equity >= maintenance_margin as i64 {
+            break;
+        }
+        let position_risk = health.positions[i];
+        let (position, market_index, price) = &mut positions[i];
+        let market = &mut markets[*market_index];
+
+        // Large positions are liquidated through auctions
+        require!(
+            market.auction_size_threshold == 0 || position.size < market.auction_size_threshold,
+            ErrorCode::LiquidationAuctionRequired
+        );
+
+        let liquidation_fee = margin_requirement(position.size, *price, market.liquidation_fee_ratio_for(position.size))?
+            .min(equity.max(0) as u64);
+        let liquidator_fee = liquidation_fee / 2;
+        let insurance_fund_fee = liquidation_fee - liquidator_fee;
+        market.insurance_fund = market
+            .insurance_fund
+            .checked_add(insurance_fund_fee)
+            .ok_or(ErrorCode::MathOverflow)?;
+        total_liquidator_fee = total_liquidator_fee
+            .checked_add(liquidator_fee)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let oracle = load_oracle(&ctx.remaining_accounts[i * 3 + 2])?;
+        let exit_price = execute_trade(market, position.side.opposite(), position.size, &oracle, true)?;
+        let pnl = calculate_pnl(position.side, position.entry_price, exit_price, position.size)?;
+        settle_liquidation_loss(market, margin_account, position, pnl, exit_price, liquidation_fee)?;
+        decrease_open_interest(market, position.side, position.size, position.entry_price)?;
+        margin_account.positions.retain(|&key| key != position.key());
+        position.is_open = false;
+
+        emit!(PositionLiquidatedEvent {
+            market: market.key(),
+            position: position.key(),
+            trader: position.trader,
+            side: position.side,
+            size: position.size,
+            remaining_size: 0,
+            collateral: position.collateral,
+            entry_price: position.entry_price,
+            exit_price,
+            liquidator: liquidator.key(),
+            liquidation_fee,
+            liquidator_fee,
+            insurance_fund_fee,
+        });
+
+        // Closing realizes the position's PnL, so only the fee and the vAMM's price impact
+        // change account equity
+        equity = equity
+            .checked_sub(liquidation_fee as i64)
+            .and_then(|equity| equity.checked_add(pnl.checked_sub(position_risk.unrealized_pnl)?))
+            .ok_or(ErrorCode::MathOverflow)?;
+        maintenance_margin = maintenance_margin
+            .checked_sub(position_risk.maintenance_margin)
+            .ok_or(ErrorCode::MathOverflow)?;
+    }
+
+    // Persist markets and positions; liquidated positions are closed to the liquidator
+    for market in &markets {
+        market.exit(ctx.program_id)?;
+    }
+    for (position, _, _) in &positions {
+        if position.is_open {
+            position.exit(ctx.program_id)?;
+        } else {
+            position.close(liquidator.to_account_info())?;
+        }
+    }
+
+    // Transfer liquidator fee from the collateral market's vault to liquidator
+    if total_liquidator_fee > 0 {
+        let collateral_market = &ctx.accounts.collateral_market;
+        let seeds = &[
+            b"market".as_ref(),
+            collateral_market.market_symbol.as_bytes(),
+            &[collateral_market.bump],
+        ];
+        let signer = &[&seeds[..]];
+        let transfer_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.market_vault.to_account_info(),
+                to: ctx.accounts.liquidator_token_account.to_account_info(),
+                authority: collateral_market.to_account_info(),
+            },
+            signer,
+        );
+        token::transfer(transfer_ctx, total_liquidator_fee)?;
+    }
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct StartLiquidationAuction<'info> {
+    pub market: Account<'info, Market>,
+    #[account(
+        has_one = market,
+        constraint = position.is_open @ ErrorCode::PositionClosed,
+    )]
+    pub position: Account<'info, Position>,
+    #[account(
+        constraint = margin_account.owner == position.trader @ ErrorCode::Unauthorized,
+        constraint = margin_account.positions.contains(&position.key()) @ ErrorCode::InvalidPosition,
+    )]
+    pub margin_account: Account<'info, MarginAccount>,
+    #[account(
+        init,
+        payer = keeper,
+        space = LiquidationAuction::SPACE,
+        seeds = [b"liquidation_auction", position.key().as_ref()],
+        bump
+    )]
+    pub auction: Account<'info, LiquidationAuction>,
+    /// Permissionless keeper that starts the auction
+    #[account(mut)]
+    pub keeper: Signer<'info>,
+    /// CHECK: Oracle account - validated in instruction
+    #[account(address = market.oracle @ ErrorCode::InvalidOracleAccount)]
+    pub price_update: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+    // Cross accounts pass every position via remaining_accounts as (position, market, oracle) triples
+}
+
+/// Start a Dutch auction for a liquidatable position at or above the market's auction size threshold
+pub fn start_liquidation_auction<'info>(
+    ctx: Context<'_, '_, 'info, 'info, StartLiquidationAuction<'info>>,
+) -> Result<()> {
+    let market = &ctx.accounts.market;
+    let position = &ctx.accounts.position;
+    let auction = &mut ctx.accounts.auction;
+
+    require!(
+        market.auction_size_threshold > 0 && position.size >= market.auction_size_threshold,
+        ErrorCode::LiquidationAuctionNotRequired
+    );
+
+    let current_price = oracle_price(&ctx.accounts.price_update)?;
+    require!(
+        is_liquidatable(market, position, &ctx.accounts.margin_account, current_price, ctx.remaining_accounts)?,
+        ErrorCode::PositionNotLiquidatable
+    );
+
+    let start_slot = Clock::get()?.slot;
+    auction.market = market.key();
+    auction.position = position.key();
+    auction.margin_account = ctx.accounts.margin_account.key();
+    auction.keeper = ctx.accounts.keeper.key();
+    auction.size = position.size;
+    auction.start_slot = start_slot;
+    auction.bump = ctx.bumps.auction;
+
+    emit!(LiquidationAuctionStartedEvent {
+        market: market.key(),
+        position: position.key(),
+        trader: position.trader,
+        auction: auction.key(),
+        size: position.size,
+        start_slot,
+        end_slot: start_slot.saturating_add(market.auction_duration_slots),
+    });
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+#[instruction(leverage: u64, client_order_id: u64)]
+pub struct TakeLiquidationAuction<'info> {
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+    #[account(
+        mut,
+        has_one = market,
+        constraint = position.is_open @ ErrorCode::PositionClosed,
+    )]
+    pub position: Account<'info, Position>,
+    #[account(
+        mut,
+        constraint = margin_account.owner == position.trader @ ErrorCode::Unauthorized,
+        constraint = margin_account.positions.contains(&position.key()) @ ErrorCode::InvalidPosition,
+    )]
+    pub margin_account: Account<'info, MarginAccount>,
+    #[account(
+        mut,
+        seeds = [b"liquidation_auction", position.key().as_ref()],
+        bump = auction.bump,
+        close = keeper  // The auction's rent goes back to the keeper that started it
+    )]
+    pub auction: Account<'info, LiquidationAuction>,
+    /// CHECK: Receives the auction account's rent
+    #[account(mut, address = auction.keeper)]
+    pub keeper: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub liquidator: Signer<'info>,
+    #[account(
+        mut,
+        constraint = liquidator_margin_account.owner == liquidator.key() @ ErrorCode::Unauthorized,
+        constraint = liquidator_margin_account.key() != margin_account.key() @ ErrorCode::InvalidParameter,
+        constraint = liquidator_margin_account.collateral_mint == margin_account.collateral_mint @ ErrorCode::InvalidCollateralMint,
+    )]
+    pub liquidator_margin_account: Account<'info, MarginAccount>,
+    #[account(
+        init,
+        payer = liquidator,
+        space = Position::SPACE,
+        seeds = [b"liquidation_position", market.key().as_ref(), liquidator.key().as_ref(), &client_order_id.to_le_bytes()],
+        bump
+    )]
+    pub liquidator_position: Account<'info, Position>,
+    /// CHECK: Oracle account - validated in instruction
+    #[account(address = market.oracle @ ErrorCode::InvalidOracleAccount)]
+    pub price_update: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+    // Remaining accounts are (position, market, oracle) triples for every position of a cross
+    // liquidated account, followed by every position of a cross liquidator account
+}
+
+/// Take an auctioned position onto the liquidator's own margin account at the oracle price
+/// minus the current auction discount, which grows linearly from the start to the maximum
+/// discount over the auction's duration
+pub fn take_liquidation_auction<'info>(
+    ctx: Context<'_, '_, 'info, 'info, TakeLiquidationAuction<'info>>,
+    leverage: u64,
+    client_order_id: u64,
+) -> Result<()> {
+    let market = &mut ctx.accounts.market;
+    let position = &mut ctx.accounts.position;
+    let margin_account = &mut ctx.accounts.margin_account;
+    let auction = &ctx.accounts.auction;
+
+    let elapsed = Clock::get()?.slot.saturating_sub(auction.start_slot);
+    require!(elapsed <= market.auction_duration_slots, ErrorCode::LiquidationAuctionEnded);
+
+    let current_price = oracle_price(&ctx.accounts.price_update)?;
+    settle_funding(market, position, margin_account)?;
+    let (victim_accounts, liquidator_accounts) =
+        split_health_accounts(margin_account, ctx.remaining_accounts)?;
+    require!(
+        is_liquidatable(market, position, margin_account, current_price, victim_accounts)?,
+        ErrorCode::PositionNotLiquidatable
+    );
+
+    let discount = market.auction_start_discount
+        .checked_add(
+            (market.auction_max_discount - market.auction_start_discount)
+                .checked_mul(elapsed)
+                .ok_or(ErrorCode::MathOverflow)?
+                .checked_div(market.auction_duration_slots)
+                .ok_or(ErrorCode::MathOverflow)?,
+        )
+        .ok_or(ErrorCode::MathOverflow)?;
+    let execution_price = discounted_price(position.side, current_price, discount)?;
+
+    let size = position.size;
+    take_over_position(
+        market,
+        position,
+        margin_account,
+        &mut ctx.accounts.liquidator_position,
+        &mut ctx.accounts.liquidator_margin_account,
+        liquidator_accounts,
+        TakeOver {
+            size,
+            oracle_price: current_price,
+            execution_price,
+            leverage,
+            client_order_id,
+            bump: ctx.bumps.liquidator_position,
+        },
+    )?;
+
+    emit!(LiquidationAuctionTakenEvent {
+        market: market.key(),
+        position: position.key(),
+        trader: position.trader,
+        liquidator: ctx.accounts.liquidator.key(),
+        liquidator_position: ctx.accounts.liquidator_position.key(),
+        size,
+        oracle_price: current_price,
+        execution_price,
+        discount,
+    });
+
+    position.close(ctx.accounts.liquidator.to_account_info())?;
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+#[instruction(size: u64, leverage: u64, client_order_id: u64)]
+pub struct LiquidateWithTakeover<'info> {
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+    #[account(
+        mut,
+        has_one = market,
+        constraint = position.is_open @ ErrorCode::PositionClosed,
+    )]
+    pub position: Account<'info, Position>,
+    #[account(
+        mut,
+        constraint = margin_account.owner == position.trader @ ErrorCode::Unauthorized,
+        constraint = margin_account.positions.contains(&position.key()) @ ErrorCode::InvalidPosition,
+    )]
+    pub margin_account: Account<'info, MarginAccount>,
+    #[account(mut)]
+    pub liquidator: Signer<'info>,
+    #[account(
+        mut,
+        constraint = liquidator_margin_account.owner == liquidator.key() @ ErrorCode::Unauthorized,
+        constraint = liquidator_margin_account.key() != margin_account.key() @ ErrorCode::InvalidParameter,
+        constraint = liquidator_margin_account.collateral_mint == margin_account.collateral_mint @ ErrorCode::InvalidCollateralMint,
+    )]
+    pub liquidator_margin_account: Account<'info, MarginAccount>,
+    #[account(
+        init,
+        payer = liquidator,
+        space = Position::SPACE,
+        seeds = [b"liquidation_position", market.key().as_ref(), liquidator.key().as_ref(), &client_order_id.to_le_bytes()],
+        bump
+    )]
+    pub liquidator_position: Account<'info, Position>,
+    /// CHECK: Oracle account - validated in instruction
+    #[account(address = market.oracle @ ErrorCode::InvalidOracleAccount)]
+    pub price_update: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+    // Remaining accounts are (position, market, oracle) triples for every position of a cross
+    // liquidated account, followed by every position of a cross liquidator account
+}
+
+/// Liquidate a position by taking over `size` of it onto the liquidator's margin account at
+/// the oracle price less the liquidation fee ratio, instead of closing it against the market.
+/// The liquidator may take at most the size a partial liquidation would close.
+pub fn liquidate_with_takeover<'info>(
+    ctx: Context<'_, '_, 'info, 'info, LiquidateWithTakeover<'info>>,
+    size: u64,
+    leverage: u64,
+    client_order_id: u64,
+) -> Result<()> {
+    let market = &mut ctx.accounts.market;
+    let position = &mut ctx.accounts.position;
+    let margin_account = &mut ctx.accounts.margin_account;
+
+    // Large positions are taken over through liquidation auctions
+    require!(
+        market.auction_size_threshold == 0 || position.size < market.auction_size_threshold,
+        ErrorCode::LiquidationAuctionRequired
+    );
+
+    let current_price = oracle_price(&ctx.accounts.price_update)?;
+    settle_funding(market, position, margin_account)?;
+    let (victim_accounts, liquidator_accounts) =
+        split_health_accounts(margin_account, ctx.remaining_accounts)?;
+
+    // Equity available to the position: its own for isolated accounts, and for cross accounts
+    // what remains after the maintenance margin and buffer of every other position
+    let equity = match margin_account.margin_type {
+        MarginType::Isolated => {
+            let maintenance_margin =
+                margin_requirement(position.size, current_price, market.maintenance_margin_ratio_for(position.size))?;
+            let equity = position_equity(position, current_price)?;
+            require!(equity < maintenance_margin as i64, ErrorCode::PositionNotLiquidatable);
+            equity
+        }
+        MarginType::Cross => {
+            let positions = load_position_health(margin_account, victim_accounts)?;
+            let health = account_health(margin_account, positions)?;
+            require!(health.is_liquidatable, ErrorCode::PositionNotLiquidatable);
+            cross_liquidation_equity(&health, position.key())?
+        }
+    };
+
+    let max_size = partial_liquidation_size(market, position, equity, current_price)?;
+    require!(size > 0 && size <= max_size, ErrorCode::LiquidationSizeExceeded);
+
+    let execution_price = discounted_price(position.side, current_price, market.liquidation_fee_ratio_for(position.size))?;
+
+    take_over_position(
+        market,
+        position,
+        margin_account,
+        &mut ctx.accounts.liquidator_position,
+        &mut ctx.accounts.liquidator_margin_account,
+        liquidator_accounts,
+        TakeOver {
+            size,
+            oracle_price: current_price,
+            execution_price,
+            leverage,
+            client_order_id,
+            bump: ctx.bumps.liquidator_position,
+        },
+    )?;
+
+    let remaining_size = if position.is_open { position.size } else { 0 };
+    emit!(PositionTakenOverEvent {
+        market: market.key(),
+        position: position.key(),
+        trader: position.trader,
+        liquidator: ctx.accounts.liquidator.key(),
+        liquidator_position: ctx.accounts.liquidator_position.key(),
+        size,
+        remaining_size,
+        oracle_price: current_price,
+        execution_price,
+    });
+
+    if remaining_size == 0 {
+        position.close(ctx.accounts.liquidator.to_account_info())?;
+    }
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct CancelLiquidationAuction<'info> {
+    pub market: Account<'info, Market>,
+    /// CHECK: Auctioned position. It may already have been closed by another liquidation
+    #[account(address = auction.position)]
+    pub position: UncheckedAccount<'info>,
+    /// CHECK: Margin account of the auctioned position
+    #[account(address = auction.margin_account)]
+    pub margin_account: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        has_one = market,
+        close = keeper  // The auction's rent goes back to the keeper that started it
+    )]
+    pub auction: Account<'info, LiquidationAuction>,
+    /// CHECK: Receives the auction account's rent
+    #[account(mut, address = auction.keeper)]
+    pub keeper: UncheckedAccount<'info>,
+    /// Permissionless caller
+    pub signer: Signer<'info>,
+    /// CHECK: Oracle account - validated in instruction
+    #[account(address = market.oracle @ ErrorCode::InvalidOracleAccount)]
+    pub price_update: UncheckedAccount<'info>,
+    // Cross accounts pass every position via remaining_accounts as (position, market, oracle) triples
+}
+
+/// Close an auction that has ended, whose position is gone, or whose position is healthy again
+pub fn cancel_liquidation_auction<'info>(
+    ctx: Context<'_, '_, 'info, 'info, CancelLiquidationAuction<'info>>,
+) -> Result<()> {
+    let market = &ctx.accounts.market;
+    let auction = &ctx.accounts.auction;
+
+    let ended = Clock::get()?.slot.saturating_sub(auction.start_slot) > market.auction_duration_slots;
+    if !ended && !ctx.accounts.position.data_is_empty() {
+        require_keys_eq!(*ctx.accounts.position.owner, crate::ID, ErrorCode::InvalidPosition);
+        let position = Position::try_deserialize(&mut &ctx.accounts.position.try_borrow_data()?[..])?;
+        let margin_account =
+            MarginAccount::try_deserialize(&mut &ctx.accounts.margin_account.try_borrow_data()?[..])?;
+        let current_price = oracle_price(&ctx.accounts.price_update)?;
+        require!(
+            !position.is_open
+                || !is_liquidatable(market, &position, &margin_account, current_price, ctx.remaining_accounts)?,
+            ErrorCode::InvalidParameter
+        );
+    }
+
+    emit!(LiquidationAuctionCancelledEvent {
+        market: market.key(),
+        position: auction.position,
+        auction: auction.key(),
+    });
+
+    Ok(())
+}
+
+/// Terms on which a liquidator takes over (part of) a position
+pub(crate) struct TakeOver {
+    pub size: u64,
+    pub oracle_price: u64,
+    pub execution_price: u64,
+    pub leverage: u64,
+    pub client_order_id: u64,
+    pub bump: u8,
+}
+
+/// Move `size` of a position onto a new position of the liquidator. The liquidated side closes
+/// at the execution price and the liquidator opens at it, margined against their own account
+/// at the oracle price. Open interest is unchanged overall.
+///
+/// A cross liquidator passes the (position, market, oracle) triples of its other positions
+/// in `liquidator_accounts` and must meet its initial margin across all of them.
+pub(crate) fn take_over_position<'info>(
+    market: &mut Account<Market>,
+    position: &mut Account<Position>,
+    margin_account: &mut Account<MarginAccount>,
+    liquidator_position: &mut Account<Position>,
+    liquidator_margin_account: &mut Account<MarginAccount>,
+    liquidator_accounts: &'info [AccountInfo<'info>],
+    terms: TakeOver,
+) -> Result<()> {
+    let side = position.side;
+
+    // A partial takeover must be a valid order size and leave a valid position behind.
+    // Taking over the whole position is always allowed so that it can be cleared.
+    if terms.size < position.size {
+        validate_order_size(market, terms.size)?;
+        validate_remaining_size(market, position.size - terms.size)?;
+    }
+
+    // The liquidated side realizes PnL at the execution price
+    if terms.size == position.size {
+        close_position(market, position, margin_account, terms.execution_price)?;
+        position.is_open = false;
+    } else {
+        reduce_open_position(market, position, margin_account, terms.size, terms.execution_price)?;
+    }
+
+    // The liquidator opens the same exposure with their own collateral
+    validate_leverage(market, terms.size, terms.leverage)?;
+    validate_position_size(market, 0, terms.size)?;
+    let required_collateral =
+        calculate_required_collateral(market, terms.size, terms.oracle_price, terms.leverage)?;
+    reserve_margin(liquidator_margin_account, required_collateral)?;
+
+    let current_timestamp = Clock::get()?.unix_timestamp;
+    liquidator_position.trader = liquidator_margin_account.owner;
+    liquidator_position.market = market.key();
+    liquidator_position.order_type = OrderType::Market;
+    liquidator_position.side = side;
+    liquidator_position.size = terms.size;
+    liquidator_position.filled_size = terms.size;
+    liquidator_position.price = terms.execution_price;
+    liquidator_position.collateral = required_collateral;
+    liquidator_position.entry_price = terms.execution_price;
+    liquidator_position.entry_funding_rate = market.funding_rate;
+    liquidator_position.leverage = terms.leverage;
+    liquidator_position.realized_pnl = 0;
+    liquidator_position.last_funding_payment_time = current_timestamp;
+    liquidator_position.last_cumulative_funding = market.cumulative_funding_rate;
+    liquidator_position.is_open = true;
+    liquidator_position.created_at = current_timestamp;
+    liquidator_position.client_order_id = terms.client_order_id;
+    liquidator_position.bump = terms.bump;
+
+    increase_open_interest(market, side, terms.size, terms.execution_price)?;
+
+    // The liquidator must be able to carry the position it takes on
+    match liquidator_margin_account.margin_type {
+        MarginType::Isolated => {
+            check_initial_margin(market, liquidator_position, required_collateral, terms.oracle_price)?;
+        }
+        MarginType::Cross => {
+            let mut positions = load_position_health(liquidator_margin_account, liquidator_accounts)?;
+            positions.push(position_health(market, liquidator_position, terms.oracle_price)?);
+            let health = account_health(liquidator_margin_account, positions)?;
+            require!(health.equity >= health.initial_margin as i64, ErrorCode::InsufficientMargin);
+        }
+    }
+    liquidator_margin_account.positions.push(liquidator_position.key());
+
+    emit!(PositionOpenedEvent {
+        market: market.key(),
+        position: liquidator_position.key(),
+        trader: liquidator_position.trader,
+        side,
+        size: terms.size,
+        collateral: required_collateral,
+        entry_price: terms.execution_price,
+        leverage: terms.leverage,
+        liquidation_price: liquidation_price(liquidator_position, market.maintenance_margin_ratio_for(liquidator_position.size))?,
+        margin_type: liquidator_margin_account.margin_type,
+    });
+
+    Ok(())
+}
+
+/// Price at which a liquidator takes over a position: below the oracle price for longs,
+/// above it for shorts
+pub(crate) fn discounted_price(side: Side, price: u64, discount: u64) -> Result<u64> {
+    let ratio = match side {
+        Side::Long => 10000u64.checked_sub(discount),
+        Side::Short => 10000u64.checked_add(discount),
+    }
+    .ok_or(ErrorCode::MathOverflow)?;
+
+    (price as u128)
+        .checked_mul(ratio as u128)
+        .and_then(|value| value.checked_div(10000))
+        .and_then(|value| u64::try_from(value).ok())
+        .ok_or(ErrorCode::MathOverflow.into())
+}
+
+/// Split remaining accounts into the health triples of a cross liquidated account, which come
+/// first, and those of the liquidator's account
+fn split_health_accounts<'info>(
+    margin_account: &MarginAccount,
+    remaining_accounts: &'info [AccountInfo<'info>],
+) -> Result<(&'info [AccountInfo<'info>], &'info [AccountInfo<'info>])> {
+    let split = match margin_account.margin_type {
+        MarginType::Isolated => 0,
+        MarginType::Cross => margin_account.positions.len()
+            .checked_mul(3)
+            .ok_or(ErrorCode::MathOverflow)?,
+    };
+    require!(remaining_accounts.len() >= split, ErrorCode::InvalidPosition);
+    Ok(remaining_accounts.split_at(split))
+}
+
+/// Whether a position can be liquidated: on its own for isolated accounts, at the account
+/// level for cross accounts, whose positions are passed as (position, market, oracle) triples
+pub(crate) fn is_liquidatable<'info>(
+    market: &Market,
+    position: &Position,
+    margin_account: &MarginAccount,
+    price: u64,
+    remaining_accounts: &'info [AccountInfo<'info>],
+) -> Result<bool> {
+    match margin_account.margin_type {
+        MarginType::Isolated => {
+            let maintenance_margin =
+                margin_requirement(position.size, price, market.maintenance_margin_ratio_for(position.size))?;
+            Ok(position_equity(position, price)? < maintenance_margin as i64)
+        }
+        MarginType::Cross => {
+            let positions = load_position_health(margin_account, remaining_accounts)?;
+            Ok(account_health(margin_account, positions)?.is_liquidatable)
+        }
+    }
+}
+
+/// Release a fully liquidated position's collateral and charge its realized loss and the
+/// liquidation fee to the collateral backing it: the position's own collateral in isolated
+/// mode, the whole account balance in cross mode. Whatever that cannot cover is bad debt.
+pub(crate) fn settle_liquidation_loss(
+    market: &mut Account<Market>,
+    margin_account: &mut MarginAccount,
+    position: &Account<Position>,
+    pnl: i64,
+    exit_price: u64,
+    liquidation_fee: u64,
+) -> Result<()> {
+    release_margin(margin_account, position.collateral)?;
+    let pnl = record_trader_pnl(market, pnl)?;
+
+    let charge = (liquidation_fee as i64)
+        .checked_sub(pnl)
+        .ok_or(ErrorCode::MathOverflow)?;
+    if charge <= 0 {
+        realize_pnl(margin_account, -charge)?;
+        return Ok(());
+    }
+
+    let available = match margin_account.margin_type {
+        MarginType::Isolated => position.collateral.min(margin_account.collateral),
+        MarginType::Cross => margin_account.collateral,
+    };
+    let paid = charge.unsigned_abs().min(available);
+    margin_account.collateral -= paid;
+
+    let deficit = charge.unsigned_abs() - paid;
+    if deficit > 0 {
+        record_bad_debt(market, position, deficit, position.size, exit_price)?;
+    }
+
+    Ok(())
+}
+
+/// Cover a bankrupt position's deficit from the insurance fund and leave the rest as bad debt
+/// for auto-deleveraging the opposite side. `size` was closed at `exit_price` when the deficit
+/// arose. Deficits from several bankruptcies add up, and the side's bankruptcy price becomes
+/// their average weighted by bad debt.
+pub(crate) fn record_bad_debt(
+    market: &mut Account<Market>,
+    position: &Account<Position>,
+    deficit: u64,
+    size: u64,
+    exit_price: u64,
+) -> Result<()> {
+    let adl_side = position.side.opposite();
+    let covered = use_insurance_fund(market, deficit)?;
+    let uncovered = deficit - covered;
+    let bankruptcy_price = bankruptcy_price(position.side, exit_price, size, deficit)?;
+
+    if uncovered > 0 {
+        // The LP pool never collects the part of the loss nobody pays
+        market.counterparty_pnl = market.counterparty_pnl
+            .checked_sub(uncovered as i64)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let state: &mut Market = market;
+        let (bad_debt, adl_price) = match adl_side {
+            Side::Long => (&mut state.long_bad_debt, &mut state.long_bankruptcy_price),
+            Side::Short => (&mut state.short_bad_debt, &mut state.short_bankruptcy_price),
+        };
+        let total = bad_debt
+            .checked_add(uncovered)
+            .ok_or(ErrorCode::MathOverflow)?;
+        *adl_price = (*adl_price as u128)
+            .checked_mul(*bad_debt as u128)
+            .and_then(|value| value.checked_add(bankruptcy_price as u128 * uncovered as u128))
+            .and_then(|value| value.checked_div(total as u128))
+            .ok_or(ErrorCode::MathOverflow)? as u64;
+        *bad_debt = total;
+    }
+
+    emit!(BadDebtRecordedEvent {
+        market: market.key(),
+        position: position.key(),
+        deficit,
+        insurance_fund_covered: covered,
+        uncovered,
+        bad_debt: adl_debt(market, adl_side).0,
+        adl_side,
+        bankruptcy_price,
+    });
+
+    Ok(())
+}
+
+/// Bad debt recovered by deleveraging `side`, and the bankruptcy price it is deleveraged at
+fn adl_debt(market: &Market, side: Side) -> (u64, u64) {
+    match side {
+        Side::Long => (market.long_bad_debt, market.long_bankruptcy_price),
+        Side::Short => (market.short_bad_debt, market.short_bankruptcy_price),
+    }
+}
+
+/// Pay off `amount` of the bad debt recovered from `side`
+fn cover_bad_debt(market: &mut Market, side: Side, amount: u64) {
+    let (bad_debt, adl_price) = match side {
+        Side::Long => (&mut market.long_bad_debt, &mut market.long_bankruptcy_price),
+        Side::Short => (&mut market.short_bad_debt, &mut market.short_bankruptcy_price),
+    };
+    *bad_debt = bad_debt.saturating_sub(amount);
+    if *bad_debt == 0 {
+        *adl_price = 0;
+    }
+}
+
+/// Price a deleveraged position on `side` is reduced at: the bankruptcy price, kept between the
+/// position's entry price and the oracle price so that it gives up profit but never takes a loss
+fn adl_fill_price(side: Side, entry_price: u64, oracle_price: u64, bankruptcy_price: u64) -> u64 {
+    match side {
+        Side::Long => bankruptcy_price.max(entry_price).min(oracle_price),
+        Side::Short => bankruptcy_price.min(entry_price).max(oracle_price),
+    }
+}
+
+/// Pay up to `amount` of losses out of the insurance fund, returning what it covered
+fn use_insurance_fund(market: &mut Account<Market>, amount: u64) -> Result<u64> {
+    let covered = market.insurance_fund.min(amount);
+    if covered > 0 {
+        market.insurance_fund -= covered;
+        emit!(InsuranceFundUsedEvent {
+            market: market.key(),
+            amount: covered,
+            insurance_fund: market.insurance_fund,
+        });
+    }
+
+    Ok(covered)
+}
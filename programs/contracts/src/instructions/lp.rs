@@ -0,0 +1,728 @@
+// Liquidity provider pool. The pool is the counterparty to the market's trader PnL: it takes
+// realized trader losses and funding as positions settle them and pays realized profits. It also
+// earns what the market's fee pool accrues, which is the only ledger for spread revenue. The
+// pool's collateral sits in its own vault, apart from trader collateral; realized PnL and fees are
+// moved between the market vault and the pool vault whenever the pool is settled. Depositors hold
+// share tokens redeemable for their part of the pool's realized value.
+use anchor_lang::prelude::*;
+use anchor_spl::token::{self, Burn, Mint, MintTo, Token, TokenAccount, Transfer};
+use crate::{errors::ErrorCode, events::*, risk::oracle_price, LpPool, LpWithdrawal, Market};
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default)]
+pub struct LpPoolNav {
+    pub nav: i64,                     // Realized pool value; negative when trader profits exceed deposits
+    pub share_supply: u64,
+    pub net_deposits: i64,
+    pub realized_pnl: i64,            // Counterparty PnL realized since the pool was created
+    pub fee_income: i64,              // Fee pool growth since the pool was created
+    pub unrealized_trader_pnl: i64,   // Open trader PnL at the oracle price; not part of the NAV
+    pub oracle_price: u64,
+}
+
+/// PnL of all open positions at `price`, computed from the market's aggregate entry notional
+pub fn unrealized_trader_pnl(market: &Market, price: u64) -> Result<i64> {
+    let long_value = (market.long_open_interest as i128)
+        .checked_mul(price as i128)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let short_value = (market.short_open_interest as i128)
+        .checked_mul(price as i128)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    long_value
+        .checked_sub(market.long_entry_notional as i128)
+        .and_then(|pnl| pnl.checked_add(market.short_entry_notional as i128))
+        .and_then(|pnl| pnl.checked_sub(short_value))
+        .and_then(|pnl| i64::try_from(pnl).ok())
+        .ok_or(ErrorCode::MathOverflow.into())
+}
+
+/// Value of the pool: net deposits plus the counterparty PnL and fee pool growth realized since
+/// the pool was created, whether settled into its vault yet or not. Open positions are reported
+/// at `price` but left out, so deposits and withdrawals only move collateral that was collected.
+pub fn pool_nav(pool: &LpPool, market: &Market, price: u64, share_supply: u64) -> Result<LpPoolNav> {
+    let realized = pool.realized_pnl
+        .checked_add(market.counterparty_pnl)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let fee_income = i64::try_from(pool.fee_income as i128 + unsettled_fees(pool, market) as i128)
+        .map_err(|_| ErrorCode::MathOverflow)?;
+    let nav = pool.net_deposits
+        .checked_add(realized)
+        .and_then(|nav| nav.checked_add(fee_income))
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    Ok(LpPoolNav {
+        nav,
+        share_supply,
+        net_deposits: pool.net_deposits,
+        realized_pnl: realized,
+        fee_income,
+        unrealized_trader_pnl: unrealized_trader_pnl(market, price)?,
+        oracle_price: price,
+    })
+}
+
+/// Fee pool growth the pool has not settled. Fees the vAMM spent below the last settlement
+/// were never the pool's to take.
+fn unsettled_fees(pool: &LpPool, market: &Market) -> u64 {
+    market.fee_pool.saturating_sub(pool.fee_pool_baseline)
+}
+
+/// Amount to move into (positive) or out of the pool vault to settle the market's counterparty
+/// PnL and fee growth, and the part of it that is PnL. A pool vault that cannot cover its losses
+/// pays what it holds and leaves the rest owed.
+fn settlement(pool: &LpPool, market: &Market) -> Result<(i64, i64)> {
+    let fees = i64::try_from(unsettled_fees(pool, market)).map_err(|_| ErrorCode::MathOverflow)?;
+    let owed = market.counterparty_pnl
+        .checked_add(fees)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let amount = owed.max(-i64::try_from(market.lp_balance).map_err(|_| ErrorCode::MathOverflow)?);
+    let pnl = amount
+        .checked_sub(fees)
+        .ok_or(ErrorCode::MathOverflow)?;
+    Ok((amount, pnl))
+}
+
+/// Move realized counterparty PnL and fees between the market vault and the pool vault, so the
+/// pool vault holds the pool's realized value
+fn settle_pool<'info>(
+    market: &mut Account<'info, Market>,
+    lp_pool: &mut Account<'info, LpPool>,
+    market_vault: &Account<'info, TokenAccount>,
+    lp_vault: &Account<'info, TokenAccount>,
+    token_program: &Program<'info, Token>,
+) -> Result<()> {
+    let fees = unsettled_fees(lp_pool, market);
+    let (amount, pnl) = settlement(lp_pool, market)?;
+
+    if amount != 0 {
+        let market_symbol = market.market_symbol.clone();
+        let market_bump = market.bump;
+        let seeds = &[
+            b"market".as_ref(),
+            market_symbol.as_bytes(),
+            &[market_bump],
+        ];
+        let signer = &[&seeds[..]];
+        let (from, to) = if amount > 0 {
+            (market_vault, lp_vault)
+        } else {
+            (lp_vault, market_vault)
+        };
+        let transfer_ctx = CpiContext::new_with_signer(
+            token_program.to_account_info(),
+            Transfer {
+                from: from.to_account_info(),
+                to: to.to_account_info(),
+                authority: market.to_account_info(),
+            },
+            signer,
+        );
+        token::transfer(transfer_ctx, amount.unsigned_abs())?;
+    }
+
+    market.counterparty_pnl = market.counterparty_pnl
+        .checked_sub(pnl)
+        .ok_or(ErrorCode::MathOverflow)?;
+    market.fee_pool -= fees;
+    market.lp_balance = (market.lp_balance as i64)
+        .checked_add(amount)
+        .and_then(|balance| u64::try_from(balance).ok())
+        .ok_or(ErrorCode::MathOverflow)?;
+    lp_pool.realized_pnl = lp_pool.realized_pnl
+        .checked_add(pnl)
+        .ok_or(ErrorCode::MathOverflow)?;
+    lp_pool.fee_income = lp_pool.fee_income
+        .checked_add(fees)
+        .ok_or(ErrorCode::MathOverflow)?;
+    lp_pool.fee_pool_baseline = market.fee_pool;
+
+    emit!(LpPoolSettledEvent {
+        pool: lp_pool.key(),
+        pnl,
+        fees,
+        lp_balance: market.lp_balance,
+    });
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct InitializeLpPool<'info> {
+    #[account(mut, has_one = authority)]
+    pub market: Account<'info, Market>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(
+        init,
+        payer = authority,
+        space = LpPool::SPACE,
+        seeds = [b"lp_pool", market.key().as_ref()],
+        bump
+    )]
+    pub lp_pool: Account<'info, LpPool>,
+    #[account(
+        init,
+        payer = authority,
+        mint::decimals = collateral_mint.decimals,
+        mint::authority = market,
+        seeds = [b"lp_mint", market.key().as_ref()],
+        bump
+    )]
+    pub share_mint: Account<'info, Mint>,
+    #[account(
+        init,
+        payer = authority,
+        token::mint = collateral_mint,
+        token::authority = market,
+        seeds = [b"lp_vault", market.key().as_ref()],
+        bump
+    )]
+    pub lp_vault: Account<'info, TokenAccount>,
+    #[account(address = market_vault.mint @ ErrorCode::InvalidCollateralMint)]
+    pub collateral_mint: Account<'info, Mint>,
+    #[account(constraint = market_vault.key() == market.vault @ ErrorCode::InvalidVault)]
+    pub market_vault: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+/// Create the market's LP pool and its vault. The pool takes the counterparty PnL realized from
+/// here on, including that of positions already open when they close. Withdrawal requests can be
+/// redeemed for `withdrawal_window` seconds once `withdrawal_cooldown` has passed.
+pub fn initialize_lp_pool(
+    ctx: Context<InitializeLpPool>,
+    withdrawal_cooldown: i64,
+    withdrawal_window: i64,
+) -> Result<()> {
+    require!(withdrawal_cooldown >= 0 && withdrawal_window > 0, ErrorCode::InvalidParameter);
+    let market = &mut ctx.accounts.market;
+    // PnL realized before the pool existed was settled between traders
+    market.counterparty_pnl = 0;
+    market.lp_vault = ctx.accounts.lp_vault.key();
+    market.lp_balance = 0;
+
+    let lp_pool = &mut ctx.accounts.lp_pool;
+    lp_pool.market = market.key();
+    lp_pool.share_mint = ctx.accounts.share_mint.key();
+    lp_pool.net_deposits = 0;
+    lp_pool.realized_pnl = 0;
+    lp_pool.withdrawal_cooldown = withdrawal_cooldown;
+    lp_pool.bump = ctx.bumps.lp_pool;
+    lp_pool.withdrawal_window = withdrawal_window;
+    lp_pool.fee_pool_baseline = market.fee_pool;
+    lp_pool.fee_income = 0;
+    lp_pool.vault = market.lp_vault;
+
+    msg!(
+        "Initialized LP pool for {} with cooldown {}s, window {}s",
+        market.market_symbol,
+        withdrawal_cooldown,
+        withdrawal_window
+    );
+
+    emit!(LpPoolInitializedEvent {
+        market: market.key(),
+        pool: lp_pool.key(),
+        share_mint: lp_pool.share_mint,
+        withdrawal_cooldown,
+        withdrawal_window,
+        vault: lp_pool.vault,
+    });
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct SettleLpPool<'info> {
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+    #[account(
+        mut,
+        has_one = market,
+        has_one = vault @ ErrorCode::InvalidVault,
+        seeds = [b"lp_pool", market.key().as_ref()],
+        bump = lp_pool.bump,
+    )]
+    pub lp_pool: Account<'info, LpPool>,
+    #[account(mut)]
+    pub vault: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = market_vault.key() == market.vault @ ErrorCode::InvalidVault,
+    )]
+    pub market_vault: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+}
+
+/// Permissionless: move the pool's realized PnL and fees between the market vault and the pool
+/// vault, so that profits credited to traders are backed in the market vault
+pub fn settle_lp_pool(ctx: Context<SettleLpPool>) -> Result<()> {
+    settle_pool(
+        &mut ctx.accounts.market,
+        &mut ctx.accounts.lp_pool,
+        &ctx.accounts.market_vault,
+        &ctx.accounts.vault,
+        &ctx.accounts.token_program,
+    )
+}
+
+#[derive(Accounts)]
+pub struct DepositLp<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    #[account(mut, constraint = market.is_active @ ErrorCode::MarketInactive)]
+    pub market: Account<'info, Market>,
+    #[account(
+        mut,
+        has_one = market,
+        has_one = share_mint,
+        has_one = vault @ ErrorCode::InvalidVault,
+        seeds = [b"lp_pool", market.key().as_ref()],
+        bump = lp_pool.bump,
+    )]
+    pub lp_pool: Account<'info, LpPool>,
+    #[account(mut)]
+    pub share_mint: Account<'info, Mint>,
+    #[account(
+        mut,
+        constraint = user_token_account.owner == owner.key() @ ErrorCode::Unauthorized,
+    )]
+    pub user_token_account: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = share_mint,
+        constraint = user_share_account.owner == owner.key() @ ErrorCode::Unauthorized,
+    )]
+    pub user_share_account: Account<'info, TokenAccount>,
+    /// Pool vault receiving the deposit
+    #[account(mut)]
+    pub vault: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = market_vault.key() == market.vault @ ErrorCode::InvalidVault,
+    )]
+    pub market_vault: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+}
+
+/// Deposit collateral into the pool vault in exchange for pool shares at the current NAV
+pub fn deposit_lp(ctx: Context<DepositLp>, amount: u64) -> Result<()> {
+    require!(amount > 0, ErrorCode::InvalidParameter);
+    settle_pool(
+        &mut ctx.accounts.market,
+        &mut ctx.accounts.lp_pool,
+        &ctx.accounts.market_vault,
+        &ctx.accounts.vault,
+        &ctx.accounts.token_program,
+    )?;
+
+    let market = &mut ctx.accounts.market;
+    let share_supply = ctx.accounts.share_mint.supply;
+    let nav = pool_nav(&ctx.accounts.lp_pool, market, market.last_oracle_price, share_supply)?;
+    let shares = shares_for_deposit(amount, nav.nav, share_supply)?;
+
+    let transfer_ctx = CpiContext::new(
+        ctx.accounts.token_program.to_account_info(),
+        Transfer {
+            from: ctx.accounts.user_token_account.to_account_info(),
+            to: ctx.accounts.vault.to_account_info(),
+            authority: ctx.accounts.owner.to_account_info(),
+        },
+    );
+    token::transfer(transfer_ctx, amount)?;
+
+    let market_symbol = market.market_symbol.clone();
+    let market_bump = market.bump;
+    let seeds = &[
+        b"market".as_ref(),
+        market_symbol.as_bytes(),
+        &[market_bump],
+    ];
+    let signer = &[&seeds[..]];
+    let mint_ctx = CpiContext::new_with_signer(
+        ctx.accounts.token_program.to_account_info(),
+        MintTo {
+            mint: ctx.accounts.share_mint.to_account_info(),
+            to: ctx.accounts.user_share_account.to_account_info(),
+            authority: market.to_account_info(),
+        },
+        signer,
+    );
+    token::mint_to(mint_ctx, shares)?;
+
+    market.lp_balance = market.lp_balance
+        .checked_add(amount)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let lp_pool = &mut ctx.accounts.lp_pool;
+    lp_pool.net_deposits = lp_pool.net_deposits
+        .checked_add(i64::try_from(amount).map_err(|_| ErrorCode::MathOverflow)?)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    emit!(LpDepositedEvent {
+        pool: lp_pool.key(),
+        owner: ctx.accounts.owner.key(),
+        amount,
+        shares,
+        nav: nav.nav,
+        share_supply: share_supply
+            .checked_add(shares)
+            .ok_or(ErrorCode::MathOverflow)?,
+    });
+
+    Ok(())
+}
+
+/// Shares minted for depositing `amount`: one to one for the first deposit, at `nav` after that
+fn shares_for_deposit(amount: u64, nav: i64, share_supply: u64) -> Result<u64> {
+    let shares = if share_supply == 0 {
+        amount
+    } else {
+        require!(nav > 0, ErrorCode::LpPoolInsolvent);
+        (amount as u128)
+            .checked_mul(share_supply as u128)
+            .and_then(|value| value.checked_div(nav as u128))
+            .and_then(|shares| u64::try_from(shares).ok())
+            .ok_or(ErrorCode::MathOverflow)?
+    };
+    require!(shares > 0, ErrorCode::InvalidParameter);
+    Ok(shares)
+}
+
+/// Collateral paid out for redeeming `shares`. Shares of an insolvent pool are burned for nothing.
+fn withdrawal_amount(shares: u64, nav: i64, share_supply: u64) -> Result<u64> {
+    if nav <= 0 {
+        return Ok(0);
+    }
+    (shares as u128)
+        .checked_mul(nav as u128)
+        .and_then(|value| value.checked_div(share_supply as u128))
+        .and_then(|amount| u64::try_from(amount).ok())
+        .ok_or(ErrorCode::MathOverflow.into())
+}
+
+#[derive(Accounts)]
+pub struct RequestLpWithdrawal<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    #[account(has_one = share_mint)]
+    pub lp_pool: Account<'info, LpPool>,
+    pub share_mint: Account<'info, Mint>,
+    #[account(
+        token::mint = share_mint,
+        constraint = user_share_account.owner == owner.key() @ ErrorCode::Unauthorized,
+    )]
+    pub user_share_account: Account<'info, TokenAccount>,
+    #[account(
+        init_if_needed,
+        payer = owner,
+        space = LpWithdrawal::SPACE,
+        seeds = [b"lp_withdrawal", lp_pool.key().as_ref(), owner.key().as_ref()],
+        bump
+    )]
+    pub lp_withdrawal: Account<'info, LpWithdrawal>,
+    pub system_program: Program<'info, System>,
+}
+
+/// Request to redeem `shares` once the pool's cooldown has passed. The request expires when the
+/// pool's withdrawal window after the cooldown ends. A new request replaces the previous one and
+/// restarts the cooldown.
+pub fn request_lp_withdrawal(ctx: Context<RequestLpWithdrawal>, shares: u64) -> Result<()> {
+    require!(shares > 0, ErrorCode::InvalidParameter);
+    require!(
+        ctx.accounts.user_share_account.amount >= shares,
+        ErrorCode::InsufficientLpShares
+    );
+
+    let current_time = Clock::get()?.unix_timestamp;
+    let lp_withdrawal = &mut ctx.accounts.lp_withdrawal;
+    lp_withdrawal.pool = ctx.accounts.lp_pool.key();
+    lp_withdrawal.owner = ctx.accounts.owner.key();
+    lp_withdrawal.shares = shares;
+    lp_withdrawal.requested_at = current_time;
+    lp_withdrawal.bump = ctx.bumps.lp_withdrawal;
+
+    let (available_at, expires_at) = withdrawal_period(&ctx.accounts.lp_pool, current_time)?;
+    msg!(
+        "Requested withdrawal of {} LP shares, available from {} until {}",
+        shares,
+        available_at,
+        expires_at
+    );
+
+    emit!(LpWithdrawalRequestedEvent {
+        pool: lp_withdrawal.pool,
+        owner: lp_withdrawal.owner,
+        shares,
+        available_at,
+        expires_at,
+    });
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct WithdrawLp<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+    #[account(
+        mut,
+        has_one = market,
+        has_one = share_mint,
+        has_one = vault @ ErrorCode::InvalidVault,
+        seeds = [b"lp_pool", market.key().as_ref()],
+        bump = lp_pool.bump,
+    )]
+    pub lp_pool: Account<'info, LpPool>,
+    #[account(
+        mut,
+        close = owner,
+        has_one = owner,
+        constraint = lp_withdrawal.pool == lp_pool.key() @ ErrorCode::Unauthorized,
+        seeds = [b"lp_withdrawal", lp_pool.key().as_ref(), owner.key().as_ref()],
+        bump = lp_withdrawal.bump,
+    )]
+    pub lp_withdrawal: Account<'info, LpWithdrawal>,
+    #[account(mut)]
+    pub share_mint: Account<'info, Mint>,
+    #[account(
+        mut,
+        token::mint = share_mint,
+        constraint = user_share_account.owner == owner.key() @ ErrorCode::Unauthorized,
+    )]
+    pub user_share_account: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = user_token_account.owner == owner.key() @ ErrorCode::Unauthorized,
+    )]
+    pub user_token_account: Account<'info, TokenAccount>,
+    /// Pool vault paying out the withdrawal
+    #[account(mut)]
+    pub vault: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = market_vault.key() == market.vault @ ErrorCode::InvalidVault,
+    )]
+    pub market_vault: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+}
+
+/// Redeem a requested withdrawal between the end of its cooldown and its expiry: burn the shares
+/// and pay out their part of the pool's realized NAV from the pool vault
+pub fn withdraw_lp(ctx: Context<WithdrawLp>) -> Result<()> {
+    let current_time = Clock::get()?.unix_timestamp;
+    check_withdrawal_period(&ctx.accounts.lp_pool, ctx.accounts.lp_withdrawal.requested_at, current_time)?;
+
+    let shares = ctx.accounts.lp_withdrawal.shares;
+    require!(
+        ctx.accounts.user_share_account.amount >= shares,
+        ErrorCode::InsufficientLpShares
+    );
+
+    settle_pool(
+        &mut ctx.accounts.market,
+        &mut ctx.accounts.lp_pool,
+        &ctx.accounts.market_vault,
+        &ctx.accounts.vault,
+        &ctx.accounts.token_program,
+    )?;
+
+    let market = &mut ctx.accounts.market;
+    let share_supply = ctx.accounts.share_mint.supply;
+    let nav = pool_nav(&ctx.accounts.lp_pool, market, market.last_oracle_price, share_supply)?;
+    // Losses the pool vault could not pay are still owed, so the NAV never exceeds the vault
+    let amount = withdrawal_amount(shares, nav.nav, share_supply)?;
+
+    let burn_ctx = CpiContext::new(
+        ctx.accounts.token_program.to_account_info(),
+        Burn {
+            mint: ctx.accounts.share_mint.to_account_info(),
+            from: ctx.accounts.user_share_account.to_account_info(),
+            authority: ctx.accounts.owner.to_account_info(),
+        },
+    );
+    token::burn(burn_ctx, shares)?;
+
+    if amount > 0 {
+        let market_symbol = market.market_symbol.clone();
+        let market_bump = market.bump;
+        let seeds = &[
+            b"market".as_ref(),
+            market_symbol.as_bytes(),
+            &[market_bump],
+        ];
+        let signer = &[&seeds[..]];
+        let transfer_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.vault.to_account_info(),
+                to: ctx.accounts.user_token_account.to_account_info(),
+                authority: market.to_account_info(),
+            },
+            signer,
+        );
+        token::transfer(transfer_ctx, amount)?;
+    }
+
+    market.lp_balance = market.lp_balance
+        .checked_sub(amount)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let lp_pool = &mut ctx.accounts.lp_pool;
+    lp_pool.net_deposits = lp_pool.net_deposits
+        .checked_sub(i64::try_from(amount).map_err(|_| ErrorCode::MathOverflow)?)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    emit!(LpWithdrawnEvent {
+        pool: lp_pool.key(),
+        owner: ctx.accounts.owner.key(),
+        shares,
+        amount,
+        nav: nav.nav,
+        share_supply: share_supply - shares,
+    });
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct GetLpPoolNav<'info> {
+    pub market: Account<'info, Market>,
+    #[account(has_one = market, has_one = share_mint)]
+    pub lp_pool: Account<'info, LpPool>,
+    pub share_mint: Account<'info, Mint>,
+    /// CHECK: Oracle account - validated in instruction
+    #[account(address = market.oracle @ ErrorCode::InvalidOracleAccount)]
+    pub price_update: UncheckedAccount<'info>,
+}
+
+/// Report, through return data, the pool's NAV, with open trader PnL at the current oracle price
+pub fn get_lp_pool_nav(ctx: Context<GetLpPoolNav>) -> Result<LpPoolNav> {
+    let current_price = oracle_price(&ctx.accounts.price_update)?;
+    pool_nav(&ctx.accounts.lp_pool, &ctx.accounts.market, current_price, ctx.accounts.share_mint.supply)
+}
+
+/// Times from which, and until which, a withdrawal requested at `requested_at` can be redeemed
+fn withdrawal_period(pool: &LpPool, requested_at: i64) -> Result<(i64, i64)> {
+    let available_at = requested_at
+        .checked_add(pool.withdrawal_cooldown)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let expires_at = available_at
+        .checked_add(pool.withdrawal_window)
+        .ok_or(ErrorCode::MathOverflow)?;
+    Ok((available_at, expires_at))
+}
+
+/// Require `current_time` to fall between the end of the request's cooldown and its expiry
+fn check_withdrawal_period(pool: &LpPool, requested_at: i64, current_time: i64) -> Result<()> {
+    let (available_at, expires_at) = withdrawal_period(pool, requested_at)?;
+    require!(current_time >= available_at, ErrorCode::LpWithdrawalCooldown);
+    require!(current_time < expires_at, ErrorCode::LpWithdrawalExpired);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instructions::order::record_trader_pnl;
+
+    fn pool() -> LpPool {
+        LpPool {
+            net_deposits: 10_000,
+            realized_pnl: 500,
+            fee_income: 100,
+            fee_pool_baseline: 1_000,
+            withdrawal_cooldown: 100,
+            withdrawal_window: 50,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn nav_counts_realized_pnl_and_fees_but_not_open_positions() {
+        // Longs opened 10 at 1000 and are 1000 in profit at 1100
+        let market = Market {
+            counterparty_pnl: -200,
+            fee_pool: 1_300,
+            long_open_interest: 10,
+            long_entry_notional: 10 * 1_000,
+            ..Default::default()
+        };
+
+        let nav = pool_nav(&pool(), &market, 1_100, 1_000).unwrap();
+        assert_eq!(nav.realized_pnl, 500 - 200);
+        assert_eq!(nav.fee_income, 100 + 300);
+        assert_eq!(nav.nav, 10_000 + 300 + 400);
+        assert_eq!(nav.unrealized_trader_pnl, 1_000);
+    }
+
+    #[test]
+    fn settlement_moves_realized_pnl_and_fees_to_the_pool_vault() {
+        let market = Market { counterparty_pnl: 300, fee_pool: 1_050, lp_balance: 400, ..Default::default() };
+        assert_eq!(settlement(&pool(), &market).unwrap(), (350, 300));
+
+        // Fees the vAMM spent below the last settlement are not the pool's
+        let market = Market { counterparty_pnl: 300, fee_pool: 900, lp_balance: 400, ..Default::default() };
+        assert_eq!(settlement(&pool(), &market).unwrap(), (300, 300));
+
+        // Losses beyond what the pool vault holds stay owed
+        let market = Market { counterparty_pnl: -1_000, fee_pool: 1_050, lp_balance: 400, ..Default::default() };
+        assert_eq!(settlement(&pool(), &market).unwrap(), (-400, -450));
+    }
+
+    #[test]
+    fn deposits_and_withdrawals_are_priced_at_nav() {
+        // The first deposit mints shares one to one
+        assert_eq!(shares_for_deposit(1_000, 0, 0).unwrap(), 1_000);
+        // Later ones buy in at the NAV per share
+        assert_eq!(shares_for_deposit(1_000, 2_000, 1_000).unwrap(), 500);
+        assert_eq!(
+            shares_for_deposit(1_000, 0, 1_000).unwrap_err(),
+            ErrorCode::LpPoolInsolvent.into()
+        );
+        assert_eq!(
+            shares_for_deposit(1, 2_000, 1_000).unwrap_err(),
+            ErrorCode::InvalidParameter.into()
+        );
+
+        assert_eq!(withdrawal_amount(500, 2_000, 1_000).unwrap(), 1_000);
+        // Shares of an insolvent pool redeem for nothing
+        assert_eq!(withdrawal_amount(500, -100, 1_000).unwrap(), 0);
+    }
+
+    #[test]
+    fn withdrawals_open_after_the_cooldown_and_expire_after_the_window() {
+        let pool = pool();
+        assert_eq!(
+            check_withdrawal_period(&pool, 1_000, 1_099).unwrap_err(),
+            ErrorCode::LpWithdrawalCooldown.into()
+        );
+        assert!(check_withdrawal_period(&pool, 1_000, 1_100).is_ok());
+        assert!(check_withdrawal_period(&pool, 1_000, 1_149).is_ok());
+        assert_eq!(
+            check_withdrawal_period(&pool, 1_000, 1_150).unwrap_err(),
+            ErrorCode::LpWithdrawalExpired.into()
+        );
+    }
+
+    #[test]
+    fn trader_profits_are_capped_at_the_pool_value() {
+        // Without a pool, trader profits are paid in full
+        let mut market = Market { counterparty_pnl: -300, ..Default::default() };
+        assert_eq!(record_trader_pnl(&mut market, 900).unwrap(), 900);
+
+        // A pool holding 1000 that already owes 300 pays at most 700
+        let mut market = Market {
+            lp_vault: Pubkey::new_unique(),
+            lp_balance: 1_000,
+            counterparty_pnl: -300,
+            ..Default::default()
+        };
+        assert_eq!(record_trader_pnl(&mut market, 900).unwrap(), 700);
+        assert_eq!(market.counterparty_pnl, -1_000);
+        assert_eq!(record_trader_pnl(&mut market, 100).unwrap(), 0);
+        // Losses are always taken in full
+        assert_eq!(record_trader_pnl(&mut market, -250).unwrap(), -250);
+        assert_eq!(market.counterparty_pnl, -750);
+    }
+}
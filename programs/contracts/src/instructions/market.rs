@@ -1,6 +1,8 @@
 use anchor_lang::prelude::*;
+use anchor_lang::system_program;
+use anchor_lang::Discriminator;
 use anchor_spl::token::{Mint, Token, TokenAccount};
-use crate::{errors::ErrorCode, events::*, Market};
+use crate::{errors::ErrorCode, events::*, MarginTier, Market, Position, Side, MAX_MARGIN_TIERS};
 
 #[derive(Accounts)]
 #[instruction(
@@ -11,6 +13,13 @@ use crate::{errors::ErrorCode, events::*, Market};
     initial_margin_ratio: u64,
     max_leverage: u64,
     liquidation_fee_ratio: u64,
+    min_order_size: u64,
+    order_step_size: u64,
+    max_position_size: u64,
+    max_open_interest: u64,
+    base_asset_reserve: u64,
+    quote_asset_reserve: u64,
+    peg_multiplier: u64,
     bump: u8
 )]
 pub struct InitializeMarket<'info> {
@@ -41,6 +50,7 @@ pub struct InitializeMarket<'info> {
     pub system_program: Program<'info, System>
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn initialize_market(
     ctx: Context<InitializeMarket>,
     market_symbol: String,
@@ -50,6 +60,13 @@ pub fn initialize_market(
     initial_margin_ratio: u64,
     max_leverage: u64,
     liquidation_fee_ratio: u64,
+    min_order_size: u64,
+    order_step_size: u64,
+    max_position_size: u64,
+    max_open_interest: u64,
+    base_asset_reserve: u64,
+    quote_asset_reserve: u64,
+    peg_multiplier: u64,
     bump: u8,
 ) -> Result<()> {
     // Validate inputs
@@ -65,6 +82,12 @@ pub fn initialize_market(
     );
     require!(max_leverage > 0, ErrorCode::InvalidLeverage);
     require!(liquidation_fee_ratio > 0 && liquidation_fee_ratio < 10000, ErrorCode::InvalidParameter);
+    validate_size_params(min_order_size, order_step_size, max_position_size)?;
+    require!(max_open_interest > 0, ErrorCode::InvalidParameter);
+    require!(
+        base_asset_reserve > 0 && quote_asset_reserve > 0 && peg_multiplier > 0,
+        ErrorCode::InvalidParameter
+    );
 
     let market = &mut ctx.accounts.market;
     let authority = &ctx.accounts.authority;
@@ -73,8 +96,8 @@ pub fn initialize_market(
     // Initialize market
     market.authority = authority.key();
     market.market_symbol = market_symbol.clone();
-    market.base_asset_reserve = 1_000_000_000; // Start with 1 billion base units
-    market.quote_asset_reserve = 0;
+    market.base_asset_reserve = base_asset_reserve;
+    market.quote_asset_reserve = quote_asset_reserve;
     market.funding_rate = initial_funding_rate;
     market.last_funding_time = clock.unix_timestamp;
     market.funding_interval = funding_interval;
@@ -88,6 +111,39 @@ pub fn initialize_market(
     market.vault = ctx.accounts.vault.key();
     market.is_active = true;
     market.bump = bump;
+    market.cumulative_funding_rate = 0;
+    market.min_order_size = min_order_size;
+    market.order_step_size = order_step_size;
+    market.max_position_size = max_position_size;
+    market.long_open_interest = 0;
+    market.short_open_interest = 0;
+    market.max_open_interest = max_open_interest;
+    market.long_bad_debt = 0;
+    market.short_bad_debt = 0;
+    market.long_bankruptcy_price = 0;
+    market.short_bankruptcy_price = 0;
+    market.auction_size_threshold = 0;
+    market.auction_duration_slots = 0;
+    market.auction_start_discount = 0;
+    market.auction_max_discount = 0;
+    market.margin_tiers = [MarginTier::default(); MAX_MARGIN_TIERS];
+    market.margin_tier_count = 0;
+    market.peg_multiplier = peg_multiplier;
+    market.amm_max_adjustment_cost = 0;
+    market.amm_fee_pool_share = 0;
+    market.base_spread = 0;
+    market.max_spread = 0;
+    market.skew_spread = 0;
+    market.oracle_volatility = 0;
+    market.last_oracle_price = 0;
+    market.long_entry_notional = 0;
+    market.short_entry_notional = 0;
+    market.counterparty_pnl = 0;
+    market.max_oracle_deviation = 0;
+    market.amm_net_base = 0;
+    market.last_volatility_update = 0;
+    market.lp_vault = Pubkey::default();
+    market.lp_balance = 0;
 
     // Emit event
     emit!(MarketInitializedEvent {
@@ -100,6 +156,13 @@ pub fn initialize_market(
         initial_margin_ratio,
         max_leverage,
         liquidation_fee_ratio,
+        min_order_size,
+        order_step_size,
+        max_position_size,
+        max_open_interest,
+        base_asset_reserve,
+        quote_asset_reserve,
+        peg_multiplier,
     });
 
     Ok(())
@@ -112,12 +175,17 @@ pub struct UpdateMarketParams<'info> {
     pub authority: Signer<'info>,
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn update_market_params(
     ctx: Context<UpdateMarketParams>,
     maintenance_margin_ratio: Option<u64>,
     initial_margin_ratio: Option<u64>,
     funding_interval: Option<i64>,
     max_leverage: Option<u64>,
+    min_order_size: Option<u64>,
+    order_step_size: Option<u64>,
+    max_position_size: Option<u64>,
+    max_open_interest: Option<u64>,
 ) -> Result<()> {
     let market = &mut ctx.accounts.market;
 
@@ -146,6 +214,26 @@ pub fn update_market_params(
         market.max_leverage = leverage;
     }
 
+    if let Some(size) = min_order_size {
+        market.min_order_size = size;
+    }
+
+    if let Some(size) = order_step_size {
+        market.order_step_size = size;
+    }
+
+    if let Some(size) = max_position_size {
+        market.max_position_size = size;
+    }
+
+    validate_size_params(market.min_order_size, market.order_step_size, market.max_position_size)?;
+
+    // Lowering the cap below current open interest only blocks new exposure
+    if let Some(open_interest) = max_open_interest {
+        require!(open_interest > 0, ErrorCode::InvalidParameter);
+        market.max_open_interest = open_interest;
+    }
+
     // Emit event
     emit!(MarketParamsUpdatedEvent {
         market: market.key(),
@@ -154,6 +242,289 @@ pub fn update_market_params(
         initial_margin_ratio: market.initial_margin_ratio,
         funding_interval: market.funding_interval,
         max_leverage: market.max_leverage,
+        min_order_size: market.min_order_size,
+        order_step_size: market.order_step_size,
+        max_position_size: market.max_position_size,
+        max_open_interest: market.max_open_interest,
+    });
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct UpdateLiquidationAuctionParams<'info> {
+    #[account(mut, has_one = authority)]
+    pub market: Account<'info, Market>,
+    pub authority: Signer<'info>,
+}
+
+/// Configure Dutch-auction liquidations. A `size_threshold` of 0 turns them off.
+pub fn update_liquidation_auction_params(
+    ctx: Context<UpdateLiquidationAuctionParams>,
+    size_threshold: u64,
+    duration_slots: u64,
+    start_discount: u64,
+    max_discount: u64,
+) -> Result<()> {
+    let market = &mut ctx.accounts.market;
+
+    if size_threshold > 0 {
+        require!(duration_slots > 0, ErrorCode::InvalidParameter);
+        require!(
+            start_discount <= max_discount && max_discount < 10000,
+            ErrorCode::InvalidParameter
+        );
+    }
+
+    market.auction_size_threshold = size_threshold;
+    market.auction_duration_slots = duration_slots;
+    market.auction_start_discount = start_discount;
+    market.auction_max_discount = max_discount;
+
+    emit!(LiquidationAuctionParamsUpdatedEvent {
+        market: market.key(),
+        authority: ctx.accounts.authority.key(),
+        size_threshold,
+        duration_slots,
+        start_discount,
+        max_discount,
+    });
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct SetMarginTiers<'info> {
+    #[account(mut, has_one = authority)]
+    pub market: Account<'info, Market>,
+    pub authority: Signer<'info>,
+}
+
+/// Replace the market's margin tiers. Tiers are ordered by ascending `max_size`, and larger
+/// tiers may not lower the initial or maintenance margin. Each tier's maintenance margin offset
+/// is derived here so that the maintenance margin is continuous across tier boundaries; offsets
+/// passed in are ignored. An empty list falls back to the market's margin and liquidation fee
+/// ratios for every size.
+pub fn set_margin_tiers(ctx: Context<SetMarginTiers>, mut tiers: Vec<MarginTier>) -> Result<()> {
+    let market = &mut ctx.accounts.market;
+
+    require!(tiers.len() <= MAX_MARGIN_TIERS, ErrorCode::InvalidParameter);
+    for i in 0..tiers.len() {
+        let tier = tiers[i];
+        require!(tier.max_size > 0, ErrorCode::InvalidParameter);
+        // Positions must be able to open above their maintenance margin
+        require!(
+            tier.maintenance_margin_ratio > 0
+                && tier.maintenance_margin_ratio < tier.initial_margin_ratio
+                && tier.initial_margin_ratio < 10000,
+            ErrorCode::InvalidParameter
+        );
+        require!(
+            tier.liquidation_fee_ratio > 0 && tier.liquidation_fee_ratio < 10000,
+            ErrorCode::InvalidParameter
+        );
+
+        tiers[i].maintenance_margin_offset = if i == 0 {
+            0
+        } else {
+            let previous = tiers[i - 1];
+            require!(tier.max_size > previous.max_size, ErrorCode::InvalidParameter);
+            require!(
+                tier.initial_margin_ratio >= previous.initial_margin_ratio
+                    && tier.maintenance_margin_ratio >= previous.maintenance_margin_ratio,
+                ErrorCode::InvalidParameter
+            );
+            // At the previous tier's largest size both tiers require the same maintenance margin
+            previous.max_size
+                .checked_mul(tier.maintenance_margin_ratio - previous.maintenance_margin_ratio)
+                .and_then(|step| step.checked_add(previous.maintenance_margin_offset))
+                .ok_or(ErrorCode::MathOverflow)?
+        };
+    }
+
+    market.margin_tiers = [MarginTier::default(); MAX_MARGIN_TIERS];
+    market.margin_tiers[..tiers.len()].copy_from_slice(&tiers);
+    market.margin_tier_count = tiers.len() as u8;
+
+    emit!(MarginTiersUpdatedEvent {
+        market: market.key(),
+        authority: ctx.accounts.authority.key(),
+        tiers,
+    });
+
+    Ok(())
+}
+
+/// Sizes must be positive, the minimum a whole number of lots, and the maximum at least the minimum
+fn validate_size_params(min_order_size: u64, order_step_size: u64, max_position_size: u64) -> Result<()> {
+    require!(order_step_size > 0, ErrorCode::InvalidParameter);
+    require!(
+        min_order_size > 0 && min_order_size % order_step_size == 0,
+        ErrorCode::InvalidParameter
+    );
+    require!(max_position_size >= min_order_size, ErrorCode::InvalidParameter);
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct MigrateMarket<'info> {
+    /// CHECK: Market in a pre-upgrade layout, which no longer deserializes as `Market`.
+    /// Ownership, discriminator and authority are checked in the instruction.
+    #[account(mut, owner = crate::ID)]
+    pub market: UncheckedAccount<'info>,
+    /// Pays the rent for the larger account
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+/// Grow a market created before the current layout to `Market::SPACE`. Fields added since then
+/// are appended after the original ones and start zeroed; the ones without a usable zero value
+/// are set here. The market must be paused. Open interest starts from zero and is rebuilt as its
+/// positions are brought to the current layout with `migrate_position`.
+#[allow(clippy::too_many_arguments)]
+pub fn migrate_market(
+    ctx: Context<MigrateMarket>,
+    min_order_size: u64,
+    order_step_size: u64,
+    max_position_size: u64,
+    max_open_interest: u64,
+    base_asset_reserve: u64,
+    quote_asset_reserve: u64,
+    peg_multiplier: u64,
+) -> Result<()> {
+    validate_size_params(min_order_size, order_step_size, max_position_size)?;
+    require!(max_open_interest > 0, ErrorCode::InvalidParameter);
+    require!(
+        base_asset_reserve > 0 && quote_asset_reserve > 0 && peg_multiplier > 0,
+        ErrorCode::InvalidParameter
+    );
+
+    let market_info = ctx.accounts.market.to_account_info();
+    let old_space = market_info.data_len();
+    {
+        let data = market_info.try_borrow_data()?;
+        require!(data.len() >= 40 && data[..8] == Market::DISCRIMINATOR, ErrorCode::InvalidParameter);
+        // The authority is the first field in every layout
+        require!(
+            data[8..40] == ctx.accounts.authority.key().to_bytes(),
+            ErrorCode::Unauthorized
+        );
+    }
+    require!(old_space < Market::SPACE, ErrorCode::InvalidParameter);
+
+    let rent_due = Rent::get()?
+        .minimum_balance(Market::SPACE)
+        .saturating_sub(market_info.lamports());
+    if rent_due > 0 {
+        system_program::transfer(
+            CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                system_program::Transfer {
+                    from: ctx.accounts.authority.to_account_info(),
+                    to: market_info.clone(),
+                },
+            ),
+            rent_due,
+        )?;
+    }
+    market_info.realloc(Market::SPACE, true)?;
+
+    let mut market = Market::try_deserialize(&mut &market_info.try_borrow_data()?[..])?;
+    require!(!market.is_active, ErrorCode::MarketAlreadyActive);
+
+    market.min_order_size = min_order_size;
+    market.order_step_size = order_step_size;
+    market.max_position_size = max_position_size;
+    market.max_open_interest = max_open_interest;
+    market.base_asset_reserve = base_asset_reserve;
+    market.quote_asset_reserve = quote_asset_reserve;
+    market.peg_multiplier = peg_multiplier;
+    market.try_serialize(&mut &mut market_info.try_borrow_mut_data()?[..])?;
+
+    emit!(MarketMigratedEvent {
+        market: market_info.key(),
+        authority: ctx.accounts.authority.key(),
+        old_space: old_space as u64,
+        new_space: Market::SPACE as u64,
+    });
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct MigratePosition<'info> {
+    /// Market of the position, already in the current layout
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+    /// CHECK: Position in a pre-upgrade layout, which no longer deserializes as `Position`.
+    /// Ownership, discriminator and market are checked in the instruction.
+    #[account(mut, owner = crate::ID)]
+    pub position: UncheckedAccount<'info>,
+    /// Pays the rent for the larger account
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+/// Grow a position created before the current layout to `Position::SPACE` and count it in its
+/// market's open interest. Legacy positions cannot be used until they are migrated, and each is
+/// migrated only once, so anyone may run this: the appended fields start zeroed.
+pub fn migrate_position(ctx: Context<MigratePosition>) -> Result<()> {
+    let position_info = ctx.accounts.position.to_account_info();
+    let old_space = position_info.data_len();
+    {
+        let data = position_info.try_borrow_data()?;
+        require!(data.len() >= 72 && data[..8] == Position::DISCRIMINATOR, ErrorCode::InvalidParameter);
+        // The market follows the trader in every layout
+        require!(
+            data[40..72] == ctx.accounts.market.key().to_bytes(),
+            ErrorCode::InvalidPosition
+        );
+    }
+    require!(old_space < Position::SPACE, ErrorCode::InvalidParameter);
+
+    let rent_due = Rent::get()?
+        .minimum_balance(Position::SPACE)
+        .saturating_sub(position_info.lamports());
+    if rent_due > 0 {
+        system_program::transfer(
+            CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                system_program::Transfer {
+                    from: ctx.accounts.payer.to_account_info(),
+                    to: position_info.clone(),
+                },
+            ),
+            rent_due,
+        )?;
+    }
+    position_info.realloc(Position::SPACE, true)?;
+
+    let position = Position::try_deserialize(&mut &position_info.try_borrow_data()?[..])?;
+    if position.is_open {
+        // Existing exposure is counted as it is, without applying the open interest cap
+        let market: &mut Market = &mut ctx.accounts.market;
+        let (open_interest, entry_notional) = match position.side {
+            Side::Long => (&mut market.long_open_interest, &mut market.long_entry_notional),
+            Side::Short => (&mut market.short_open_interest, &mut market.short_entry_notional),
+        };
+        *open_interest = open_interest
+            .checked_add(position.size)
+            .ok_or(ErrorCode::MathOverflow)?;
+        *entry_notional = (position.size as u128)
+            .checked_mul(position.entry_price as u128)
+            .and_then(|notional| entry_notional.checked_add(notional))
+            .ok_or(ErrorCode::MathOverflow)?;
+    }
+
+    emit!(PositionMigratedEvent {
+        market: ctx.accounts.market.key(),
+        position: position_info.key(),
+        trader: position.trader,
+        old_space: old_space as u64,
+        new_space: Position::SPACE as u64,
     });
 
     Ok(())
@@ -1,12 +1,18 @@
 // instructions/mod.rs
+pub mod amm;
 pub mod funding;
+pub mod liquidation;
+pub mod lp;
 pub mod market;
 pub mod order;
-//pub mod position;
+pub mod position;
 pub mod collateral;
 
+pub use amm::*;
 pub use funding::*;
+pub use liquidation::*;
+pub use lp::*;
 pub use market::*;
 pub use order::*;
-//pub use position::*;
+pub use position::*;
 pub use collateral::*;
\ No newline at end of file
@@ -1,9 +1,19 @@
 use crate::{
     errors::ErrorCode,
     events::*,
-    state::{MarginAccount, MarginType, Market, OrderType, Position, Side},
+    instructions::{funding::settle_funding, liquidation::{record_bad_debt, settle_liquidation_loss}, position::reduce_open_position},
+    risk::{
+        account_health, calculate_pnl, cross_liquidation_equity, liquidation_price, load_position_health, margin_requirement,
+        partial_liquidation_size, position_health,
+    },
+    vamm::execute_trade,
+    state::{
+        LiquidationAuction, MarginAccount, MarginType, Market, Order, OrderType, Position,
+        SelfTradePrevention, Side,
+    },
 };
 use anchor_lang::prelude::*;
+use anchor_spl::token::{self, Token, TokenAccount, Transfer};
 use mock_oracle::Oracle;
 
 #[derive(Accounts)]
@@ -26,6 +36,7 @@ pub struct PlaceMarketOrder<'info> {
     #[account(mut)]
     pub trader: Signer<'info>,
     /// CHECK: Oracle account - validated in instruction
+    #[account(address = market.oracle @ ErrorCode::InvalidOracleAccount)]
     pub price_update: UncheckedAccount<'info>,
     pub system_program: Program<'info, System>,
 }
@@ -36,7 +47,7 @@ pub fn place_market_order(
     size: u64,
     leverage: u64,
     position_bump: u8,
-    _uid: u64,
+    uid: u64,
 ) -> Result<()> {
     let market = &mut ctx.accounts.market;
     let position = &mut ctx.accounts.position;
@@ -49,67 +60,18 @@ pub fn place_market_order(
 
     // Validate inputs
     require!(market.is_active, ErrorCode::MarketInactive);
-    require!(leverage <= market.max_leverage, ErrorCode::LeverageTooHigh);
-    require!(size > 0, ErrorCode::InvalidOrderSize);
-
-    // Validate that leverage is compatible with initial margin ratio
-    // For leverage to work, we need: 1/leverage >= initial_margin_ratio/10000
-    // This ensures required collateral >= minimum margin
-    let max_allowed_leverage = 10000u64
-        .checked_div(market.initial_margin_ratio)
-        .ok_or(ErrorCode::MathOverflow)?;
-    require!(leverage <= max_allowed_leverage, ErrorCode::LeverageTooHigh);
+    validate_order_size(market, size)?;
+    validate_leverage(market, size, leverage)?;
 
     // Get current price from oracle
     let oracle_data = ctx.accounts.price_update.try_borrow_data()?;
     let oracle = Oracle::try_deserialize(&mut oracle_data.as_ref())?;
     let current_price = oracle.price;
-    
-    // SIMPLE CALCULATION - No scaling
-    let position_value = size
-        .checked_mul(current_price)
-        .ok_or(ErrorCode::MathOverflow)?;
-
-    let required_collateral = position_value
-        .checked_div(leverage)
-        .ok_or(ErrorCode::MathOverflow)?;
-
-    // Ensure minimum margin requirements are met
-    let min_required_margin = position_value
-        .checked_mul(market.initial_margin_ratio)
-        .ok_or(ErrorCode::MathOverflow)?
-        .checked_div(10000)
-        .ok_or(ErrorCode::MathOverflow)?;
-
-    require!(
-        required_collateral >= min_required_margin,
-        ErrorCode::InsufficientMargin
-    );
-
-    // Check if there's enough available margin based on margin type
-    match margin_account.margin_type {
-        MarginType::Isolated => {
-            // For isolated margin, check if there's enough available margin
-            let available_margin = margin_account.available_margin()?;
-            require!(
-                available_margin >= required_collateral,
-                ErrorCode::InsufficientMargin
-            );
 
-            // Allocate margin to this position
-            margin_account.allocated_margin = margin_account
-                .allocated_margin
-                .checked_add(required_collateral)
-                .ok_or(ErrorCode::MathOverflow)?;
-        }
-        MarginType::Cross => {
-            // For cross margin, check if total margin is sufficient
-            require!(
-                margin_account.collateral >= required_collateral,
-                ErrorCode::InsufficientMargin
-            );
-        }
-    }
+    // Margin is valued at the oracle price; the position opens at the vAMM execution price
+    let required_collateral = calculate_required_collateral(market, size, current_price, leverage)?;
+    reserve_margin(margin_account, required_collateral)?;
+    let execution_price = execute_trade(market, side, size, &oracle, false)?;
 
     // Initialize position
     position.trader = trader.key();
@@ -118,61 +80,228 @@ pub fn place_market_order(
     position.side = side;
     position.size = size;
     position.filled_size = size; // Market orders fill immediately
-    position.price = current_price;
+    position.price = execution_price;
     position.collateral = required_collateral;
-    position.entry_price = current_price;
+    position.entry_price = execution_price;
     position.entry_funding_rate = market.funding_rate;
     position.leverage = leverage;
     position.realized_pnl = 0;
     position.last_funding_payment_time = current_timestamp;
-    position.last_cumulative_funding = 0;
+    position.last_cumulative_funding = market.cumulative_funding_rate;
     position.is_open = true;
     position.created_at = current_timestamp;
+    position.client_order_id = uid;
     position.bump = position_bump;
 
     // Update market state
-    match side {
-        Side::Long => {
-            market.base_asset_reserve = market
-                .base_asset_reserve
-                .checked_add(size)
-                .ok_or(ErrorCode::MathOverflow)?;
-        }
-        Side::Short => {
-            market.base_asset_reserve = market
-                .base_asset_reserve
-                .checked_sub(size)
-                .ok_or(ErrorCode::MathOverflow)?;
-        }
-    }
+    increase_open_interest(market, side, size, execution_price)?;
 
     // Add position to margin account
     margin_account.positions.push(position.key());
 
     // Emit events
+    emit!(PositionOpenedEvent {
+        market: market.key(),
+        position: position.key(),
+        trader: trader.key(),
+        side,
+        size,
+        collateral: required_collateral,
+        entry_price: execution_price,
+        leverage,
+        liquidation_price: liquidation_price(position, market.maintenance_margin_ratio_for(position.size))?,
+        margin_type: margin_account.margin_type,
+    });
+
+    emit!(OrderPlacedEvent {
+        market: market.key(),
+        position: position.key(),
+        trader: trader.key(),
+        client_order_id: uid,
+        side,
+        order_type: OrderType::Market,
+        price: execution_price,
+        size,
+        leverage,
+        timestamp: current_timestamp,
+    });
+
+    emit!(OrderFilledEvent {
+        market: market.key(),
+        position: position.key(),
+        trader: trader.key(),
+        client_order_id: uid,
+        side,
+        price: execution_price,
+        size,
+        filled_size: size,
+        timestamp: current_timestamp,
+    });
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct PlaceNetOrder<'info> {
+    #[account(mut, constraint = market.is_active @ ErrorCode::MarketInactive)]
+    pub market: Account<'info, Market>,
+    #[account(
+        init_if_needed,
+        payer = trader,
+        space = Position::SPACE,
+        seeds = [b"net_position", market.key().as_ref(), margin_account.key().as_ref()],
+        bump
+    )]
+    pub position: Account<'info, Position>,
+    #[account(
+        mut,
+        constraint = margin_account.owner == trader.key() @ ErrorCode::Unauthorized,
+        constraint = margin_account.margin_type == MarginType::Cross @ ErrorCode::InvalidParameter,
+    )]
+    pub margin_account: Account<'info, MarginAccount>,
+    #[account(mut)]
+    pub trader: Signer<'info>,
+    /// CHECK: Oracle account - validated in instruction
+    #[account(address = market.oracle @ ErrorCode::InvalidOracleAccount)]
+    pub price_update: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+/// Trade against the single netted position a cross margin account holds in a market.
+/// Same-side trades add to it; opposite-side trades reduce, close or flip it.
+pub fn place_net_order(
+    ctx: Context<PlaceNetOrder>,
+    side: Side,
+    size: u64,
+    leverage: u64,
+    client_order_id: u64,
+) -> Result<()> {
+    let market = &mut ctx.accounts.market;
+    let position = &mut ctx.accounts.position;
+    let margin_account = &mut ctx.accounts.margin_account;
+    let trader = &ctx.accounts.trader;
+    let current_timestamp = Clock::get()?.unix_timestamp;
+
+    validate_order_size(market, size)?;
+
+    // Get current price from oracle
+    let oracle_data = ctx.accounts.price_update.try_borrow_data()?;
+    let oracle = Oracle::try_deserialize(&mut oracle_data.as_ref())?;
+    let current_price = oracle.price;
+
+    // The whole order fills against the vAMM at one price, whether it reduces or opens exposure.
+    // Only an order that does not flip the position is a reducing fill.
+    let reducing = position.is_open && position.side != side && size <= position.size;
+    let execution_price = execute_trade(market, side, size, &oracle, reducing)?;
+
     emit!(OrderPlacedEvent {
         market: market.key(),
         position: position.key(),
         trader: trader.key(),
+        client_order_id,
         side,
         order_type: OrderType::Market,
-        price: current_price,
+        price: execution_price,
         size,
         leverage,
         timestamp: current_timestamp,
     });
 
+    // Size left to open on `side` once any opposite exposure has been netted out
+    let mut open_size = size;
+
+    if position.is_open && position.side == side {
+        // Collateral is reserved at the position's leverage, so adds may not re-margin it
+        require!(leverage == position.leverage, ErrorCode::LeverageMismatch);
+        validate_position_size(market, position.size, size)?;
+        let new_size = position.size
+            .checked_add(size)
+            .ok_or(ErrorCode::MathOverflow)?;
+        validate_leverage(market, new_size, leverage)?;
+        settle_funding(market, position, margin_account)?;
+
+        let required_collateral = calculate_required_collateral(market, size, current_price, leverage)?;
+        reserve_margin(margin_account, required_collateral)?;
+        add_to_position(position, size, execution_price, required_collateral)?;
+        open_size = 0;
+
+        increase_open_interest(market, side, size, execution_price)?;
+    } else if position.is_open {
+        if size < position.size {
+            validate_remaining_size(market, position.size - size)?;
+            reduce_open_position(market, position, margin_account, size, execution_price)?;
+            open_size = 0;
+        } else {
+            // Close the existing exposure; anything beyond it flips the position
+            open_size = size - position.size;
+            if open_size > 0 {
+                validate_order_size(market, open_size)?;
+            }
+            close_position(market, position, margin_account, execution_price)?;
+            position.is_open = false;
+        }
+    }
+
+    if open_size > 0 {
+        validate_leverage(market, open_size, leverage)?;
+        let required_collateral =
+            calculate_required_collateral(market, open_size, current_price, leverage)?;
+        reserve_margin(margin_account, required_collateral)?;
+
+        position.trader = trader.key();
+        position.market = market.key();
+        position.order_type = OrderType::Market;
+        position.side = side;
+        position.size = open_size;
+        position.filled_size = open_size;
+        position.price = execution_price;
+        position.collateral = required_collateral;
+        position.entry_price = execution_price;
+        position.entry_funding_rate = market.funding_rate;
+        position.leverage = leverage;
+        position.realized_pnl = 0;
+        position.last_funding_payment_time = current_timestamp;
+        position.last_cumulative_funding = market.cumulative_funding_rate;
+        position.is_open = true;
+        position.created_at = current_timestamp;
+        position.client_order_id = client_order_id;
+        position.bump = ctx.bumps.position;
+
+        increase_open_interest(market, side, open_size, execution_price)?;
+
+        margin_account.positions.push(position.key());
+
+        emit!(PositionOpenedEvent {
+            market: market.key(),
+            position: position.key(),
+            trader: trader.key(),
+            side,
+            size: open_size,
+            collateral: required_collateral,
+            entry_price: execution_price,
+            leverage,
+            liquidation_price: liquidation_price(position, market.maintenance_margin_ratio_for(position.size))?,
+            margin_type: margin_account.margin_type,
+        });
+    }
+
     emit!(OrderFilledEvent {
         market: market.key(),
         position: position.key(),
         trader: trader.key(),
+        client_order_id,
         side,
-        price: current_price,
+        price: execution_price,
         size,
         filled_size: size,
         timestamp: current_timestamp,
     });
 
+    // A position netted down to zero gives its rent back
+    if !position.is_open {
+        position.close(trader.to_account_info())?;
+    }
+
     Ok(())
 }
 
@@ -195,6 +324,7 @@ pub struct CloseMarketOrder<'info> {
     #[account(mut)]
     pub trader: Signer<'info>,
     /// CHECK: Oracle account - validated in instruction
+    #[account(address = market.oracle @ ErrorCode::InvalidOracleAccount)]
     pub price_update: UncheckedAccount<'info>,
 }
 
@@ -202,48 +332,30 @@ pub fn close_market_order(ctx: Context<CloseMarketOrder>) -> Result<()> {
     let market = &mut ctx.accounts.market;
     let position = &mut ctx.accounts.position;
     let margin_account = &mut ctx.accounts.margin_account;
-    let trader = &ctx.accounts.trader;
 
     // Get current price from oracle
     let oracle_data = ctx.accounts.price_update.try_borrow_data()?;
     let oracle = Oracle::try_deserialize(&mut oracle_data.as_ref())?;
-    let current_price = oracle.price;
 
-    // Calculate PnL
-    msg!("Starting PnL calculation...");
-    msg!("Position size: {}", position.size);
-    msg!("Entry price: {}", position.entry_price);
-    msg!("Current price: {}", current_price);
-
-    // Convert values to i64 for PnL calculation
-    let entry_price: i64 = position.entry_price as i64;
-    let exit_price: i64 = current_price as i64;
-    let size: i64 = position.size as i64;
-
-    // Calculate PnL based on position side
-    let pnl = match position.side {
-        Side::Long => {
-            msg!("Calculating long PnL: (exit_price - entry_price) * size");
-            let price_diff = exit_price.checked_sub(entry_price).ok_or_else(|| {
-                msg!("Overflow in price difference calculation for long");
-                ErrorCode::MathOverflow
-            })?;
-            price_diff.checked_mul(size)
-        }
-        Side::Short => {
-            msg!("Calculating short PnL: (entry_price - exit_price) * size");
-            let price_diff = entry_price.checked_sub(exit_price).ok_or_else(|| {
-                msg!("Overflow in price difference calculation for short");
-                ErrorCode::MathOverflow
-            })?;
-            price_diff.checked_mul(size)
-        }
-    }
-    .ok_or_else(|| {
-        msg!("Overflow in PnL calculation");
-        ErrorCode::MathOverflow
-    })?;
-    msg!("Final PnL: {}", pnl);
+    let exit_price = execute_trade(market, position.side.opposite(), position.size, &oracle, true)?;
+    close_position(market, position, margin_account, exit_price)?;
+
+    Ok(())
+}
+
+/// Close a whole position at `current_price`, realizing its PnL on the margin account.
+/// The caller is responsible for closing the position account itself.
+pub(crate) fn close_position(
+    market: &mut Account<Market>,
+    position: &mut Account<Position>,
+    margin_account: &mut Account<MarginAccount>,
+    current_price: u64,
+) -> Result<i64> {
+    // Funding accrued while the position was open is realized first
+    settle_funding(market, position, margin_account)?;
+
+    let pnl = calculate_pnl(position.side, position.entry_price, current_price, position.size)?;
+    let pnl = record_trader_pnl(market, pnl)?;
 
     // Store values before account is closed
     let position_side = position.side;
@@ -253,110 +365,34 @@ pub fn close_market_order(ctx: Context<CloseMarketOrder>) -> Result<()> {
     let position_key = position.key();
 
     // Update market state
-    match position_side {
-        Side::Long => {
-            msg!("Updating market state for long position");
-            market.base_asset_reserve = market
-                .base_asset_reserve
-                .checked_sub(position_size)
-                .ok_or_else(|| {
-                    msg!(
-                        "Overflow in base_asset_reserve subtraction: {} - {}",
-                        market.base_asset_reserve,
-                        position_size
-                    );
-                    ErrorCode::MathOverflow
-                })?;
-        }
-        Side::Short => {
-            msg!("Updating market state for short position");
-            market.base_asset_reserve = market
-                .base_asset_reserve
-                .checked_add(position_size)
-                .ok_or_else(|| {
-                    msg!(
-                        "Overflow in base_asset_reserve addition: {} + {}",
-                        market.base_asset_reserve,
-                        position_size
-                    );
-                    ErrorCode::MathOverflow
-                })?;
-        }
+    decrease_open_interest(market, position_side, position_size, position_entry_price)?;
+
+    // Isolated collateral backing the position is no longer allocated
+    if margin_account.margin_type == MarginType::Isolated {
+        margin_account.allocated_margin = margin_account
+            .allocated_margin
+            .checked_sub(position_collateral)
+            .ok_or(ErrorCode::MathOverflow)?;
     }
 
-    // Update margin account based on margin type
-    match margin_account.margin_type {
-        MarginType::Isolated => {
-            msg!("Updating isolated margin account");
-            margin_account.allocated_margin = margin_account
-                .allocated_margin
-                .checked_sub(position_collateral)
-                .ok_or_else(|| {
-                    msg!(
-                        "Overflow in allocated_margin subtraction: {} - {}",
-                        margin_account.allocated_margin,
-                        position_collateral
-                    );
-                    ErrorCode::MathOverflow
-                })?;
-
-            if pnl > 0 {
-                msg!("Adding positive PnL to collateral: {}", pnl);
-                margin_account.collateral = margin_account
-                    .collateral
-                    .checked_add(pnl as u64)
-                    .ok_or_else(|| {
-                        msg!(
-                            "Overflow in collateral addition: {} + {}",
-                            margin_account.collateral,
-                            pnl
-                        );
-                        ErrorCode::MathOverflow
-                    })?;
-            } else {
-                msg!("Subtracting negative PnL from collateral: {}", -pnl);
-                margin_account.collateral = margin_account
-                    .collateral
-                    .checked_sub((-pnl) as u64)
-                    .ok_or_else(|| {
-                        msg!(
-                            "Overflow in collateral subtraction: {} - {}",
-                            margin_account.collateral,
-                            -pnl
-                        );
-                        ErrorCode::MathOverflow
-                    })?;
-            }
-        }
-        MarginType::Cross => {
-            msg!("Updating cross margin account");
-            if pnl > 0 {
-                msg!("Adding positive PnL to collateral: {}", pnl);
-                margin_account.collateral = margin_account
-                    .collateral
-                    .checked_add(pnl as u64)
-                    .ok_or_else(|| {
-                        msg!(
-                            "Overflow in collateral addition: {} + {}",
-                            margin_account.collateral,
-                            pnl
-                        );
-                        ErrorCode::MathOverflow
-                    })?;
-            } else {
-                msg!("Subtracting negative PnL from collateral: {}", -pnl);
-                margin_account.collateral = margin_account
-                    .collateral
-                    .checked_sub((-pnl) as u64)
-                    .ok_or_else(|| {
-                        msg!(
-                            "Overflow in collateral subtraction: {} - {}",
-                            margin_account.collateral,
-                            -pnl
-                        );
-                        ErrorCode::MathOverflow
-                    })?;
-            }
+    if pnl > 0 {
+        margin_account.collateral = margin_account
+            .collateral
+            .checked_add(pnl as u64)
+            .ok_or(ErrorCode::MathOverflow)?;
+    } else {
+        // A loss is paid from the collateral backing the position: its own collateral in
+        // isolated mode, the whole account balance in cross mode. Anything beyond is bad debt.
+        let loss = pnl.unsigned_abs();
+        let available = match margin_account.margin_type {
+            MarginType::Isolated => position_collateral.min(margin_account.collateral),
+            MarginType::Cross => margin_account.collateral,
+        };
+        let paid = loss.min(available);
+        margin_account.collateral -= paid;
+
+        if loss > paid {
+            record_bad_debt(market, position, loss - paid, position.size, current_price)?;
         }
     }
 
@@ -373,7 +409,7 @@ pub fn close_market_order(ctx: Context<CloseMarketOrder>) -> Result<()> {
     emit!(PositionClosedEvent {
         market: market.key(),
         position: position_key,
-        trader: trader.key(),
+        trader: position.trader,
         side: position_side,
         size: position_size,
         collateral: position_collateral,
@@ -383,7 +419,7 @@ pub fn close_market_order(ctx: Context<CloseMarketOrder>) -> Result<()> {
         margin_type: margin_account.margin_type,
     });
 
-    Ok(())
+    Ok(pnl)
 }
 
 #[derive(Accounts)]
@@ -394,239 +430,183 @@ pub struct LiquidateMarketOrder<'info> {
         mut,
         has_one = market,
         constraint = position.is_open @ ErrorCode::PositionClosed,
-        close = liquidator  // This closes the position account and sends rent to liquidator
     )]
     pub position: Account<'info, Position>,
     #[account(
         mut,
         constraint = margin_account.owner == position.trader @ ErrorCode::Unauthorized,
+        constraint = margin_account.positions.contains(&position.key()) @ ErrorCode::InvalidPosition,
     )]
     pub margin_account: Account<'info, MarginAccount>,
     #[account(mut)]
     pub liquidator: Signer<'info>,
+    #[account(
+        mut,
+        constraint = liquidator_token_account.owner == liquidator.key() @ ErrorCode::Unauthorized,
+        constraint = liquidator_token_account.mint == market_vault.mint @ ErrorCode::InvalidCollateralMint,
+    )]
+    pub liquidator_token_account: Account<'info, TokenAccount>,
+    /// Market whose vault holds the account's collateral and pays the liquidator fee
+    #[account(address = margin_account.collateral_market @ ErrorCode::InvalidVault)]
+    pub collateral_market: Account<'info, Market>,
+    #[account(
+        mut,
+        constraint = market_vault.key() == collateral_market.vault @ ErrorCode::InvalidVault,
+    )]
+    pub market_vault: Account<'info, TokenAccount>,
     /// CHECK: Oracle account - validated in instruction
+    #[account(address = market.oracle @ ErrorCode::InvalidOracleAccount)]
     pub price_update: UncheckedAccount<'info>,
+    pub token_program: Program<'info, Token>,
+    /// Expired auction of the position, required for positions above the auction size threshold
+    #[account(has_one = position)]
+    pub auction: Option<Account<'info, LiquidationAuction>>,
 }
 
-pub fn liquidate_market_order(ctx: Context<LiquidateMarketOrder>) -> Result<()> {
+pub fn liquidate_market_order<'info>(
+    ctx: Context<'_, '_, 'info, 'info, LiquidateMarketOrder<'info>>,
+) -> Result<()> {
     let market = &mut ctx.accounts.market;
     let position = &mut ctx.accounts.position;
     let margin_account = &mut ctx.accounts.margin_account;
-    let _liquidator = &ctx.accounts.liquidator;
+    let liquidator = &ctx.accounts.liquidator;
 
     // Get current price from oracle
     let oracle_data = ctx.accounts.price_update.try_borrow_data()?;
     let oracle = Oracle::try_deserialize(&mut oracle_data.as_ref())?;
     let current_price = oracle.price;
-    msg!("Current oracle price: {}", current_price);
+
+    // Funding is settled first so the health check sees the up-to-date collateral
+    settle_funding(market, position, margin_account)?;
 
     // Calculate position value and equity
-    msg!("Position size: {}", position.size);
-    let position_value = position.size.checked_mul(current_price).ok_or_else(|| {
-        msg!(
-            "Overflow in position_value calculation: {} * {}",
-            position.size,
-            current_price
-        );
-        ErrorCode::MathOverflow
-    })?;
-    msg!("Position value: {}", position_value);
+    let health = position_health(market, position, current_price)?;
 
-    let entry_value = position
-        .size
-        .checked_mul(position.entry_price)
-        .ok_or_else(|| {
-            msg!(
-                "Overflow in entry_value calculation: {} * {}",
-                position.size,
-                position.entry_price
-            );
-            ErrorCode::MathOverflow
-        })?;
-    msg!("Entry value: {}", entry_value);
-
-    let position_value_i64 = position_value as i64;
-    let entry_value_i64 = entry_value as i64;
-    msg!("Position value (i64): {}", position_value_i64);
-    msg!("Entry value (i64): {}", entry_value_i64);
-
-    let unrealized_pnl = match position.side {
-        Side::Long => position_value_i64.checked_sub(entry_value_i64),
-        Side::Short => entry_value_i64.checked_sub(position_value_i64),
-    }
-    .ok_or_else(|| {
-        msg!(
-            "Overflow in unrealized_pnl calculation for {:?} position",
-            position.side
-        );
-        ErrorCode::MathOverflow
-    })?;
-    msg!("Unrealized PnL: {}", unrealized_pnl);
+    // Isolated positions are liquidated against their own collateral. Cross accounts are
+    // liquidated at the account level, against the equity of all of their positions, which
+    // are passed via remaining accounts as (position, market, oracle) triples.
+    let (is_liquidatable, equity) = match margin_account.margin_type {
+        MarginType::Isolated => (health.equity < health.maintenance_margin as i64, health.equity),
+        MarginType::Cross => {
+            let positions = load_position_health(margin_account, ctx.remaining_accounts)?;
+            let account = account_health(margin_account, positions)?;
 
-    let equity = if unrealized_pnl > 0 {
-        position.collateral.checked_add(unrealized_pnl as u64)
-    } else {
-        // For negative PnL, we need to ensure we don't underflow
-        if (-unrealized_pnl) as u64 > position.collateral {
-            // If loss is greater than collateral, equity is 0
-            Some(0)
-        } else {
-            position.collateral.checked_sub((-unrealized_pnl) as u64)
+            // Equity left for this position once the other positions' liquidation targets are met
+            let equity = cross_liquidation_equity(&account, position.key())?;
+            (account.is_liquidatable, equity)
         }
-    }
-    .ok_or_else(|| {
-        msg!(
-            "Overflow in equity calculation: collateral {} +/- PnL {}",
-            position.collateral,
-            unrealized_pnl
-        );
-        ErrorCode::MathOverflow
-    })?;
-    msg!("Position equity: {}", equity);
+    };
 
     // Check if position is liquidatable
-    let maintenance_margin = position_value
-        .checked_mul(market.maintenance_margin_ratio)
-        .ok_or_else(|| {
-            msg!(
-                "Overflow in maintenance_margin calculation: {} * {}",
-                position_value,
-                market.maintenance_margin_ratio
-            );
-            ErrorCode::MathOverflow
-        })?
-        .checked_div(10000)
-        .ok_or_else(|| {
-            msg!(
-                "Overflow in maintenance_margin division: {} / 10000",
-                position_value * market.maintenance_margin_ratio
-            );
-            ErrorCode::MathOverflow
-        })?;
-    msg!("Maintenance margin: {}", maintenance_margin);
+    require!(is_liquidatable, ErrorCode::PositionNotLiquidatable);
 
-    require!(
-        equity < maintenance_margin,
-        ErrorCode::PositionNotLiquidatable
-    );
+    // Large positions are auctioned first and only liquidated here once their auction has ended
+    if market.auction_size_threshold > 0 && position.size >= market.auction_size_threshold {
+        let auction = ctx.accounts.auction.as_ref().ok_or(ErrorCode::LiquidationAuctionRequired)?;
+        let elapsed = Clock::get()?.slot.saturating_sub(auction.start_slot);
+        require!(elapsed > market.auction_duration_slots, ErrorCode::LiquidationAuctionRequired);
+    }
 
-    // Calculate liquidation fees
-    let liquidation_fee = position_value
-        .checked_mul(market.liquidation_fee_ratio)
-        .ok_or_else(|| {
-            msg!(
-                "Overflow in liquidation fee calculation: {} * {}",
-                position_value,
-                market.liquidation_fee_ratio
-            );
-            ErrorCode::MathOverflow
-        })?;
-    msg!("Liquidation fee: {}", liquidation_fee);
-
-    let liquidator_fee = liquidation_fee.checked_div(2).ok_or_else(|| {
-        msg!(
-            "Overflow in liquidator fee calculation: {} / 2",
-            liquidation_fee
-        );
-        ErrorCode::MathOverflow
-    })?;
-    msg!("Liquidator fee: {}", liquidator_fee);
-
-    let insurance_fund_fee = liquidation_fee.checked_sub(liquidator_fee).ok_or_else(|| {
-        msg!(
-            "Overflow in insurance fund fee calculation: {} - {}",
-            liquidation_fee,
-            liquidator_fee
-        );
-        ErrorCode::MathOverflow
-    })?;
-    msg!("Insurance fund fee: {}", insurance_fund_fee);
+    // Only close as much as is needed to bring the position back above maintenance margin
+    let liquidation_size = partial_liquidation_size(market, position, equity, current_price)?;
+    let remaining_size = position.size
+        .checked_sub(liquidation_size)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    // Calculate liquidation fees on the liquidated part, capped at the position's remaining
+    // equity so fees are never paid out of other users' collateral
+    let liquidation_fee =
+        margin_requirement(liquidation_size, current_price, market.liquidation_fee_ratio_for(position.size))?
+            .min(equity.max(0) as u64);
+    let liquidator_fee = liquidation_fee / 2;
+    let insurance_fund_fee = liquidation_fee - liquidator_fee;
 
     // Update insurance fund
     market.insurance_fund = market
         .insurance_fund
         .checked_add(insurance_fund_fee)
-        .ok_or_else(|| {
-            msg!(
-                "Overflow in insurance_fund update: {} + {}",
-                market.insurance_fund,
-                insurance_fund_fee
-            );
-            ErrorCode::MathOverflow
-        })?;
-    msg!("Updated insurance fund: {}", market.insurance_fund);
+        .ok_or(ErrorCode::MathOverflow)?;
 
     // Store values before account is closed
     let position_side = position.side;
-    let position_size = position.size;
     let position_collateral = position.collateral;
     let position_key = position.key();
 
-    // Update market state
-    match position_side {
-        Side::Long => {
-            msg!("Updating market state for long position");
-            market.base_asset_reserve = market
-                .base_asset_reserve
-                .checked_sub(position_size)
-                .ok_or_else(|| {
-                    msg!(
-                        "Overflow in base_asset_reserve subtraction: {} - {}",
-                        market.base_asset_reserve,
-                        position_size
-                    );
-                    ErrorCode::MathOverflow
-                })?;
+    // The liquidated size is closed against the vAMM
+    let exit_price = execute_trade(market, position_side.opposite(), liquidation_size, &oracle, true)?;
+    let pnl = calculate_pnl(position_side, position.entry_price, exit_price, liquidation_size)?;
+    let pnl = record_trader_pnl(market, pnl)?;
+
+    if remaining_size > 0 {
+        let new_collateral = match margin_account.margin_type {
+            // The realized loss and the fee come out of the position's collateral, which
+            // leaves the remaining size with a healthier margin ratio than before
+            MarginType::Isolated => (position_collateral as i64)
+                .checked_add(pnl)
+                .and_then(|collateral| collateral.checked_sub(liquidation_fee as i64))
+                .and_then(|collateral| u64::try_from(collateral).ok())
+                .ok_or(ErrorCode::MathOverflow)?,
+            // Cross positions are backed by the whole account, so their collateral shrinks pro-rata
+            MarginType::Cross => (position_collateral as u128)
+                .checked_mul(remaining_size as u128)
+                .and_then(|collateral| collateral.checked_div(position.size as u128))
+                .ok_or(ErrorCode::MathOverflow)? as u64,
+        };
+
+        if margin_account.margin_type == MarginType::Isolated {
+            release_margin(margin_account, position_collateral)?;
+            margin_account.allocated_margin = margin_account
+                .allocated_margin
+                .checked_add(new_collateral)
+                .ok_or(ErrorCode::MathOverflow)?;
         }
-        Side::Short => {
-            msg!("Updating market state for short position");
-            market.base_asset_reserve = market
-                .base_asset_reserve
-                .checked_add(position_size)
-                .ok_or_else(|| {
-                    msg!(
-                        "Overflow in base_asset_reserve addition: {} + {}",
-                        market.base_asset_reserve,
-                        position_size
-                    );
-                    ErrorCode::MathOverflow
-                })?;
+        let deficit = realize_pnl(margin_account, pnl)?;
+        if deficit > 0 {
+            record_bad_debt(market, position, deficit, liquidation_size, exit_price)?;
         }
-    }
-    msg!("Updated base asset reserve: {}", market.base_asset_reserve);
+        margin_account.collateral = margin_account
+            .collateral
+            .checked_sub(liquidation_fee)
+            .ok_or(ErrorCode::MathOverflow)?;
 
-    // Update margin account state
-    // For liquidated positions, we set collateral to 0 since the position is underwater
-    margin_account.collateral = 0;
-    margin_account.allocated_margin = margin_account
-        .allocated_margin
-        .checked_sub(position_collateral)
-        .ok_or_else(|| {
-            msg!(
-                "Overflow in allocated_margin subtraction: {} - {}",
-                margin_account.allocated_margin,
-                position_collateral
-            );
-            ErrorCode::MathOverflow
-        })?;
-    msg!(
-        "Updated margin account collateral: {}",
-        margin_account.collateral
-    );
-    msg!(
-        "Updated margin account allocated margin: {}",
-        margin_account.allocated_margin
-    );
+        position.size = remaining_size;
+        position.filled_size = remaining_size;
+        position.collateral = new_collateral;
+        position.realized_pnl = position.realized_pnl
+            .checked_add(pnl)
+            .ok_or(ErrorCode::MathOverflow)?;
+    } else {
+        settle_liquidation_loss(market, margin_account, position, pnl, exit_price, liquidation_fee)?;
 
-    // Remove position from margin account
-    margin_account.positions.retain(|&x| x != position_key);
-    msg!("Removed position from margin account");
+        // Remove position from margin account
+        margin_account.positions.retain(|&x| x != position_key);
+
+        position.is_open = false;
+    }
 
-    // Close position account
-    position.is_open = false;
-    msg!("Closed position account");
+    // Update market state
+    decrease_open_interest(market, position_side, liquidation_size, position.entry_price)?;
 
-    // TODO: Transfer liquidator_fee to liquidator
+    // Transfer liquidator fee from the vault holding the account's collateral to liquidator
+    if liquidator_fee > 0 {
+        let collateral_market = &ctx.accounts.collateral_market;
+        let seeds = &[
+            b"market".as_ref(),
+            collateral_market.market_symbol.as_bytes(),
+            &[collateral_market.bump],
+        ];
+        let signer = &[&seeds[..]];
+        let transfer_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.market_vault.to_account_info(),
+                to: ctx.accounts.liquidator_token_account.to_account_info(),
+                authority: collateral_market.to_account_info(),
+            },
+            signer,
+        );
+        token::transfer(transfer_ctx, liquidator_fee)?;
+    }
 
     // Emit events
     emit!(PositionLiquidatedEvent {
@@ -634,15 +614,1491 @@ pub fn liquidate_market_order(ctx: Context<LiquidateMarketOrder>) -> Result<()>
         position: position_key,
         trader: position.trader,
         side: position_side,
-        size: position_size,
+        size: liquidation_size,
+        remaining_size,
         collateral: position_collateral,
         entry_price: position.entry_price,
-        exit_price: current_price,
-        liquidator: _liquidator.key(),
+        exit_price,
+        liquidator: liquidator.key(),
         liquidation_fee,
         liquidator_fee,
         insurance_fund_fee,
     });
 
+    // Close position account once it has been fully liquidated
+    if remaining_size == 0 {
+        position.close(liquidator.to_account_info())?;
+    }
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+#[instruction(
+    side: Side,
+    price: u64,
+    size: u64,
+    leverage: u64,
+    client_order_id: u64,
+    stp_mode: SelfTradePrevention,
+    stp_group: u64
+)]
+pub struct PlaceLimitOrder<'info> {
+    #[account(constraint = market.is_active @ ErrorCode::MarketInactive)]
+    pub market: Account<'info, Market>,
+    #[account(
+        init,
+        payer = trader,
+        space = Order::SPACE,
+        seeds = [b"order", market.key().as_ref(), trader.key().as_ref(), &client_order_id.to_le_bytes()],
+        bump
+    )]
+    pub order: Account<'info, Order>,
+    #[account(
+        mut,
+        constraint = margin_account.owner == trader.key() @ ErrorCode::Unauthorized,
+    )]
+    pub margin_account: Account<'info, MarginAccount>,
+    #[account(mut)]
+    pub trader: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn place_limit_order(
+    ctx: Context<PlaceLimitOrder>,
+    side: Side,
+    price: u64,
+    size: u64,
+    leverage: u64,
+    client_order_id: u64,
+    stp_mode: SelfTradePrevention,
+    stp_group: u64,
+) -> Result<()> {
+    let order = &mut ctx.accounts.order;
+
+    open_limit_order(
+        order,
+        &ctx.accounts.market,
+        &mut ctx.accounts.margin_account,
+        ctx.accounts.trader.key(),
+        side,
+        price,
+        size,
+        leverage,
+    )?;
+    order.client_order_id = client_order_id;
+    order.stp_mode = stp_mode;
+    order.stp_group = stp_group;
+    order.bump = ctx.bumps.order;
+
+    emit_order_placed(order, ctx.program_id);
+
     Ok(())
 }
+
+#[derive(Accounts)]
+#[instruction(
+    side: Side,
+    price: u64,
+    size: u64,
+    leverage: u64,
+    client_order_id: u64
+)]
+pub struct PlaceBracketOrder<'info> {
+    #[account(constraint = market.is_active @ ErrorCode::MarketInactive)]
+    pub market: Account<'info, Market>,
+    #[account(
+        init,
+        payer = trader,
+        space = Order::SPACE,
+        seeds = [b"order", market.key().as_ref(), trader.key().as_ref(), &client_order_id.to_le_bytes()],
+        bump
+    )]
+    pub entry_order: Account<'info, Order>,
+    #[account(
+        init,
+        payer = trader,
+        space = Order::SPACE,
+        seeds = [b"take_profit", entry_order.key().as_ref()],
+        bump
+    )]
+    pub take_profit_order: Account<'info, Order>,
+    #[account(
+        init,
+        payer = trader,
+        space = Order::SPACE,
+        seeds = [b"stop_loss", entry_order.key().as_ref()],
+        bump
+    )]
+    pub stop_loss_order: Account<'info, Order>,
+    #[account(
+        mut,
+        constraint = margin_account.owner == trader.key() @ ErrorCode::Unauthorized,
+    )]
+    pub margin_account: Account<'info, MarginAccount>,
+    #[account(mut)]
+    pub trader: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+/// Place a limit entry order with attached take-profit and stop-loss legs.
+/// The legs only become executable once the entry has filled, and executing
+/// one of them cancels the other.
+#[allow(clippy::too_many_arguments)]
+pub fn place_bracket_order(
+    ctx: Context<PlaceBracketOrder>,
+    side: Side,
+    price: u64,
+    size: u64,
+    leverage: u64,
+    client_order_id: u64,
+    take_profit_price: u64,
+    stop_loss_price: u64,
+    stp_mode: SelfTradePrevention,
+    stp_group: u64,
+) -> Result<()> {
+    // Take-profit must sit on the profitable side of the entry and stop-loss on the losing side
+    let valid_prices = match side {
+        Side::Long => stop_loss_price < price && price < take_profit_price,
+        Side::Short => take_profit_price < price && price < stop_loss_price,
+    };
+    require!(valid_prices && stop_loss_price > 0, ErrorCode::InvalidOrderPrice);
+
+    let entry_order = &mut ctx.accounts.entry_order;
+    open_limit_order(
+        entry_order,
+        &ctx.accounts.market,
+        &mut ctx.accounts.margin_account,
+        ctx.accounts.trader.key(),
+        side,
+        price,
+        size,
+        leverage,
+    )?;
+    entry_order.client_order_id = client_order_id;
+    entry_order.stp_mode = stp_mode;
+    entry_order.stp_group = stp_group;
+    entry_order.bump = ctx.bumps.entry_order;
+    entry_order.linked_order = ctx.accounts.take_profit_order.key();
+
+    // Protective legs close the position, so they trade the opposite side and reserve no collateral
+    let exit_side = match side {
+        Side::Long => Side::Short,
+        Side::Short => Side::Long,
+    };
+    let take_profit_key = ctx.accounts.take_profit_order.key();
+    let stop_loss_key = ctx.accounts.stop_loss_order.key();
+
+    ctx.accounts.take_profit_order.set_inner(Order {
+        side: exit_side,
+        order_type: OrderType::TakeProfit,
+        price: take_profit_price,
+        collateral: 0,
+        trigger_price: take_profit_price,
+        parent_order: entry_order.key(),
+        linked_order: stop_loss_key,
+        bump: ctx.bumps.take_profit_order,
+        ..entry_order.clone().into_inner()
+    });
+    ctx.accounts.stop_loss_order.set_inner(Order {
+        side: exit_side,
+        order_type: OrderType::StopLoss,
+        price: stop_loss_price,
+        collateral: 0,
+        trigger_price: stop_loss_price,
+        parent_order: entry_order.key(),
+        linked_order: take_profit_key,
+        bump: ctx.bumps.stop_loss_order,
+        ..entry_order.clone().into_inner()
+    });
+
+    emit_order_placed(entry_order, ctx.program_id);
+    emit_order_placed(&ctx.accounts.take_profit_order, ctx.program_id);
+    emit_order_placed(&ctx.accounts.stop_loss_order, ctx.program_id);
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct ExecuteTriggerOrder<'info> {
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+    #[account(
+        mut,
+        has_one = market,
+        has_one = margin_account,
+        constraint = order.is_active @ ErrorCode::OrderNotActive,
+        constraint = matches!(order.order_type, OrderType::TakeProfit | OrderType::StopLoss) @ ErrorCode::InvalidParameter,
+        close = trader  // This closes the executed leg and sends rent to trader
+    )]
+    pub order: Account<'info, Order>,
+    #[account(
+        mut,
+        address = order.linked_order,
+        close = trader  // One-cancels-other: the remaining leg is closed as well
+    )]
+    pub linked_order: Account<'info, Order>,
+    #[account(
+        mut,
+        address = order.parent_order,
+        constraint = entry_order.filled_size > 0 @ ErrorCode::BracketNotActive,
+        close = trader  // The bracket is done once one of its legs executes
+    )]
+    pub entry_order: Account<'info, Order>,
+    #[account(
+        mut,
+        seeds = [b"order_position", market.key().as_ref(), order.trader.as_ref(), &order.client_order_id.to_le_bytes()],
+        bump = position.bump,
+        constraint = position.is_open @ ErrorCode::PositionClosed,
+        close = trader  // This closes the position account and sends rent to trader
+    )]
+    pub position: Account<'info, Position>,
+    #[account(
+        mut,
+        constraint = margin_account.positions.contains(&position.key()) @ ErrorCode::InvalidPosition,
+    )]
+    pub margin_account: Account<'info, MarginAccount>,
+    /// CHECK: Receives the rent of the closed accounts
+    #[account(mut, address = order.trader)]
+    pub trader: UncheckedAccount<'info>,
+    /// Permissionless keeper that executes the trigger
+    pub keeper: Signer<'info>,
+    /// CHECK: Oracle account - validated in instruction
+    #[account(address = market.oracle @ ErrorCode::InvalidOracleAccount)]
+    pub price_update: UncheckedAccount<'info>,
+}
+
+/// Execute a take-profit or stop-loss leg once the oracle price crosses its trigger
+pub fn execute_trigger_order(ctx: Context<ExecuteTriggerOrder>) -> Result<()> {
+    let market = &mut ctx.accounts.market;
+    let order = &mut ctx.accounts.order;
+    let position = &mut ctx.accounts.position;
+    let margin_account = &mut ctx.accounts.margin_account;
+
+    // A partially filled entry stops filling once the position it opened is closed
+    if ctx.accounts.entry_order.is_active {
+        cancel_order(&mut ctx.accounts.entry_order, margin_account)?;
+    }
+
+    // Get current price from oracle
+    let oracle_data = ctx.accounts.price_update.try_borrow_data()?;
+    let oracle = Oracle::try_deserialize(&mut oracle_data.as_ref())?;
+    let current_price = oracle.price;
+
+    // Legs trade the exit side: a short leg protects a long and vice versa
+    let triggered = match (order.order_type, order.side) {
+        (OrderType::TakeProfit, Side::Short) | (OrderType::StopLoss, Side::Long) => {
+            current_price >= order.trigger_price
+        }
+        _ => current_price <= order.trigger_price,
+    };
+    require!(triggered, ErrorCode::TriggerNotReached);
+
+    let position_size = position.size;
+    let exit_price = execute_trade(market, position.side.opposite(), position_size, &oracle, true)?;
+    close_position(market, position, margin_account, exit_price)?;
+
+    order.filled_size = position_size;
+    order.is_active = false;
+
+    emit!(OrderFilledEvent {
+        market: market.key(),
+        position: position.key(),
+        trader: order.trader,
+        client_order_id: order.client_order_id,
+        side: order.side,
+        price: exit_price,
+        size: position_size,
+        filled_size: position_size,
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+
+    cancel_order(&mut ctx.accounts.linked_order, margin_account)?;
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+#[instruction(client_order_id: u64)]
+pub struct CancelBracketLegs<'info> {
+    pub market: Account<'info, Market>,
+    #[account(
+        mut,
+        seeds = [b"order", market.key().as_ref(), trader.key().as_ref(), &client_order_id.to_le_bytes()],
+        bump = entry_order.bump,
+        has_one = trader,
+    )]
+    pub entry_order: Account<'info, Order>,
+    #[account(
+        mut,
+        seeds = [b"take_profit", entry_order.key().as_ref()],
+        bump = take_profit_order.bump,
+        has_one = trader,
+        has_one = margin_account,
+        close = trader
+    )]
+    pub take_profit_order: Account<'info, Order>,
+    #[account(
+        mut,
+        seeds = [b"stop_loss", entry_order.key().as_ref()],
+        bump = stop_loss_order.bump,
+        has_one = trader,
+        has_one = margin_account,
+        close = trader
+    )]
+    pub stop_loss_order: Account<'info, Order>,
+    #[account(mut)]
+    pub margin_account: Account<'info, MarginAccount>,
+    #[account(mut)]
+    pub trader: Signer<'info>,
+}
+
+/// Cancel both protective legs of a bracket, e.g. after the position was closed manually.
+/// An entry that is still resting becomes a plain limit order; a filled one is closed.
+pub fn cancel_bracket_legs(ctx: Context<CancelBracketLegs>, _client_order_id: u64) -> Result<()> {
+    cancel_order(&mut ctx.accounts.take_profit_order, &mut ctx.accounts.margin_account)?;
+    cancel_order(&mut ctx.accounts.stop_loss_order, &mut ctx.accounts.margin_account)?;
+
+    let entry_order = &mut ctx.accounts.entry_order;
+    if entry_order.is_active {
+        entry_order.linked_order = Pubkey::default();
+    } else {
+        entry_order.close(ctx.accounts.trader.to_account_info())?;
+    }
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+#[instruction(client_order_id: u64)]
+pub struct CancelOrderByClientId<'info> {
+    pub market: Account<'info, Market>,
+    #[account(
+        mut,
+        seeds = [b"order", market.key().as_ref(), trader.key().as_ref(), &client_order_id.to_le_bytes()],
+        bump = order.bump,
+        has_one = trader,
+        has_one = market,
+        has_one = margin_account,
+        constraint = order.is_active @ ErrorCode::OrderNotActive,
+        close = trader  // This closes the order account and sends rent to trader
+    )]
+    pub order: Account<'info, Order>,
+    /// Required when the order is a bracket entry, whose legs are cancelled with it
+    #[account(
+        mut,
+        seeds = [b"take_profit", order.key().as_ref()],
+        bump = take_profit_order.bump,
+        close = trader
+    )]
+    pub take_profit_order: Option<Account<'info, Order>>,
+    #[account(
+        mut,
+        seeds = [b"stop_loss", order.key().as_ref()],
+        bump = stop_loss_order.bump,
+        close = trader
+    )]
+    pub stop_loss_order: Option<Account<'info, Order>>,
+    #[account(mut)]
+    pub margin_account: Account<'info, MarginAccount>,
+    #[account(mut)]
+    pub trader: Signer<'info>,
+}
+
+pub fn cancel_order_by_client_id(ctx: Context<CancelOrderByClientId>, _client_order_id: u64) -> Result<()> {
+    let accounts = ctx.accounts;
+    cancel_order(&mut accounts.order, &mut accounts.margin_account)?;
+
+    // Legs of a cancelled bracket entry would otherwise be left without an entry to arm them
+    if accounts.order.linked_order != Pubkey::default() {
+        let take_profit_order = accounts.take_profit_order.as_mut().ok_or(ErrorCode::InvalidParameter)?;
+        cancel_order(take_profit_order, &mut accounts.margin_account)?;
+        let stop_loss_order = accounts.stop_loss_order.as_mut().ok_or(ErrorCode::InvalidParameter)?;
+        cancel_order(stop_loss_order, &mut accounts.margin_account)?;
+    }
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct MatchOrders<'info> {
+    #[account(mut, constraint = market.is_active @ ErrorCode::MarketInactive)]
+    pub market: Account<'info, Market>,
+    #[account(
+        mut,
+        has_one = market,
+        constraint = maker_order.is_active @ ErrorCode::OrderNotActive,
+        constraint = maker_order.order_type == OrderType::Limit @ ErrorCode::InvalidParameter,
+    )]
+    pub maker_order: Account<'info, Order>,
+    #[account(
+        mut,
+        has_one = market,
+        constraint = taker_order.is_active @ ErrorCode::OrderNotActive,
+        constraint = taker_order.order_type == OrderType::Limit @ ErrorCode::InvalidParameter,
+        constraint = taker_order.key() != maker_order.key() @ ErrorCode::InvalidParameter,
+    )]
+    pub taker_order: Account<'info, Order>,
+    #[account(mut, address = maker_order.margin_account)]
+    pub maker_margin_account: Account<'info, MarginAccount>,
+    #[account(mut, address = taker_order.margin_account)]
+    pub taker_margin_account: Account<'info, MarginAccount>,
+    #[account(
+        init_if_needed,
+        payer = matcher,
+        space = Position::SPACE,
+        seeds = [
+            b"order_position",
+            market.key().as_ref(),
+            maker_order.trader.as_ref(),
+            &maker_order.client_order_id.to_le_bytes()
+        ],
+        bump
+    )]
+    pub maker_position: Account<'info, Position>,
+    #[account(
+        init_if_needed,
+        payer = matcher,
+        space = Position::SPACE,
+        seeds = [
+            b"order_position",
+            market.key().as_ref(),
+            taker_order.trader.as_ref(),
+            &taker_order.client_order_id.to_le_bytes()
+        ],
+        bump
+    )]
+    pub taker_position: Account<'info, Position>,
+    /// CHECK: Receives the maker order's rent once it is filled or cancelled
+    #[account(mut, address = maker_order.trader)]
+    pub maker: UncheckedAccount<'info>,
+    /// CHECK: Receives the taker order's rent once it is filled or cancelled
+    #[account(mut, address = taker_order.trader)]
+    pub taker: UncheckedAccount<'info>,
+    /// Permissionless cranker that pays rent for newly created positions
+    #[account(mut)]
+    pub matcher: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+pub fn match_orders(ctx: Context<MatchOrders>) -> Result<()> {
+    let bumps = ctx.bumps;
+    let accounts = ctx.accounts;
+    let market = &mut accounts.market;
+    let maker_order = &mut accounts.maker_order;
+    let taker_order = &mut accounts.taker_order;
+    let current_timestamp = Clock::get()?.unix_timestamp;
+
+    // The maker must have been resting on the book before the taker arrived
+    require!(maker_order.side != taker_order.side, ErrorCode::OrdersDoNotCross);
+    require!(
+        maker_order.created_at <= taker_order.created_at,
+        ErrorCode::InvalidParameter
+    );
+    let crosses = match taker_order.side {
+        Side::Long => taker_order.price >= maker_order.price,
+        Side::Short => taker_order.price <= maker_order.price,
+    };
+    require!(crosses, ErrorCode::OrdersDoNotCross);
+
+    // Fills always happen at the resting maker price
+    let fill_price = maker_order.price;
+    let fill_size = remaining_size(maker_order)?.min(remaining_size(taker_order)?);
+
+    // Self-trades are detected by margin account owner or a shared STP group
+    let is_self_trade = accounts.maker_margin_account.owner == accounts.taker_margin_account.owner
+        || (maker_order.stp_group != 0 && maker_order.stp_group == taker_order.stp_group);
+
+    if is_self_trade {
+        // The taker's mode decides which side of the self-trade is cancelled
+        let stp_mode = taker_order.stp_mode;
+        emit!(SelfTradePreventedEvent {
+            market: market.key(),
+            maker_order: maker_order.key(),
+            taker_order: taker_order.key(),
+            maker: maker_order.trader,
+            taker: taker_order.trader,
+            maker_client_order_id: maker_order.client_order_id,
+            taker_client_order_id: taker_order.client_order_id,
+            stp_mode,
+            price: fill_price,
+            size: fill_size,
+            timestamp: current_timestamp,
+        });
+
+        if matches!(stp_mode, SelfTradePrevention::CancelMaker | SelfTradePrevention::CancelBoth) {
+            cancel_order(maker_order, &mut accounts.maker_margin_account)?;
+            if !is_bracket_entry(maker_order) {
+                maker_order.close(accounts.maker.to_account_info())?;
+            }
+        }
+        if matches!(stp_mode, SelfTradePrevention::CancelTaker | SelfTradePrevention::CancelBoth) {
+            cancel_order(taker_order, &mut accounts.taker_margin_account)?;
+            if !is_bracket_entry(taker_order) {
+                taker_order.close(accounts.taker.to_account_info())?;
+            }
+        }
+
+        // Nothing was filled, so hand back any position accounts created for this match
+        for position in [&mut accounts.maker_position, &mut accounts.taker_position] {
+            if !position.is_open {
+                position.close(accounts.matcher.to_account_info())?;
+            }
+        }
+
+        return Ok(());
+    }
+
+    fill_order(
+        market,
+        maker_order,
+        &mut accounts.maker_position,
+        &mut accounts.maker_margin_account,
+        fill_size,
+        fill_price,
+        current_timestamp,
+        bumps.maker_position,
+    )?;
+    fill_order(
+        market,
+        taker_order,
+        &mut accounts.taker_position,
+        &mut accounts.taker_margin_account,
+        fill_size,
+        fill_price,
+        current_timestamp,
+        bumps.taker_position,
+    )?;
+
+    // Fully filled orders are closed and their rent returned to the trader.
+    // Bracket entries stay open as the fill record that arms their legs.
+    if !maker_order.is_active && !is_bracket_entry(maker_order) {
+        maker_order.close(accounts.maker.to_account_info())?;
+    }
+    if !taker_order.is_active && !is_bracket_entry(taker_order) {
+        taker_order.close(accounts.taker.to_account_info())?;
+    }
+
+    Ok(())
+}
+
+/// Validate a limit order, reserve its collateral and populate the order account
+#[allow(clippy::too_many_arguments)]
+fn open_limit_order(
+    order: &mut Account<Order>,
+    market: &Account<Market>,
+    margin_account: &mut Account<MarginAccount>,
+    trader: Pubkey,
+    side: Side,
+    price: u64,
+    size: u64,
+    leverage: u64,
+) -> Result<()> {
+    validate_order_size(market, size)?;
+    require!(price > 0, ErrorCode::InvalidOrderPrice);
+    validate_leverage(market, size, leverage)?;
+
+    // Collateral is reserved at the limit price so the fill is always covered
+    let required_collateral = calculate_required_collateral(market, size, price, leverage)?;
+    reserve_order_margin(margin_account, required_collateral)?;
+
+    order.trader = trader;
+    order.market = market.key();
+    order.margin_account = margin_account.key();
+    order.side = side;
+    order.order_type = OrderType::Limit;
+    order.price = price;
+    order.size = size;
+    order.filled_size = 0;
+    order.leverage = leverage;
+    order.collateral = required_collateral;
+    order.created_at = Clock::get()?.unix_timestamp;
+    order.is_active = true;
+    order.trigger_price = 0;
+    order.parent_order = Pubkey::default();
+    order.linked_order = Pubkey::default();
+
+    Ok(())
+}
+
+/// Emit `OrderPlacedEvent` for a resting order
+fn emit_order_placed(order: &Order, program_id: &Pubkey) {
+    // The position opened by an order's fills uses the same client order ID as its seed
+    let (position, _) = Pubkey::find_program_address(
+        &[
+            b"order_position",
+            order.market.as_ref(),
+            order.trader.as_ref(),
+            &order.client_order_id.to_le_bytes(),
+        ],
+        program_id,
+    );
+
+    emit!(OrderPlacedEvent {
+        market: order.market,
+        position,
+        trader: order.trader,
+        client_order_id: order.client_order_id,
+        side: order.side,
+        order_type: order.order_type,
+        price: order.price,
+        size: order.size,
+        leverage: order.leverage,
+        timestamp: order.created_at,
+    });
+}
+
+/// Cancel an order and release the collateral still reserved for its unfilled size
+fn cancel_order(order: &mut Account<Order>, margin_account: &mut MarginAccount) -> Result<()> {
+    let released_collateral = order.collateral;
+    release_order_margin(margin_account, released_collateral)?;
+
+    order.collateral = 0;
+    order.is_active = false;
+
+    emit!(OrderCancelledEvent {
+        market: order.market,
+        order: order.key(),
+        trader: order.trader,
+        client_order_id: order.client_order_id,
+        released_collateral,
+    });
+
+    Ok(())
+}
+
+/// Whether an order is the entry of a bracket, which links to its take-profit leg
+fn is_bracket_entry(order: &Order) -> bool {
+    order.order_type == OrderType::Limit && order.linked_order != Pubkey::default()
+}
+
+/// Size of an order that is still waiting to be filled
+fn remaining_size(order: &Order) -> Result<u64> {
+    order
+        .size
+        .checked_sub(order.filled_size)
+        .ok_or(ErrorCode::MathOverflow.into())
+}
+
+/// Apply a fill to an order, moving its reserved collateral into the position it opens
+#[allow(clippy::too_many_arguments)]
+fn fill_order(
+    market: &mut Account<Market>,
+    order: &mut Account<Order>,
+    position: &mut Account<Position>,
+    margin_account: &mut MarginAccount,
+    fill_size: u64,
+    fill_price: u64,
+    current_timestamp: i64,
+    position_bump: u8,
+) -> Result<()> {
+    // Collateral was reserved for the whole order, so it moves across pro-rata
+    let remaining = remaining_size(order)?;
+    let fill_collateral = if fill_size == remaining {
+        order.collateral
+    } else {
+        (order.collateral as u128)
+            .checked_mul(fill_size as u128)
+            .ok_or(ErrorCode::MathOverflow)?
+            .checked_div(remaining as u128)
+            .ok_or(ErrorCode::MathOverflow)? as u64
+    };
+
+    order.filled_size = order
+        .filled_size
+        .checked_add(fill_size)
+        .ok_or(ErrorCode::MathOverflow)?;
+    order.collateral = order
+        .collateral
+        .checked_sub(fill_collateral)
+        .ok_or(ErrorCode::MathOverflow)?;
+    // Isolated collateral stays allocated to the position, while cross positions are backed by
+    // the whole account. Realized losses since the order was placed may have used up collateral
+    // it had reserved, in which case it can no longer fill.
+    if margin_account.margin_type == MarginType::Cross {
+        require!(
+            margin_account.collateral >= margin_account.allocated_margin,
+            ErrorCode::InsufficientMargin
+        );
+        release_order_margin(margin_account, fill_collateral)?;
+    }
+    if order.filled_size == order.size {
+        order.is_active = false;
+    } else if remaining_size(order)? < market.min_order_size {
+        // A remainder below the minimum order size could never fill on its own
+        cancel_order(order, margin_account)?;
+    }
+
+    if position.is_open {
+        // Later fills of the same order add to its position at a size-weighted entry price
+        require!(position.side == order.side, ErrorCode::PositionSideMismatch);
+        settle_funding(market, position, margin_account)?;
+        add_to_position(position, fill_size, fill_price, fill_collateral)?;
+    } else {
+        position.trader = order.trader;
+        position.market = market.key();
+        position.order_type = OrderType::Limit;
+        position.side = order.side;
+        position.size = fill_size;
+        position.filled_size = fill_size;
+        position.price = fill_price;
+        position.collateral = fill_collateral;
+        position.entry_price = fill_price;
+        position.entry_funding_rate = market.funding_rate;
+        position.leverage = order.leverage;
+        position.realized_pnl = 0;
+        position.last_funding_payment_time = current_timestamp;
+        position.last_cumulative_funding = market.cumulative_funding_rate;
+        position.is_open = true;
+        position.created_at = current_timestamp;
+        position.client_order_id = order.client_order_id;
+        position.bump = position_bump;
+
+        margin_account.positions.push(position.key());
+
+        emit!(PositionOpenedEvent {
+            market: market.key(),
+            position: position.key(),
+            trader: order.trader,
+            side: order.side,
+            size: fill_size,
+            collateral: fill_collateral,
+            entry_price: fill_price,
+            leverage: order.leverage,
+            liquidation_price: liquidation_price(position, market.maintenance_margin_ratio_for(position.size))?,
+            margin_type: margin_account.margin_type,
+        });
+    }
+
+    // Update market state
+    increase_open_interest(market, order.side, fill_size, fill_price)?;
+
+    emit!(OrderFilledEvent {
+        market: market.key(),
+        position: position.key(),
+        trader: order.trader,
+        client_order_id: order.client_order_id,
+        side: order.side,
+        price: fill_price,
+        size: fill_size,
+        filled_size: order.filled_size,
+        timestamp: current_timestamp,
+    });
+
+    Ok(())
+}
+
+/// Credit or debit realized PnL to a margin account's collateral. A loss is paid up to the
+/// collateral available and the part it cannot cover is returned, to be recorded as bad debt.
+pub(crate) fn realize_pnl(margin_account: &mut MarginAccount, pnl: i64) -> Result<u64> {
+    if pnl >= 0 {
+        margin_account.collateral = margin_account.collateral
+            .checked_add(pnl as u64)
+            .ok_or(ErrorCode::MathOverflow)?;
+        return Ok(0);
+    }
+
+    let loss = pnl.unsigned_abs();
+    let paid = loss.min(margin_account.collateral);
+    margin_account.collateral -= paid;
+    Ok(loss - paid)
+}
+
+/// Add size to an open position, moving its entry price to the size-weighted average
+pub(crate) fn add_to_position(
+    position: &mut Position,
+    size: u64,
+    price: u64,
+    collateral: u64,
+) -> Result<()> {
+    let new_size = position
+        .size
+        .checked_add(size)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let entry_notional = (position.size as u128)
+        .checked_mul(position.entry_price as u128)
+        .ok_or(ErrorCode::MathOverflow)?
+        .checked_add(
+            (size as u128)
+                .checked_mul(price as u128)
+                .ok_or(ErrorCode::MathOverflow)?,
+        )
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    position.entry_price = entry_notional
+        .checked_div(new_size as u128)
+        .ok_or(ErrorCode::MathOverflow)? as u64;
+    position.size = new_size;
+    position.filled_size = new_size;
+    position.price = price;
+    position.collateral = position
+        .collateral
+        .checked_add(collateral)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    Ok(())
+}
+
+/// Validate an order size against the market's minimum, lot size and maximum position size
+pub(crate) fn validate_order_size(market: &Market, size: u64) -> Result<()> {
+    require!(size > 0, ErrorCode::InvalidOrderSize);
+    require!(size >= market.min_order_size, ErrorCode::PositionSizeTooSmall);
+    require!(size % market.order_step_size == 0, ErrorCode::InvalidOrderSize);
+    require!(size <= market.max_position_size, ErrorCode::PositionSizeTooLarge);
+
+    Ok(())
+}
+
+/// Require that what is left of a partially reduced position or order is not dust
+pub(crate) fn validate_remaining_size(market: &Market, remaining_size: u64) -> Result<()> {
+    require!(remaining_size >= market.min_order_size, ErrorCode::PositionSizeTooSmall);
+    require!(remaining_size % market.order_step_size == 0, ErrorCode::InvalidOrderSize);
+
+    Ok(())
+}
+
+/// Require that growing a position of `current_size` by `size` stays within the maximum position size
+pub(crate) fn validate_position_size(market: &Market, current_size: u64, size: u64) -> Result<()> {
+    let new_size = current_size
+        .checked_add(size)
+        .ok_or(ErrorCode::MathOverflow)?;
+    require!(new_size <= market.max_position_size, ErrorCode::PositionSizeTooLarge);
+
+    Ok(())
+}
+
+/// Add `size` to the open interest on `side`, rejecting trades that would breach the market's cap
+pub(crate) fn increase_open_interest(market: &mut Market, side: Side, size: u64, entry_price: u64) -> Result<()> {
+    let max_open_interest = market.max_open_interest;
+    let (open_interest, entry_notional) = match side {
+        Side::Long => (&mut market.long_open_interest, &mut market.long_entry_notional),
+        Side::Short => (&mut market.short_open_interest, &mut market.short_entry_notional),
+    };
+    *open_interest = open_interest
+        .checked_add(size)
+        .ok_or(ErrorCode::MathOverflow)?;
+    require!(*open_interest <= max_open_interest, ErrorCode::OpenInterestCapExceeded);
+    *entry_notional = (size as u128)
+        .checked_mul(entry_price as u128)
+        .and_then(|notional| entry_notional.checked_add(notional))
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    Ok(())
+}
+
+/// Remove `size` from the open interest on `side` when positions close, shrink or are liquidated
+pub(crate) fn decrease_open_interest(market: &mut Market, side: Side, size: u64, entry_price: u64) -> Result<()> {
+    let (open_interest, entry_notional) = match side {
+        Side::Long => (&mut market.long_open_interest, &mut market.long_entry_notional),
+        Side::Short => (&mut market.short_open_interest, &mut market.short_entry_notional),
+    };
+    *open_interest = open_interest
+        .checked_sub(size)
+        .ok_or(ErrorCode::MathOverflow)?;
+    // Averaged entry prices round, so the running sum may be slightly off; it never goes negative
+    *entry_notional = entry_notional.saturating_sub((size as u128) * (entry_price as u128));
+
+    Ok(())
+}
+
+/// Record PnL realized by a trader against the LP pool, which takes the other side. Once the
+/// market has a pool, a profit is paid only up to what the pool holds, counting the PnL it has
+/// not settled yet. Returns the PnL the trader realizes.
+pub(crate) fn record_trader_pnl(market: &mut Market, pnl: i64) -> Result<i64> {
+    let pnl = if pnl > 0 && market.lp_vault != Pubkey::default() {
+        let pool_value = (market.lp_balance as i64)
+            .checked_add(market.counterparty_pnl)
+            .ok_or(ErrorCode::MathOverflow)?;
+        pnl.min(pool_value.max(0))
+    } else {
+        pnl
+    };
+    market.counterparty_pnl = market.counterparty_pnl
+        .checked_sub(pnl)
+        .ok_or(ErrorCode::MathOverflow)?;
+    Ok(pnl)
+}
+
+/// Validate leverage against the market's maximum and its initial margin ratio
+pub(crate) fn validate_leverage(market: &Market, size: u64, leverage: u64) -> Result<()> {
+    require!(leverage <= market.max_leverage, ErrorCode::LeverageTooHigh);
+
+    // Validate that leverage is compatible with the initial margin ratio of the resulting size's tier
+    // For leverage to work, we need: 1/leverage >= initial_margin_ratio/10000
+    // This ensures required collateral >= minimum margin
+    let max_allowed_leverage = 10000u64
+        .checked_div(market.initial_margin_ratio_for(size))
+        .ok_or(ErrorCode::MathOverflow)?;
+    require!(leverage <= max_allowed_leverage, ErrorCode::LeverageTooHigh);
+
+    Ok(())
+}
+
+/// Collateral required to open `size` at `price` with the given leverage
+pub(crate) fn calculate_required_collateral(market: &Market, size: u64, price: u64, leverage: u64) -> Result<u64> {
+    // SIMPLE CALCULATION - No scaling
+    let position_value = size
+        .checked_mul(price)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    let required_collateral = position_value
+        .checked_div(leverage)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    // Ensure minimum margin requirements are met
+    let min_required_margin = margin_requirement(size, price, market.initial_margin_ratio_for(size))?;
+    require!(
+        required_collateral >= min_required_margin,
+        ErrorCode::InsufficientMargin
+    );
+
+    Ok(required_collateral)
+}
+
+/// Reserve collateral on a margin account based on its margin type
+pub(crate) fn reserve_margin(margin_account: &mut MarginAccount, amount: u64) -> Result<()> {
+    match margin_account.margin_type {
+        MarginType::Isolated => {
+            // For isolated margin, check if there's enough available margin
+            let available_margin = margin_account.available_margin()?;
+            require!(available_margin >= amount, ErrorCode::InsufficientMargin);
+
+            // Allocate margin to this position
+            margin_account.allocated_margin = margin_account
+                .allocated_margin
+                .checked_add(amount)
+                .ok_or(ErrorCode::MathOverflow)?;
+        }
+        MarginType::Cross => {
+            // For cross margin, check if the collateral not reserved by orders is sufficient
+            require!(
+                margin_account.available_margin()? >= amount,
+                ErrorCode::InsufficientMargin
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Reserve collateral for a resting order. Cross accounts reserve it too, so it cannot be
+/// withdrawn or used for other trades before the order fills or is cancelled.
+fn reserve_order_margin(margin_account: &mut MarginAccount, amount: u64) -> Result<()> {
+    reserve_margin(margin_account, amount)?;
+    if margin_account.margin_type == MarginType::Cross {
+        margin_account.allocated_margin = margin_account
+            .allocated_margin
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+    }
+    Ok(())
+}
+
+/// Release collateral previously reserved with `reserve_order_margin`
+fn release_order_margin(margin_account: &mut MarginAccount, amount: u64) -> Result<()> {
+    margin_account.allocated_margin = margin_account
+        .allocated_margin
+        .checked_sub(amount)
+        .ok_or(ErrorCode::MathOverflow)?;
+    Ok(())
+}
+
+/// Release collateral previously reserved with `reserve_margin`
+pub(crate) fn release_margin(margin_account: &mut MarginAccount, amount: u64) -> Result<()> {
+    if margin_account.margin_type == MarginType::Isolated {
+        margin_account.allocated_margin = margin_account
+            .allocated_margin
+            .checked_sub(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_lang::solana_program::{
+        clock::Clock,
+        entrypoint::ProgramResult,
+        program_option::COption,
+        program_pack::Pack,
+        program_stubs::{set_syscall_stubs, SyscallStubs},
+        system_program,
+    };
+    use anchor_lang::InstructionData;
+    use anchor_spl::token::spl_token;
+
+    struct SysvarStubs;
+
+    impl SyscallStubs for SysvarStubs {
+        fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+            unsafe { *(var_addr as *mut Clock) = Clock::default() };
+            0
+        }
+
+        fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+            unsafe { *(var_addr as *mut Rent) = Rent::default() };
+            0
+        }
+    }
+
+    fn account_data<T: AccountSerialize>(account: &T) -> Vec<u8> {
+        let mut data = Vec::new();
+        account.try_serialize(&mut data).unwrap();
+        data
+    }
+
+    /// Serialize an account padded to its allocated space, so that vectors in it can grow
+    fn padded_account_data<T: AccountSerialize>(account: &T, space: usize) -> Vec<u8> {
+        let mut data = account_data(account);
+        data.resize(space, 0);
+        data
+    }
+
+    fn mint_data() -> Vec<u8> {
+        let mut data = vec![0; spl_token::state::Mint::LEN];
+        spl_token::state::Mint {
+            mint_authority: COption::None,
+            supply: 0,
+            decimals: 6,
+            is_initialized: true,
+            freeze_authority: COption::None,
+        }
+        .pack_into_slice(&mut data);
+        data
+    }
+
+    fn token_account_data(mint: Pubkey, owner: Pubkey) -> Vec<u8> {
+        let mut data = vec![0; spl_token::state::Account::LEN];
+        spl_token::state::Account {
+            mint,
+            owner,
+            state: spl_token::state::AccountState::Initialized,
+            ..Default::default()
+        }
+        .pack_into_slice(&mut data);
+        data
+    }
+
+    fn position(market: Pubkey, trader: Pubkey) -> Position {
+        Position {
+            trader,
+            market,
+            order_type: OrderType::Market,
+            side: Side::Long,
+            size: 10,
+            filled_size: 10,
+            price: 1000,
+            collateral: 1000,
+            entry_price: 1000,
+            entry_funding_rate: 0,
+            leverage: 10,
+            realized_pnl: 0,
+            last_funding_payment_time: 0,
+            last_cumulative_funding: 0,
+            is_open: true,
+            created_at: 0,
+            bump: 255,
+            client_order_id: 0,
+        }
+    }
+
+    /// Run `liquidate_market_order` against a cross account holding two positions, passing
+    /// `triples` as its health accounts
+    fn liquidate_cross(triples: &[usize]) -> ProgramResult {
+        set_syscall_stubs(Box::new(SysvarStubs));
+
+        let program_id = crate::ID;
+        let trader = Pubkey::new_unique();
+        let liquidator = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        let oracle = Pubkey::new_unique();
+        let keys = [
+            Pubkey::new_unique(), // market
+            Pubkey::new_unique(), // position
+            Pubkey::new_unique(), // other position
+            Pubkey::new_unique(), // margin account
+            liquidator,
+            Pubkey::new_unique(), // liquidator token account
+            Pubkey::new_unique(), // market vault
+            oracle,
+            spl_token::ID,
+            program_id, // no auction
+        ];
+        let market = Market {
+            base_asset_reserve: 1_000_000,
+            quote_asset_reserve: 1_000_000,
+            peg_multiplier: 1000,
+            maintenance_margin_ratio: 100,
+            initial_margin_ratio: 200,
+            liquidation_fee_ratio: 250,
+            max_leverage: 20,
+            min_order_size: 1,
+            order_step_size: 1,
+            max_position_size: 1_000,
+            max_open_interest: 1_000,
+            oracle,
+            vault: keys[6],
+            ..Default::default()
+        };
+        let margin_account = MarginAccount {
+            owner: trader,
+            margin_type: MarginType::Cross,
+            collateral_mint: mint,
+            collateral: 2000,
+            positions: vec![keys[1], keys[2]],
+            collateral_market: keys[0],
+            ..Default::default()
+        };
+        let oracle_account = Oracle { price: 1000, authority: Pubkey::default(), timestamp: 0, confidence: 0 };
+
+        let mut data = [
+            account_data(&market),
+            account_data(&position(keys[0], trader)),
+            account_data(&position(keys[0], trader)),
+            account_data(&margin_account),
+            Vec::new(),
+            token_account_data(mint, liquidator),
+            token_account_data(mint, keys[0]),
+            account_data(&oracle_account),
+            Vec::new(),
+            Vec::new(),
+        ];
+        let owners = [
+            program_id,
+            program_id,
+            program_id,
+            program_id,
+            Pubkey::default(),
+            spl_token::ID,
+            spl_token::ID,
+            mock_oracle::ID,
+            Pubkey::default(),
+            Pubkey::default(),
+        ];
+        let mut lamports = [1_000_000_000u64; 10];
+
+        let mut accounts = Vec::new();
+        let fields = keys.iter().zip(data.iter_mut()).zip(owners.iter()).zip(lamports.iter_mut());
+        for (((key, data), owner), lamports) in fields {
+            let executable = *key == spl_token::ID || *key == program_id;
+            let is_signer = *key == liquidator;
+            accounts.push(AccountInfo::new(key, is_signer, !executable, lamports, data, owner, executable, 0));
+        }
+        let mut infos = vec![
+            accounts[0].clone(), // market
+            accounts[1].clone(), // position
+            accounts[3].clone(), // margin account
+            accounts[4].clone(), // liquidator
+            accounts[5].clone(), // liquidator token account
+            accounts[0].clone(), // collateral market
+            accounts[6].clone(), // market vault
+            accounts[7].clone(), // oracle
+            accounts[8].clone(), // token program
+            accounts[9].clone(), // auction
+        ];
+        for &position in triples {
+            infos.extend([accounts[position].clone(), accounts[0].clone(), accounts[7].clone()]);
+        }
+
+        let infos: &[AccountInfo] = Box::leak(infos.into_boxed_slice());
+        crate::entry(&program_id, infos, &crate::instruction::LiquidateMarketOrder {}.data())
+    }
+
+    /// Run an instruction against `(key, data, owner, is_signer)` accounts, passed in order
+    fn run(accounts: Vec<(Pubkey, Vec<u8>, Pubkey, bool)>, instruction_data: &[u8]) -> ProgramResult {
+        run_with_accounts(accounts, instruction_data).0
+    }
+
+    /// Like `run`, also returning the accounts so that their state can be inspected afterwards
+    fn run_with_accounts(
+        accounts: Vec<(Pubkey, Vec<u8>, Pubkey, bool)>,
+        instruction_data: &[u8],
+    ) -> (ProgramResult, &'static [AccountInfo<'static>]) {
+        set_syscall_stubs(Box::new(SysvarStubs));
+
+        let infos: Vec<AccountInfo> = accounts
+            .into_iter()
+            .map(|(key, data, owner, is_signer)| {
+                let executable = [spl_token::ID, system_program::ID, crate::ID].contains(&key);
+                AccountInfo::new(
+                    Box::leak(Box::new(key)),
+                    is_signer,
+                    !executable,
+                    Box::leak(Box::new(1_000_000_000u64)),
+                    Box::leak(data.into_boxed_slice()),
+                    Box::leak(Box::new(owner)),
+                    executable,
+                    0,
+                )
+            })
+            .collect();
+
+        let infos: &[AccountInfo] = Box::leak(infos.into_boxed_slice());
+        (crate::entry(&crate::ID, infos, instruction_data), infos)
+    }
+
+    fn read_account<T: AccountDeserialize>(info: &AccountInfo) -> T {
+        T::try_deserialize(&mut &info.data.borrow()[..]).unwrap()
+    }
+
+    fn cross_margin_account(owner: Pubkey, mint: Pubkey, collateral: u64, reserved: u64) -> MarginAccount {
+        MarginAccount {
+            owner,
+            margin_type: MarginType::Cross,
+            collateral_mint: mint,
+            collateral,
+            allocated_margin: reserved,
+            ..Default::default()
+        }
+    }
+
+    fn limit_order(market: Pubkey, trader: Pubkey, margin_account: Pubkey, side: Side, size: u64, client_order_id: u64) -> Order {
+        Order {
+            trader,
+            market,
+            margin_account,
+            side,
+            order_type: OrderType::Limit,
+            price: 1000,
+            size,
+            filled_size: 0,
+            leverage: 10,
+            collateral: size * 100,
+            created_at: 0,
+            is_active: true,
+            client_order_id,
+            stp_mode: SelfTradePrevention::CancelTaker,
+            stp_group: 0,
+            trigger_price: 0,
+            parent_order: Pubkey::default(),
+            linked_order: Pubkey::default(),
+            bump: 255,
+        }
+    }
+
+    /// Match a resting cross-margin maker order, which reserved 1000 of collateral, against a
+    /// taker after the maker account's collateral has changed to `maker_collateral`. Neither
+    /// order account is closed by the match, which host tests cannot do: the maker is a
+    /// bracket entry and the taker is only partially filled.
+    fn match_cross_orders(maker_collateral: u64) -> ProgramResult {
+        let program_id = crate::ID;
+        let market = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        let (maker, taker, matcher) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let (maker_margin, taker_margin) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (maker_order, taker_order) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let order_position = |trader: &Pubkey, client_order_id: u64| {
+            Pubkey::find_program_address(
+                &[b"order_position", market.as_ref(), trader.as_ref(), &client_order_id.to_le_bytes()],
+                &program_id,
+            )
+            .0
+        };
+        let empty_position = |trader: Pubkey| {
+            let position = Position { is_open: false, size: 0, ..position(market, trader) };
+            padded_account_data(&position, Position::SPACE)
+        };
+
+        let bracket_entry = Order {
+            linked_order: Pubkey::new_unique(),
+            ..limit_order(market, maker, maker_margin, Side::Long, 10, 1)
+        };
+        let market_account = Market {
+            maintenance_margin_ratio: 100,
+            initial_margin_ratio: 200,
+            max_leverage: 20,
+            min_order_size: 1,
+            order_step_size: 1,
+            max_position_size: 1_000,
+            max_open_interest: 1_000,
+            is_active: true,
+            ..Default::default()
+        };
+
+        run(
+            vec![
+                (market, account_data(&market_account), program_id, false),
+                (maker_order, account_data(&bracket_entry), program_id, false),
+                (taker_order, account_data(&limit_order(market, taker, taker_margin, Side::Short, 20, 2)), program_id, false),
+                (
+                    maker_margin,
+                    padded_account_data(&cross_margin_account(maker, mint, maker_collateral, 1000), MarginAccount::SPACE),
+                    program_id,
+                    false,
+                ),
+                (
+                    taker_margin,
+                    padded_account_data(&cross_margin_account(taker, mint, 2000, 2000), MarginAccount::SPACE),
+                    program_id,
+                    false,
+                ),
+                (order_position(&maker, 1), empty_position(maker), program_id, false),
+                (order_position(&taker, 2), empty_position(taker), program_id, false),
+                (maker, Vec::new(), system_program::ID, false),
+                (taker, Vec::new(), system_program::ID, false),
+                (matcher, Vec::new(), system_program::ID, true),
+                (system_program::ID, Vec::new(), Pubkey::default(), false),
+            ],
+            &crate::instruction::MatchOrders {}.data(),
+        )
+    }
+
+    #[test]
+    fn cross_order_collateral_cannot_be_withdrawn() {
+        let program_id = crate::ID;
+        let owner = Pubkey::new_unique();
+        let market = Pubkey::new_unique();
+        let margin_account = Pubkey::new_unique();
+        let (user_token_account, vault, mint) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+
+        let market_account = Market { vault, is_active: true, ..Default::default() };
+        // 4000 of the 5000 collateral is reserved by a resting limit order
+        let account = MarginAccount {
+            collateral_market: market,
+            ..cross_margin_account(owner, mint, 5000, 4000)
+        };
+
+        let result = run(
+            vec![
+                (owner, Vec::new(), system_program::ID, true),
+                (margin_account, account_data(&account), program_id, false),
+                (market, account_data(&market_account), program_id, false),
+                (user_token_account, token_account_data(mint, owner), spl_token::ID, false),
+                (vault, token_account_data(mint, market), spl_token::ID, false),
+                (mint, mint_data(), spl_token::ID, false),
+                (spl_token::ID, Vec::new(), Pubkey::default(), false),
+            ],
+            &crate::instruction::WithdrawCollateral { amount: 2000 }.data(),
+        );
+
+        let below_margin = ProgramError::from(Error::from(ErrorCode::WithdrawalBelowMaintenanceMargin));
+        assert_eq!(result, Err(below_margin));
+    }
+
+    #[test]
+    fn cross_order_fill_requires_its_reserved_collateral() {
+        // The reserved collateral is still there, so the orders fill
+        assert_eq!(match_cross_orders(1000), Ok(()));
+
+        // Realized losses since the order was placed left less collateral than it reserved
+        let insufficient_margin = ProgramError::from(Error::from(ErrorCode::InsufficientMargin));
+        assert_eq!(match_cross_orders(500), Err(insufficient_margin));
+    }
+
+    #[test]
+    fn liquidate_market_order_rejects_duplicated_health_accounts() {
+        let invalid_position = ProgramError::from(Error::from(ErrorCode::InvalidPosition));
+
+        // The position is passed twice in place of the account's other position
+        assert_eq!(liquidate_cross(&[1, 1]), Err(invalid_position.clone()));
+        // Passing a position more often than the account holds positions is rejected too
+        assert_eq!(liquidate_cross(&[1, 1, 2]), Err(invalid_position));
+    }
+
+    /// Run `auto_deleverage` on a market whose shorts owe 300 of bad debt at a bankruptcy price
+    /// of 1050, with the oracle at 1000. Short positions are passed in `order` by index: the
+    /// first is ranked highest (10 at 1200, 2000 of PnL on 1000 of collateral), the second
+    /// lower (20 at 1100, 2000 of PnL on 2000 of collateral). Longs owe 700 at 900.
+    fn deleverage_shorts(order: &[usize]) -> (ProgramResult, &'static [AccountInfo<'static>]) {
+        let program_id = crate::ID;
+        let (market, oracle, keeper) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let mint = Pubkey::new_unique();
+        let shorts = [(10, 1200, 1000), (20, 1100, 2000)].map(|(size, entry_price, collateral)| {
+            let trader = Pubkey::new_unique();
+            let position = Position {
+                side: Side::Short,
+                size,
+                filled_size: size,
+                entry_price,
+                price: entry_price,
+                collateral,
+                ..position(market, trader)
+            };
+            (trader, Pubkey::new_unique(), Pubkey::new_unique(), position)
+        });
+
+        let market_account = Market {
+            oracle,
+            min_order_size: 1,
+            order_step_size: 1,
+            max_open_interest: 1_000,
+            short_open_interest: 30,
+            short_entry_notional: 10 * 1200 + 20 * 1100,
+            long_open_interest: 30,
+            short_bad_debt: 300,
+            short_bankruptcy_price: 1050,
+            long_bad_debt: 700,
+            long_bankruptcy_price: 900,
+            ..Default::default()
+        };
+        let oracle_account = Oracle { price: 1000, authority: Pubkey::default(), timestamp: 0, confidence: 0 };
+
+        let mut accounts = vec![
+            (market, account_data(&market_account), program_id, false),
+            (keeper, Vec::new(), system_program::ID, true),
+            (oracle, account_data(&oracle_account), mock_oracle::ID, false),
+        ];
+        for &index in order {
+            let (trader, position_key, margin_key, position) = &shorts[index];
+            let margin_account = MarginAccount {
+                positions: vec![*position_key],
+                ..cross_margin_account(*trader, mint, 5000, 0)
+            };
+            accounts.extend([
+                (*position_key, account_data(position), program_id, false),
+                (*margin_key, account_data(&margin_account), program_id, false),
+                (*trader, Vec::new(), system_program::ID, false),
+            ]);
+        }
+
+        run_with_accounts(accounts, &crate::instruction::AutoDeleverage {}.data())
+    }
+
+    #[test]
+    fn auto_deleverage_reduces_at_the_bankruptcy_price() {
+        let (result, accounts) = deleverage_shorts(&[0, 1]);
+        assert_eq!(result, Ok(()));
+
+        // Filling at 1050 instead of 1000 gives up 50 per unit, so 6 units cover the bad debt
+        let position: Position = read_account(&accounts[3]);
+        assert_eq!(position.size, 4);
+        assert_eq!(position.realized_pnl, 6 * 150);
+        let margin_account: MarginAccount = read_account(&accounts[4]);
+        assert_eq!(margin_account.collateral, 5000 + 6 * 150);
+
+        // The lower ranked position is not needed
+        let position: Position = read_account(&accounts[6]);
+        assert_eq!(position.size, 20);
+
+        // Only the shorts' bad debt is paid off; the longs still owe theirs
+        let market: Market = read_account(&accounts[0]);
+        assert_eq!((market.short_bad_debt, market.short_bankruptcy_price), (0, 0));
+        assert_eq!((market.long_bad_debt, market.long_bankruptcy_price), (700, 900));
+        assert_eq!(market.short_open_interest, 24);
+    }
+
+    #[test]
+    fn auto_deleverage_runs_in_batches_in_ranking_order() {
+        // A pass may leave out positions, so large sides can be deleveraged over several passes
+        let (result, accounts) = deleverage_shorts(&[1]);
+        assert_eq!(result, Ok(()));
+        let position: Position = read_account(&accounts[3]);
+        assert_eq!(position.size, 14);
+
+        // Candidates in a pass must be passed highest ranked first
+        let not_ranked = ProgramError::from(Error::from(ErrorCode::AdlCandidatesNotRanked));
+        assert_eq!(deleverage_shorts(&[1, 0]).0, Err(not_ranked));
+    }
+
+    #[test]
+    fn bankruptcy_price_leaves_the_deficit_on_the_closed_size() {
+        use crate::risk::bankruptcy_price;
+
+        // A long closed at 900 that could not pay 250 of its loss on 10 units went bankrupt at 925
+        assert_eq!(bankruptcy_price(Side::Long, 900, 10, 250), Ok(925));
+        // Shorts go bankrupt below the exit price, never below zero
+        assert_eq!(bankruptcy_price(Side::Short, 1100, 10, 250), Ok(1075));
+        assert_eq!(bankruptcy_price(Side::Short, 10, 10, 250), Ok(0));
+    }
+}
@@ -1,3 +1,529 @@
+use anchor_lang::prelude::*;
+use mock_oracle::Oracle;
+use crate::{
+    errors::ErrorCode,
+    events::*,
+    instructions::{
+        funding::settle_funding,
+        liquidation::record_bad_debt,
+        order::{
+            add_to_position, calculate_required_collateral, close_position,
+            decrease_open_interest, increase_open_interest, realize_pnl,
+            record_trader_pnl, release_margin, reserve_margin, validate_leverage, validate_order_size,
+            validate_position_size,
+        },
+    },
+    risk::{
+        account_health, calculate_pnl, liquidation_price, load_position_health, margin_requirement,
+        position_health,
+    },
+    vamm::execute_trade,
+    MarginAccount, MarginType, Market, Position,
+};
+
+#[derive(Accounts)]
+pub struct IncreasePosition<'info> {
+    #[account(mut, constraint = market.is_active @ ErrorCode::MarketInactive)]
+    pub market: Account<'info, Market>,
+    #[account(
+        mut,
+        has_one = trader,
+        has_one = market,
+        constraint = position.is_open @ ErrorCode::PositionClosed
+    )]
+    pub position: Account<'info, Position>,
+    #[account(
+        mut,
+        constraint = margin_account.owner == trader.key() @ ErrorCode::Unauthorized,
+        constraint = margin_account.positions.contains(&position.key()) @ ErrorCode::InvalidPosition,
+    )]
+    pub margin_account: Account<'info, MarginAccount>,
+    #[account(mut)]
+    pub trader: Signer<'info>,
+    /// CHECK: Oracle account - validated in instruction
+    #[account(address = market.oracle @ ErrorCode::InvalidOracleAccount)]
+    pub price_update: UncheckedAccount<'info>,
+}
+
+/// Add size to an existing position on the same side at the vAMM execution price.
+/// The added size uses the position's leverage, so collateral is topped up pro-rata
+/// at the oracle price.
+pub fn increase_position(ctx: Context<IncreasePosition>, size: u64) -> Result<()> {
+    let market = &mut ctx.accounts.market;
+    let position = &mut ctx.accounts.position;
+    let margin_account = &mut ctx.accounts.margin_account;
+
+    validate_order_size(market, size)?;
+    validate_position_size(market, position.size, size)?;
+    // The grown position must meet the initial margin of its (possibly larger) tier
+    let new_size = position.size
+        .checked_add(size)
+        .ok_or(ErrorCode::MathOverflow)?;
+    validate_leverage(market, new_size, position.leverage)?;
+
+    // Get current price from oracle
+    let oracle_data = ctx.accounts.price_update.try_borrow_data()?;
+    let oracle = Oracle::try_deserialize(&mut oracle_data.as_ref())?;
+    let current_price = oracle.price;
+
+    // Funding is settled on the old size before the position grows
+    settle_funding(market, position, margin_account)?;
+
+    let required_collateral =
+        calculate_required_collateral(market, size, current_price, position.leverage)?;
+    reserve_margin(margin_account, required_collateral)?;
+    let execution_price = execute_trade(market, position.side, size, &oracle, false)?;
+    add_to_position(position, size, execution_price, required_collateral)?;
+
+    // Update market state
+    increase_open_interest(market, position.side, size, execution_price)?;
+
+    emit!(PositionIncreasedEvent {
+        market: market.key(),
+        position: position.key(),
+        trader: position.trader,
+        side: position.side,
+        size_added: size,
+        new_size: position.size,
+        collateral_added: required_collateral,
+        new_collateral: position.collateral,
+        fill_price: execution_price,
+        entry_price: position.entry_price,
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct ReducePosition<'info> {
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+    #[account(
+        mut,
+        has_one = trader,
+        has_one = market,
+        constraint = position.is_open @ ErrorCode::PositionClosed
+    )]
+    pub position: Account<'info, Position>,
+    #[account(
+        mut,
+        constraint = margin_account.owner == trader.key() @ ErrorCode::Unauthorized,
+        constraint = margin_account.positions.contains(&position.key()) @ ErrorCode::InvalidPosition,
+    )]
+    pub margin_account: Account<'info, MarginAccount>,
+    #[account(mut)]
+    pub trader: Signer<'info>,
+    /// CHECK: Oracle account - validated in instruction
+    #[account(address = market.oracle @ ErrorCode::InvalidOracleAccount)]
+    pub price_update: UncheckedAccount<'info>,
+}
+
+/// Close part of a position at the vAMM execution price.
+/// PnL and collateral are released pro-rata; reducing the whole size closes the account.
+pub fn reduce_position(ctx: Context<ReducePosition>, size: u64) -> Result<()> {
+    let market = &mut ctx.accounts.market;
+    let position = &mut ctx.accounts.position;
+    let margin_account = &mut ctx.accounts.margin_account;
+
+    require!(size > 0 && size <= position.size, ErrorCode::InvalidOrderSize);
+    // Partial reductions are whole lots and may not leave a dust position behind
+    if size < position.size {
+        require!(size % market.order_step_size == 0, ErrorCode::InvalidOrderSize);
+        require!(position.size - size >= market.min_order_size, ErrorCode::PositionSizeTooSmall);
+    }
+
+    // Get current price from oracle
+    let oracle_data = ctx.accounts.price_update.try_borrow_data()?;
+    let oracle = Oracle::try_deserialize(&mut oracle_data.as_ref())?;
+
+    let exit_price = execute_trade(market, position.side.opposite(), size, &oracle, true)?;
+    if size == position.size {
+        // A full reduction is a regular close and reclaims the rent
+        close_position(market, position, margin_account, exit_price)?;
+        position.is_open = false;
+        return position.close(ctx.accounts.trader.to_account_info());
+    }
+
+    reduce_open_position(market, position, margin_account, size, exit_price)?;
+
+    Ok(())
+}
+
+/// Close `size` units of a position at `current_price` while keeping it open.
+/// Funding is settled first, then PnL and collateral are released pro-rata.
+pub(crate) fn reduce_open_position(
+    market: &mut Account<Market>,
+    position: &mut Account<Position>,
+    margin_account: &mut Account<MarginAccount>,
+    size: u64,
+    current_price: u64,
+) -> Result<i64> {
+    require!(size < position.size, ErrorCode::InvalidOrderSize);
+
+    settle_funding(market, position, margin_account)?;
+
+    let pnl = calculate_pnl(position.side, position.entry_price, current_price, size)?;
+    let mut released_collateral = (position.collateral as u128)
+        .checked_mul(size as u128)
+        .ok_or(ErrorCode::MathOverflow)?
+        .checked_div(position.size as u128)
+        .ok_or(ErrorCode::MathOverflow)? as u64;
+
+    let pnl = record_trader_pnl(market, pnl)?;
+    // A loss beyond the collateral backing the position is left to the insurance fund and
+    // deleveraging. In isolated mode that is the position's own collateral, so a loss above
+    // the released share is taken from the collateral that stays with the position.
+    let deficit = if margin_account.margin_type == MarginType::Isolated && pnl < 0 {
+        let loss = pnl.unsigned_abs();
+        released_collateral = released_collateral.max(loss.min(position.collateral));
+        release_margin(margin_account, released_collateral)?;
+        let paid = loss.min(released_collateral).min(margin_account.collateral);
+        margin_account.collateral -= paid;
+        loss - paid
+    } else {
+        release_margin(margin_account, released_collateral)?;
+        realize_pnl(margin_account, pnl)?
+    };
+    if deficit > 0 {
+        record_bad_debt(market, position, deficit, size, current_price)?;
+    }
+
+    position.size = position.size
+        .checked_sub(size)
+        .ok_or(ErrorCode::MathOverflow)?;
+    position.filled_size = position.size;
+    position.collateral = position.collateral
+        .checked_sub(released_collateral)
+        .ok_or(ErrorCode::MathOverflow)?;
+    position.realized_pnl = position.realized_pnl
+        .checked_add(pnl)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    // Update market state
+    decrease_open_interest(market, position.side, size, position.entry_price)?;
+
+    emit!(PositionReducedEvent {
+        market: market.key(),
+        position: position.key(),
+        trader: position.trader,
+        side: position.side,
+        size_reduced: size,
+        remaining_size: position.size,
+        released_collateral,
+        entry_price: position.entry_price,
+        exit_price: current_price,
+        realized_pnl: pnl,
+        margin_type: margin_account.margin_type,
+    });
+
+    Ok(pnl)
+}
+
+#[derive(Accounts)]
+pub struct AdjustPositionMargin<'info> {
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+    #[account(
+        mut,
+        has_one = trader,
+        has_one = market,
+        constraint = position.is_open @ ErrorCode::PositionClosed
+    )]
+    pub position: Account<'info, Position>,
+    #[account(
+        mut,
+        constraint = margin_account.owner == trader.key() @ ErrorCode::Unauthorized,
+        constraint = margin_account.margin_type == MarginType::Isolated @ ErrorCode::InvalidParameter,
+        constraint = margin_account.positions.contains(&position.key()) @ ErrorCode::InvalidPosition,
+    )]
+    pub margin_account: Account<'info, MarginAccount>,
+    pub trader: Signer<'info>,
+    /// CHECK: Oracle account - validated in instruction
+    #[account(address = market.oracle @ ErrorCode::InvalidOracleAccount)]
+    pub price_update: UncheckedAccount<'info>,
+}
+
+/// Move isolated collateral between the margin account and a position.
+pub fn adjust_position_margin(
+    ctx: Context<AdjustPositionMargin>,
+    margin_change: i64, // Positive to add, negative to remove
+) -> Result<()> {
+    let market = &mut ctx.accounts.market;
+    let position = &mut ctx.accounts.position;
+    let margin_account = &mut ctx.accounts.margin_account;
+
+    require!(margin_change != 0, ErrorCode::InvalidParameter);
+
+    // Get current price from oracle
+    let oracle_data = ctx.accounts.price_update.try_borrow_data()?;
+    let oracle = Oracle::try_deserialize(&mut oracle_data.as_ref())?;
+    let current_price = oracle.price;
+
+    settle_funding(market, position, margin_account)?;
+
+    let amount = margin_change.unsigned_abs();
+    if margin_change > 0 {
+        reserve_margin(margin_account, amount)?;
+        position.collateral = position.collateral
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+    } else {
+        let new_collateral = position.collateral
+            .checked_sub(amount)
+            .ok_or(ErrorCode::InsufficientMargin)?;
+
+        // What is left, including unrealized PnL, must still cover the initial margin
+        check_initial_margin(market, position, new_collateral, current_price)?;
+
+        release_margin(margin_account, amount)?;
+        position.collateral = new_collateral;
+    }
+
+    // Effective leverage follows the collateral now backing the position
+    let entry_value = position.size
+        .checked_mul(position.entry_price)
+        .ok_or(ErrorCode::MathOverflow)?;
+    position.leverage = entry_value
+        .checked_div(position.collateral)
+        .ok_or(ErrorCode::MathOverflow)?
+        .max(1);
+
+    emit!(MarginAdjustedEvent {
+        market: market.key(),
+        position: position.key(),
+        trader: ctx.accounts.trader.key(),
+        margin_change,
+        new_collateral: position.collateral,
+        new_leverage: position.leverage,
+        liquidation_price: liquidation_price(position, market.maintenance_margin_ratio_for(position.size))?,
+    });
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct SetPositionLeverage<'info> {
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+    #[account(
+        mut,
+        has_one = trader,
+        has_one = market,
+        constraint = position.is_open @ ErrorCode::PositionClosed
+    )]
+    pub position: Account<'info, Position>,
+    #[account(
+        mut,
+        constraint = margin_account.owner == trader.key() @ ErrorCode::Unauthorized,
+        constraint = margin_account.positions.contains(&position.key()) @ ErrorCode::InvalidPosition,
+    )]
+    pub margin_account: Account<'info, MarginAccount>,
+    pub trader: Signer<'info>,
+    /// CHECK: Oracle account - validated in instruction
+    #[account(address = market.oracle @ ErrorCode::InvalidOracleAccount)]
+    pub price_update: UncheckedAccount<'info>,
+}
+
+/// Re-margin an open position at a new leverage using the current oracle price.
+/// In isolated mode the collateral difference moves to or from `allocated_margin`.
+pub fn set_position_leverage(ctx: Context<SetPositionLeverage>, new_leverage: u64) -> Result<()> {
+    let market = &mut ctx.accounts.market;
+    let position = &mut ctx.accounts.position;
+    let margin_account = &mut ctx.accounts.margin_account;
+
+    validate_leverage(market, position.size, new_leverage)?;
+
+    // Get current price from oracle
+    let oracle_data = ctx.accounts.price_update.try_borrow_data()?;
+    let oracle = Oracle::try_deserialize(&mut oracle_data.as_ref())?;
+    let current_price = oracle.price;
+
+    settle_funding(market, position, margin_account)?;
+
+    let old_leverage = position.leverage;
+    let old_collateral = position.collateral;
+    let new_collateral =
+        calculate_required_collateral(market, position.size, current_price, new_leverage)?;
+
+    if new_collateral > old_collateral {
+        reserve_margin(margin_account, new_collateral - old_collateral)?;
+    } else {
+        check_initial_margin(market, position, new_collateral, current_price)?;
+        release_margin(margin_account, old_collateral - new_collateral)?;
+    }
+
+    position.collateral = new_collateral;
+    position.leverage = new_leverage;
+
+    emit!(PositionLeverageUpdatedEvent {
+        market: market.key(),
+        position: position.key(),
+        trader: position.trader,
+        old_leverage,
+        new_leverage,
+        old_collateral,
+        new_collateral,
+        liquidation_price: liquidation_price(position, market.maintenance_margin_ratio_for(position.size))?,
+        margin_type: margin_account.margin_type,
+    });
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct TransferPosition<'info> {
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+    #[account(
+        mut,
+        has_one = trader,
+        has_one = market,
+        constraint = position.is_open @ ErrorCode::PositionClosed
+    )]
+    pub position: Account<'info, Position>,
+    #[account(
+        mut,
+        constraint = from_margin_account.owner == trader.key() @ ErrorCode::Unauthorized,
+        constraint = from_margin_account.positions.contains(&position.key()) @ ErrorCode::InvalidPosition,
+    )]
+    pub from_margin_account: Account<'info, MarginAccount>,
+    #[account(
+        mut,
+        constraint = to_margin_account.owner == trader.key() @ ErrorCode::Unauthorized,
+        constraint = to_margin_account.key() != from_margin_account.key() @ ErrorCode::InvalidParameter,
+        constraint = to_margin_account.collateral_mint == from_margin_account.collateral_mint @ ErrorCode::InvalidCollateralMint,
+    )]
+    pub to_margin_account: Account<'info, MarginAccount>,
+    /// CHECK: Limit order with the position's client order ID. For positions opened by limit
+    /// orders it must be closed, together with any bracket legs, before the position can move
+    #[account(
+        seeds = [b"order", market.key().as_ref(), trader.key().as_ref(), &position.client_order_id.to_le_bytes()],
+        bump
+    )]
+    pub order: UncheckedAccount<'info>,
+    pub trader: Signer<'info>,
+    /// CHECK: Oracle account - validated in instruction
+    #[account(address = market.oracle @ ErrorCode::InvalidOracleAccount)]
+    pub price_update: UncheckedAccount<'info>,
+    // Remaining accounts are (position, market, oracle) triples for every other position
+    // of the source account, followed by every position of the destination account
+}
+
+/// Move an open position, together with its collateral, to another margin account of the
+/// same owner without realizing PnL. Both accounts must meet their initial margin afterwards.
+pub fn transfer_position<'info>(
+    ctx: Context<'_, '_, 'info, 'info, TransferPosition<'info>>,
+) -> Result<()> {
+    let market = &mut ctx.accounts.market;
+    let position = &mut ctx.accounts.position;
+    let from_margin_account = &mut ctx.accounts.from_margin_account;
+    let to_margin_account = &mut ctx.accounts.to_margin_account;
+
+    // Netted positions are addressed by their margin account and cannot move
+    let (net_position, _) = Pubkey::find_program_address(
+        &[b"net_position", market.key().as_ref(), from_margin_account.key().as_ref()],
+        ctx.program_id,
+    );
+    require_keys_neq!(position.key(), net_position, ErrorCode::InvalidPosition);
+
+    // Fills and bracket legs of the opening order reserve from and settle into the source account
+    let (order_position, _) = Pubkey::find_program_address(
+        &[
+            b"order_position",
+            market.key().as_ref(),
+            position.trader.as_ref(),
+            &position.client_order_id.to_le_bytes(),
+        ],
+        ctx.program_id,
+    );
+    if position.key() == order_position {
+        require!(ctx.accounts.order.data_is_empty(), ErrorCode::PositionHasOpenOrders);
+    }
+
+    // Get current price from oracle
+    let oracle_data = ctx.accounts.price_update.try_borrow_data()?;
+    let oracle = Oracle::try_deserialize(&mut oracle_data.as_ref())?;
+    let current_price = oracle.price;
+
+    settle_funding(market, position, from_margin_account)?;
+
+    // Move the position's collateral out of the source account
+    let collateral = position.collateral;
+    require!(from_margin_account.collateral >= collateral, ErrorCode::InsufficientCollateral);
+    release_margin(from_margin_account, collateral)?;
+    from_margin_account.collateral = from_margin_account.collateral
+        .checked_sub(collateral)
+        .ok_or(ErrorCode::MathOverflow)?;
+    from_margin_account.positions.retain(|key| *key != position.key());
+
+    // ... and into the destination account
+    to_margin_account.collateral = to_margin_account.collateral
+        .checked_add(collateral)
+        .ok_or(ErrorCode::MathOverflow)?;
+    reserve_margin(to_margin_account, collateral)?;
+
+    let split = from_margin_account.positions.len()
+        .checked_mul(3)
+        .ok_or(ErrorCode::MathOverflow)?;
+    require!(ctx.remaining_accounts.len() >= split, ErrorCode::InvalidPosition);
+    let (from_accounts, to_accounts) = ctx.remaining_accounts.split_at(split);
+
+    // A cross source must still cover the initial margin of its remaining positions
+    if from_margin_account.margin_type == MarginType::Cross {
+        let positions = load_position_health(from_margin_account, from_accounts)?;
+        let health = account_health(from_margin_account, positions)?;
+        require!(health.equity >= health.initial_margin as i64, ErrorCode::InsufficientMargin);
+    }
+
+    // Re-validate the position against the destination's margin requirements
+    match to_margin_account.margin_type {
+        MarginType::Isolated => {
+            check_initial_margin(market, position, collateral, current_price)?;
+        }
+        MarginType::Cross => {
+            let mut positions = load_position_health(to_margin_account, to_accounts)?;
+            positions.push(position_health(market, position, current_price)?);
+            let health = account_health(to_margin_account, positions)?;
+            require!(health.equity >= health.initial_margin as i64, ErrorCode::InsufficientMargin);
+        }
+    }
+    to_margin_account.positions.push(position.key());
+
+    emit!(PositionTransferredEvent {
+        market: market.key(),
+        position: position.key(),
+        trader: position.trader,
+        from_margin_account: from_margin_account.key(),
+        to_margin_account: to_margin_account.key(),
+        from_margin_type: from_margin_account.margin_type,
+        to_margin_type: to_margin_account.margin_type,
+        collateral,
+        liquidation_price: liquidation_price(position, market.maintenance_margin_ratio_for(position.size))?,
+    });
+
+    Ok(())
+}
+
+/// Require that a position backed by `collateral` still meets the initial margin at `current_price`
+pub(crate) fn check_initial_margin(
+    market: &Market,
+    position: &Position,
+    collateral: u64,
+    current_price: u64,
+) -> Result<()> {
+    let min_required_margin =
+        margin_requirement(position.size, current_price, market.initial_margin_ratio_for(position.size))?;
+    let unrealized_pnl =
+        calculate_pnl(position.side, position.entry_price, current_price, position.size)?;
+    let equity = (collateral as i64)
+        .checked_add(unrealized_pnl)
+        .ok_or(ErrorCode::MathOverflow)?;
+    require!(equity >= min_required_margin as i64, ErrorCode::InsufficientMargin);
+
+    Ok(())
+}
+
 // use anchor_lang::prelude::*;
 // use mock_oracle::Oracle;
 // use crate::{errors::ErrorCode, events::*, {Market, Position, Side, MarginAccount, MarginType}};
@@ -278,79 +804,6 @@
 //     Ok(())
 // }
 
-// #[derive(Accounts)]
-// pub struct AdjustPositionMargin<'info> {
-//     #[account()]
-//     pub market: Account<'info, Market>,
-//     #[account(
-//         mut,
-//         has_one = trader,
-//         has_one = market,
-//         constraint = position.is_open @ ErrorCode::PositionClosed
-//     )]
-//     pub position: Account<'info, Position>,
-//     #[account(mut)]
-//     pub trader: Signer<'info>,
-//     /// CHECK: Oracle account - validated in instruction
-//     pub price_update: UncheckedAccount<'info>,
-// }
-
-// pub fn adjust_position_margin(
-//     ctx: Context<AdjustPositionMargin>,
-//     margin_change: i64, // Positive to add, negative to remove
-// ) -> Result<()> {
-//     let market = &ctx.accounts.market;
-//     let position = &mut ctx.accounts.position;
-
-//     // Calculate current position value
-//     let oracle_data = ctx.accounts.price_update.try_borrow_data()?;
-//     let oracle = Oracle::try_deserialize(&mut oracle_data.as_ref())?;
-//     let current_price = oracle.price;
-//     let position_value = position.size
-//         .checked_mul(current_price)
-//         .ok_or(ErrorCode::MathOverflow)?;
-
-//     // Adjust collateral based on margin_change
-//     if margin_change < 0 {
-//         let margin_to_remove = (-margin_change) as u64;
-//         let new_collateral = position.collateral
-//             .checked_sub(margin_to_remove)
-//             .ok_or(ErrorCode::InsufficientMargin)?;
-
-//         let min_required_margin = position_value
-//             .checked_mul(market.initial_margin_ratio)
-//             .ok_or(ErrorCode::MathOverflow)?
-//             .checked_div(10000)
-//             .ok_or(ErrorCode::MathOverflow)?;
-
-//         require!(new_collateral >= min_required_margin, ErrorCode::InsufficientMargin);
-//         position.collateral = new_collateral;
-
-//         // TODO: Transfer margin_to_remove back to trader
-//     } else {
-//         position.collateral = position.collateral
-//             .checked_add(margin_change as u64)
-//             .ok_or(ErrorCode::MathOverflow)?;
-
-//         // TODO: Transfer margin_change from trader
-//     }
-
-//     // Recalculate liquidation price
-//     let liquidation_price = calculate_liquidation_price(position, market.maintenance_margin_ratio)?;
-//     position.liquidation_price = liquidation_price;
-
-//     // Emit event
-//     emit!(MarginAdjustedEvent {
-//         market: market.key(),
-//         position: position.key(),
-//         trader: ctx.accounts.trader.key(),
-//         margin_change,
-//         new_collateral: position.collateral,
-//     });
-
-//     Ok(())
-// }
-
 // #[derive(Accounts)]
 // pub struct LiquidatePosition<'info> {
 //     #[account(mut)]
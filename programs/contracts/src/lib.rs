@@ -5,10 +5,14 @@ use anchor_lang::prelude::*;
 pub mod errors;
 pub mod events;
 pub mod instructions;
+pub mod risk;
 pub mod state;
+pub mod vamm;
 
 use instructions::*;
+use risk::*;
 use state::*;
+use vamm::*;
 
 
 declare_id!("6UnAEvz8tLBLXM2uDmbYWYKZ6UuAgdxJHTss8HC9h3wf");
@@ -17,6 +21,7 @@ declare_id!("6UnAEvz8tLBLXM2uDmbYWYKZ6UuAgdxJHTss8HC9h3wf");
 pub mod contracts {
     use super::*;
 
+    #[allow(clippy::too_many_arguments)]
     pub fn initialize_market(
         ctx: Context<InitializeMarket>,
         market_symbol: String,
@@ -26,6 +31,13 @@ pub mod contracts {
         initial_margin_ratio: u64,
         max_leverage: u64,
         liquidation_fee_ratio: u64,
+        min_order_size: u64,
+        order_step_size: u64,
+        max_position_size: u64,
+        max_open_interest: u64,
+        base_asset_reserve: u64,
+        quote_asset_reserve: u64,
+        peg_multiplier: u64,
         bump: u8,
     ) -> Result<()> {
         instructions::market::initialize_market(
@@ -37,6 +49,13 @@ pub mod contracts {
             initial_margin_ratio,
             max_leverage,
             liquidation_fee_ratio,
+            min_order_size,
+            order_step_size,
+            max_position_size,
+            max_open_interest,
+            base_asset_reserve,
+            quote_asset_reserve,
+            peg_multiplier,
             bump,
         )
     }
@@ -49,12 +68,17 @@ pub mod contracts {
         instructions::market::resume_market(ctx)
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn update_market_params(
         ctx: Context<UpdateMarketParams>,
         maintenance_margin_ratio: Option<u64>,
         initial_margin_ratio: Option<u64>,
         funding_interval: Option<i64>,
         max_leverage: Option<u64>,
+        min_order_size: Option<u64>,
+        order_step_size: Option<u64>,
+        max_position_size: Option<u64>,
+        max_open_interest: Option<u64>,
     ) -> Result<()> {
         instructions::market::update_market_params(
             ctx,
@@ -62,13 +86,48 @@ pub mod contracts {
             initial_margin_ratio,
             funding_interval,
             max_leverage,
+            min_order_size,
+            order_step_size,
+            max_position_size,
+            max_open_interest,
         )
     }
 
+    #[allow(clippy::too_many_arguments)]
+    pub fn migrate_market(
+        ctx: Context<MigrateMarket>,
+        min_order_size: u64,
+        order_step_size: u64,
+        max_position_size: u64,
+        max_open_interest: u64,
+        base_asset_reserve: u64,
+        quote_asset_reserve: u64,
+        peg_multiplier: u64,
+    ) -> Result<()> {
+        instructions::market::migrate_market(
+            ctx,
+            min_order_size,
+            order_step_size,
+            max_position_size,
+            max_open_interest,
+            base_asset_reserve,
+            quote_asset_reserve,
+            peg_multiplier,
+        )
+    }
+
+    pub fn migrate_position(ctx: Context<MigratePosition>) -> Result<()> {
+        instructions::market::migrate_position(ctx)
+    }
+
     pub fn create_margin_account(ctx: Context<CreateMarginAccount>, margin_type: MarginType, bump: u8) -> Result<()> {
         instructions::collateral::create_margin_account(ctx, margin_type, bump)
     }
 
+    pub fn create_sub_margin_account(ctx: Context<CreateSubMarginAccount>, sub_account_id: u16, margin_type: MarginType) -> Result<()> {
+        instructions::collateral::create_sub_margin_account(ctx, sub_account_id, margin_type)
+    }
+
     pub fn deposit_collateral(ctx: Context<DepositCollateral>, amount: u64) -> Result<()> {
         instructions::collateral::deposit_collateral(ctx, amount)
     }
@@ -77,13 +136,17 @@ pub mod contracts {
         instructions::collateral::withdraw_collateral(ctx, amount)
     }
 
-    // pub fn update_funding_rate(ctx: Context<UpdateFundingRate>, new_funding_rate: i64) -> Result<()> {
-    //     instructions::funding::update_funding_rate(ctx, new_funding_rate)
-    // }
+    pub fn migrate_margin_account(ctx: Context<MigrateMarginAccount>) -> Result<()> {
+        instructions::collateral::migrate_margin_account(ctx)
+    }
 
-    // pub fn update_funding_payments(ctx: Context<UpdateFundingPayments>) -> Result<()> {
-    //     instructions::funding::update_funding_payments(ctx)
-    // }
+    pub fn update_funding_rate(ctx: Context<UpdateFundingRate>, new_funding_rate: i64) -> Result<()> {
+        instructions::funding::update_funding_rate(ctx, new_funding_rate)
+    }
+
+    pub fn update_funding_payments(ctx: Context<UpdateFundingPayments>) -> Result<()> {
+        instructions::funding::update_funding_payments(ctx)
+    }
 
     pub fn place_market_order(
         ctx: Context<PlaceMarketOrder>,
@@ -96,11 +159,239 @@ pub mod contracts {
         instructions::order::place_market_order(ctx, side, size, leverage, position_bump, uid)
     }
 
+    pub fn place_net_order(
+        ctx: Context<PlaceNetOrder>,
+        side: Side,
+        size: u64,
+        leverage: u64,
+        client_order_id: u64,
+    ) -> Result<()> {
+        instructions::order::place_net_order(ctx, side, size, leverage, client_order_id)
+    }
+
     pub fn close_market_order(ctx: Context<CloseMarketOrder>) -> Result<()> {
         instructions::order::close_market_order(ctx)
     }
 
-    pub fn liquidate_market_order(ctx: Context<LiquidateMarketOrder>) -> Result<()> {
+    pub fn liquidate_market_order<'info>(
+        ctx: Context<'_, '_, 'info, 'info, LiquidateMarketOrder<'info>>,
+    ) -> Result<()> {
         instructions::order::liquidate_market_order(ctx)
     }
-}
\ No newline at end of file
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn place_limit_order(
+        ctx: Context<PlaceLimitOrder>,
+        side: Side,
+        price: u64,
+        size: u64,
+        leverage: u64,
+        client_order_id: u64,
+        stp_mode: SelfTradePrevention,
+        stp_group: u64,
+    ) -> Result<()> {
+        instructions::order::place_limit_order(
+            ctx,
+            side,
+            price,
+            size,
+            leverage,
+            client_order_id,
+            stp_mode,
+            stp_group,
+        )
+    }
+
+    pub fn cancel_order_by_client_id(ctx: Context<CancelOrderByClientId>, client_order_id: u64) -> Result<()> {
+        instructions::order::cancel_order_by_client_id(ctx, client_order_id)
+    }
+
+    pub fn match_orders(ctx: Context<MatchOrders>) -> Result<()> {
+        instructions::order::match_orders(ctx)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn place_bracket_order(
+        ctx: Context<PlaceBracketOrder>,
+        side: Side,
+        price: u64,
+        size: u64,
+        leverage: u64,
+        client_order_id: u64,
+        take_profit_price: u64,
+        stop_loss_price: u64,
+        stp_mode: SelfTradePrevention,
+        stp_group: u64,
+    ) -> Result<()> {
+        instructions::order::place_bracket_order(
+            ctx,
+            side,
+            price,
+            size,
+            leverage,
+            client_order_id,
+            take_profit_price,
+            stop_loss_price,
+            stp_mode,
+            stp_group,
+        )
+    }
+
+    pub fn execute_trigger_order(ctx: Context<ExecuteTriggerOrder>) -> Result<()> {
+        instructions::order::execute_trigger_order(ctx)
+    }
+
+    pub fn cancel_bracket_legs(ctx: Context<CancelBracketLegs>, client_order_id: u64) -> Result<()> {
+        instructions::order::cancel_bracket_legs(ctx, client_order_id)
+    }
+
+    pub fn increase_position(ctx: Context<IncreasePosition>, size: u64) -> Result<()> {
+        instructions::position::increase_position(ctx, size)
+    }
+
+    pub fn reduce_position(ctx: Context<ReducePosition>, size: u64) -> Result<()> {
+        instructions::position::reduce_position(ctx, size)
+    }
+
+    pub fn adjust_position_margin(ctx: Context<AdjustPositionMargin>, margin_change: i64) -> Result<()> {
+        instructions::position::adjust_position_margin(ctx, margin_change)
+    }
+
+    pub fn set_position_leverage(ctx: Context<SetPositionLeverage>, new_leverage: u64) -> Result<()> {
+        instructions::position::set_position_leverage(ctx, new_leverage)
+    }
+
+    pub fn get_account_health<'info>(
+        ctx: Context<'_, '_, 'info, 'info, GetAccountHealth<'info>>,
+    ) -> Result<AccountHealth> {
+        instructions::collateral::get_account_health(ctx)
+    }
+
+    pub fn transfer_position<'info>(
+        ctx: Context<'_, '_, 'info, 'info, TransferPosition<'info>>,
+    ) -> Result<()> {
+        instructions::position::transfer_position(ctx)
+    }
+
+    pub fn auto_deleverage<'info>(
+        ctx: Context<'_, '_, 'info, 'info, AutoDeleverage<'info>>,
+    ) -> Result<()> {
+        instructions::liquidation::auto_deleverage(ctx)
+    }
+
+    pub fn liquidate_account<'info>(
+        ctx: Context<'_, '_, 'info, 'info, LiquidateAccount<'info>>,
+    ) -> Result<()> {
+        instructions::liquidation::liquidate_account(ctx)
+    }
+
+    pub fn update_liquidation_auction_params(
+        ctx: Context<UpdateLiquidationAuctionParams>,
+        size_threshold: u64,
+        duration_slots: u64,
+        start_discount: u64,
+        max_discount: u64,
+    ) -> Result<()> {
+        instructions::market::update_liquidation_auction_params(
+            ctx,
+            size_threshold,
+            duration_slots,
+            start_discount,
+            max_discount,
+        )
+    }
+
+    pub fn start_liquidation_auction<'info>(
+        ctx: Context<'_, '_, 'info, 'info, StartLiquidationAuction<'info>>,
+    ) -> Result<()> {
+        instructions::liquidation::start_liquidation_auction(ctx)
+    }
+
+    pub fn take_liquidation_auction<'info>(
+        ctx: Context<'_, '_, 'info, 'info, TakeLiquidationAuction<'info>>,
+        leverage: u64,
+        client_order_id: u64,
+    ) -> Result<()> {
+        instructions::liquidation::take_liquidation_auction(ctx, leverage, client_order_id)
+    }
+
+    pub fn cancel_liquidation_auction<'info>(
+        ctx: Context<'_, '_, 'info, 'info, CancelLiquidationAuction<'info>>,
+    ) -> Result<()> {
+        instructions::liquidation::cancel_liquidation_auction(ctx)
+    }
+
+    pub fn liquidate_with_takeover<'info>(
+        ctx: Context<'_, '_, 'info, 'info, LiquidateWithTakeover<'info>>,
+        size: u64,
+        leverage: u64,
+        client_order_id: u64,
+    ) -> Result<()> {
+        instructions::liquidation::liquidate_with_takeover(ctx, size, leverage, client_order_id)
+    }
+
+    pub fn set_margin_tiers(ctx: Context<SetMarginTiers>, tiers: Vec<MarginTier>) -> Result<()> {
+        instructions::market::set_margin_tiers(ctx, tiers)
+    }
+
+    pub fn get_execution_price(ctx: Context<GetExecutionPrice>, side: Side, size: u64) -> Result<AmmQuote> {
+        instructions::amm::get_execution_price(ctx, side, size)
+    }
+
+    pub fn repeg_amm(ctx: Context<RepegAmm>) -> Result<()> {
+        instructions::amm::repeg_amm(ctx)
+    }
+
+    pub fn update_amm_k(ctx: Context<UpdateAmmK>, base_asset_reserve: u64) -> Result<()> {
+        instructions::amm::update_amm_k(ctx, base_asset_reserve)
+    }
+
+    pub fn update_amm_budget(
+        ctx: Context<UpdateAmmBudget>,
+        max_adjustment_cost: u64,
+        fee_pool_share: u64,
+    ) -> Result<()> {
+        instructions::amm::update_amm_budget(ctx, max_adjustment_cost, fee_pool_share)
+    }
+
+    pub fn update_spread_params(
+        ctx: Context<UpdateSpreadParams>,
+        base_spread: u64,
+        max_spread: u64,
+        skew_spread: u64,
+    ) -> Result<()> {
+        instructions::amm::update_spread_params(ctx, base_spread, max_spread, skew_spread)
+    }
+
+    pub fn update_oracle_band(ctx: Context<UpdateOracleBand>, max_oracle_deviation: u64) -> Result<()> {
+        instructions::amm::update_oracle_band(ctx, max_oracle_deviation)
+    }
+
+    pub fn initialize_lp_pool(
+        ctx: Context<InitializeLpPool>,
+        withdrawal_cooldown: i64,
+        withdrawal_window: i64,
+    ) -> Result<()> {
+        instructions::lp::initialize_lp_pool(ctx, withdrawal_cooldown, withdrawal_window)
+    }
+
+    pub fn deposit_lp(ctx: Context<DepositLp>, amount: u64) -> Result<()> {
+        instructions::lp::deposit_lp(ctx, amount)
+    }
+
+    pub fn request_lp_withdrawal(ctx: Context<RequestLpWithdrawal>, shares: u64) -> Result<()> {
+        instructions::lp::request_lp_withdrawal(ctx, shares)
+    }
+
+    pub fn withdraw_lp(ctx: Context<WithdrawLp>) -> Result<()> {
+        instructions::lp::withdraw_lp(ctx)
+    }
+
+    pub fn settle_lp_pool(ctx: Context<SettleLpPool>) -> Result<()> {
+        instructions::lp::settle_lp_pool(ctx)
+    }
+
+    pub fn get_lp_pool_nav(ctx: Context<GetLpPoolNav>) -> Result<LpPoolNav> {
+        instructions::lp::get_lp_pool_nav(ctx)
+    }
+}
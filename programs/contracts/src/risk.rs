@@ -0,0 +1,325 @@
+// Shared margin and health computations for positions and margin accounts.
+// All ratios are expressed in basis points (10000 = 100%).
+use anchor_lang::prelude::*;
+use mock_oracle::Oracle;
+use crate::{errors::ErrorCode, MarginAccount, MarginType, Market, Position, Side};
+
+/// Margin kept above the maintenance margin after a partial liquidation, in basis points
+pub const LIQUIDATION_BUFFER_RATIO: u64 = 100;
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default)]
+pub struct PositionHealth {
+    pub position: Pubkey,
+    pub mark_price: u64,          // Oracle price the health was computed at
+    pub notional: u64,            // size * mark_price
+    pub unrealized_pnl: i64,
+    pub equity: i64,              // collateral + unrealized PnL (negative when bankrupt)
+    pub initial_margin: u64,
+    pub maintenance_margin: u64,
+    pub margin_ratio: u64,        // equity / notional
+    pub liquidation_price: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, Default)]
+pub struct AccountHealth {
+    pub margin_type: MarginType,
+    pub collateral: u64,
+    pub unrealized_pnl: i64,
+    pub equity: i64,
+    pub initial_margin: u64,
+    pub maintenance_margin: u64,
+    pub margin_ratio: u64,
+    pub free_collateral: u64,     // Collateral that can be withdrawn or used for new positions
+    pub is_liquidatable: bool,
+    pub positions: Vec<PositionHealth>,
+}
+
+/// Integer division rounding up. `div_ceil` is newer than the SBF toolchain's compiler.
+pub fn div_ceil(numerator: u128, denominator: u128) -> Result<u128> {
+    require!(denominator > 0, ErrorCode::MathOverflow);
+    Ok(numerator / denominator + u128::from(numerator % denominator != 0))
+}
+
+/// Read an oracle account
+pub fn load_oracle(price_update: &AccountInfo) -> Result<Oracle> {
+    let oracle_data = price_update.try_borrow_data()?;
+    Oracle::try_deserialize(&mut oracle_data.as_ref())
+}
+
+/// Read the current price from an oracle account
+pub fn oracle_price(price_update: &AccountInfo) -> Result<u64> {
+    Ok(load_oracle(price_update)?.price)
+}
+
+/// PnL of `size` units of a position if it were closed at `exit_price`
+pub fn calculate_pnl(side: Side, entry_price: u64, exit_price: u64, size: u64) -> Result<i64> {
+    let price_diff = match side {
+        Side::Long => (exit_price as i128).checked_sub(entry_price as i128),
+        Side::Short => (entry_price as i128).checked_sub(exit_price as i128),
+    }
+    .ok_or(ErrorCode::MathOverflow)?;
+
+    price_diff
+        .checked_mul(size as i128)
+        .and_then(|pnl| i64::try_from(pnl).ok())
+        .ok_or(ErrorCode::MathOverflow.into())
+}
+
+/// Margin required for `size` at `price` under a ratio in basis points
+pub fn margin_requirement(size: u64, price: u64, ratio: u64) -> Result<u64> {
+    (size as u128)
+        .checked_mul(price as u128)
+        .and_then(|value| value.checked_mul(ratio as u128))
+        .and_then(|value| value.checked_div(10000))
+        .and_then(|value| u64::try_from(value).ok())
+        .ok_or(ErrorCode::MathOverflow.into())
+}
+
+/// Collateral plus unrealized PnL of a position at `price`
+pub fn position_equity(position: &Position, price: u64) -> Result<i64> {
+    let unrealized_pnl = calculate_pnl(position.side, position.entry_price, price, position.size)?;
+    (position.collateral as i64)
+        .checked_add(unrealized_pnl)
+        .ok_or(ErrorCode::MathOverflow.into())
+}
+
+/// Equity as a share of notional, in basis points. Zero once equity is gone.
+pub fn margin_ratio(equity: i64, notional: u64) -> Result<u64> {
+    if notional == 0 {
+        return Ok(u64::MAX);
+    }
+    if equity <= 0 {
+        return Ok(0);
+    }
+    (equity as u128)
+        .checked_mul(10000)
+        .and_then(|value| value.checked_div(notional as u128))
+        .map(|ratio| ratio.min(u64::MAX as u128) as u64)
+        .ok_or(ErrorCode::MathOverflow.into())
+}
+
+/// Oracle price at which the position's equity falls to its maintenance margin.
+///
+/// Long:  collateral + (P - entry) * size = mmr * size * P
+///        => P = (entry * size - collateral) / (size * (1 - mmr))
+/// Short: collateral + (entry - P) * size = mmr * size * P
+///        => P = (entry * size + collateral) / (size * (1 + mmr))
+pub fn liquidation_price(position: &Position, maintenance_margin_ratio: u64) -> Result<u64> {
+    if position.size == 0 {
+        return Ok(0);
+    }
+    let entry_value = (position.entry_price as u128)
+        .checked_mul(position.size as u128)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let collateral = position.collateral as u128;
+
+    let (numerator, ratio) = match position.side {
+        Side::Long => {
+            // Fully collateralized longs can never be liquidated
+            if collateral >= entry_value {
+                return Ok(0);
+            }
+            (entry_value - collateral, 10000u128.checked_sub(maintenance_margin_ratio as u128))
+        }
+        Side::Short => (
+            entry_value.checked_add(collateral).ok_or(ErrorCode::MathOverflow)?,
+            10000u128.checked_add(maintenance_margin_ratio as u128),
+        ),
+    };
+    let denominator = (position.size as u128)
+        .checked_mul(ratio.ok_or(ErrorCode::MathOverflow)?)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    numerator
+        .checked_mul(10000)
+        .and_then(|value| value.checked_div(denominator))
+        .and_then(|price| u64::try_from(price).ok())
+        .ok_or(ErrorCode::MathOverflow.into())
+}
+
+/// Price at which closing `size` of a position would have used up exactly what it paid, given
+/// that closing at `exit_price` left `deficit` unpaid. Longs went bankrupt above the exit price,
+/// shorts below it.
+pub fn bankruptcy_price(side: Side, exit_price: u64, size: u64, deficit: u64) -> Result<u64> {
+    let shortfall_per_unit = div_ceil(deficit as u128, size.max(1) as u128)?;
+    let price = match side {
+        Side::Long => (exit_price as u128)
+            .checked_add(shortfall_per_unit)
+            .ok_or(ErrorCode::MathOverflow)?,
+        Side::Short => (exit_price as u128).saturating_sub(shortfall_per_unit),
+    };
+    u64::try_from(price).map_err(|_| ErrorCode::MathOverflow.into())
+}
+
+/// Health of a single position at the given oracle price
+pub fn position_health(market: &Market, position: &Account<Position>, price: u64) -> Result<PositionHealth> {
+    let notional = position.size
+        .checked_mul(price)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let unrealized_pnl = calculate_pnl(position.side, position.entry_price, price, position.size)?;
+    let equity = (position.collateral as i64)
+        .checked_add(unrealized_pnl)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    Ok(PositionHealth {
+        position: position.key(),
+        mark_price: price,
+        notional,
+        unrealized_pnl,
+        equity,
+        initial_margin: margin_requirement(position.size, price, market.initial_margin_ratio_for(position.size))?,
+        maintenance_margin: margin_requirement(position.size, price, market.maintenance_margin_ratio_for(position.size))?,
+        margin_ratio: margin_ratio(equity, notional)?,
+        liquidation_price: liquidation_price(position, market.maintenance_margin_ratio_for(position.size))?,
+    })
+}
+
+/// Size, in whole lots, to liquidate so that the rest of the position is back above the
+/// maintenance margin plus `LIQUIDATION_BUFFER_RATIO` once the realized loss and the
+/// liquidation fee are taken from its collateral. With f the fee ratio and t the target ratio,
+/// both from the position's margin tier:
+///
+///   equity - x * P * f >= (size - x) * P * t  =>  x >= (size * P * t - equity) / (P * (t - f))
+///
+/// Returns the full size when no partial liquidation can restore the position.
+pub fn partial_liquidation_size(market: &Market, position: &Position, equity: i64, price: u64) -> Result<u64> {
+    let tier = market.margin_tier(position.size);
+    let target_ratio = market.maintenance_margin_ratio_for(position.size)
+        .checked_add(LIQUIDATION_BUFFER_RATIO)
+        .ok_or(ErrorCode::MathOverflow)?;
+    if equity <= 0 || price == 0 || target_ratio <= tier.liquidation_fee_ratio {
+        return Ok(position.size);
+    }
+
+    let required = (position.size as u128)
+        .checked_mul(price as u128)
+        .and_then(|value| value.checked_mul(target_ratio as u128))
+        .ok_or(ErrorCode::MathOverflow)?;
+    let available = (equity as u128)
+        .checked_mul(10000)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let per_unit = (price as u128)
+        .checked_mul((target_ratio - tier.liquidation_fee_ratio) as u128)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    let step = market.order_step_size as u128;
+    let size = div_ceil(div_ceil(required.saturating_sub(available), per_unit)?.max(1), step)?
+        .checked_mul(step)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    // Leaving less than the minimum order size behind is a full liquidation
+    let remaining = (position.size as u128).saturating_sub(size);
+    if remaining < market.min_order_size as u128 {
+        return Ok(position.size);
+    }
+    Ok(size as u64)
+}
+
+/// Equity a cross account leaves for one of its positions once every other position is
+/// covered up to its maintenance margin plus `LIQUIDATION_BUFFER_RATIO`
+pub fn cross_liquidation_equity(account: &AccountHealth, position: Pubkey) -> Result<i64> {
+    let mut equity = account.equity;
+    for other in account.positions.iter().filter(|p| p.position != position) {
+        let buffer = margin_requirement(1, other.notional, LIQUIDATION_BUFFER_RATIO)?;
+        equity = equity
+            .checked_sub(other.maintenance_margin as i64)
+            .and_then(|equity| equity.checked_sub(buffer as i64))
+            .ok_or(ErrorCode::MathOverflow)?;
+    }
+    Ok(equity)
+}
+
+/// Aggregate position health into the health of their margin account.
+///
+/// Cross accounts are healthy while total equity covers the total maintenance margin,
+/// and may use any equity above the initial margin. Isolated accounts are only as healthy
+/// as their weakest position and can use only collateral not allocated to positions.
+pub fn account_health(margin_account: &MarginAccount, positions: Vec<PositionHealth>) -> Result<AccountHealth> {
+    let mut unrealized_pnl: i64 = 0;
+    let mut notional: u64 = 0;
+    let mut initial_margin: u64 = 0;
+    let mut maintenance_margin: u64 = 0;
+    let mut any_position_liquidatable = false;
+
+    for health in &positions {
+        unrealized_pnl = unrealized_pnl
+            .checked_add(health.unrealized_pnl)
+            .ok_or(ErrorCode::MathOverflow)?;
+        notional = notional
+            .checked_add(health.notional)
+            .ok_or(ErrorCode::MathOverflow)?;
+        initial_margin = initial_margin
+            .checked_add(health.initial_margin)
+            .ok_or(ErrorCode::MathOverflow)?;
+        maintenance_margin = maintenance_margin
+            .checked_add(health.maintenance_margin)
+            .ok_or(ErrorCode::MathOverflow)?;
+        any_position_liquidatable |= health.equity < health.maintenance_margin as i64;
+    }
+
+    let equity = (margin_account.collateral as i64)
+        .checked_add(unrealized_pnl)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    let (free_collateral, is_liquidatable) = match margin_account.margin_type {
+        MarginType::Isolated => (margin_account.available_margin()?, any_position_liquidatable),
+        MarginType::Cross => {
+            // Collateral reserved by resting orders is not free either
+            let free = equity
+                .checked_sub(initial_margin as i64)
+                .and_then(|free| free.checked_sub(margin_account.allocated_margin as i64))
+                .ok_or(ErrorCode::MathOverflow)?
+                .clamp(0, margin_account.available_margin()? as i64) as u64;
+            (free, !positions.is_empty() && equity < maintenance_margin as i64)
+        }
+    };
+
+    Ok(AccountHealth {
+        margin_type: margin_account.margin_type,
+        collateral: margin_account.collateral,
+        unrealized_pnl,
+        equity,
+        initial_margin,
+        maintenance_margin,
+        margin_ratio: margin_ratio(equity, notional)?,
+        free_collateral,
+        is_liquidatable,
+        positions,
+    })
+}
+
+/// Load every position of a margin account from remaining accounts passed as
+/// `(position, market, oracle)` triples, in any order, and compute its health.
+/// Each position must be passed exactly once.
+pub fn load_position_health<'info>(
+    margin_account: &MarginAccount,
+    remaining_accounts: &'info [AccountInfo<'info>],
+) -> Result<Vec<PositionHealth>> {
+    require!(remaining_accounts.len() % 3 == 0, ErrorCode::InvalidPosition);
+    require!(
+        remaining_accounts.len() / 3 == margin_account.positions.len(),
+        ErrorCode::InvalidPosition
+    );
+
+    let mut positions: Vec<PositionHealth> = Vec::with_capacity(remaining_accounts.len() / 3);
+    for accounts in remaining_accounts.chunks(3) {
+        let position = Account::<Position>::try_from(&accounts[0])?;
+        let market = Account::<Market>::try_from(&accounts[1])?;
+        require!(
+            margin_account.positions.contains(&position.key()),
+            ErrorCode::InvalidPosition
+        );
+        // With the count check above, rejecting duplicates means every position is present
+        require!(
+            !positions.iter().any(|p| p.position == position.key()),
+            ErrorCode::InvalidPosition
+        );
+        require_keys_eq!(position.market, market.key(), ErrorCode::InvalidPosition);
+        require_keys_eq!(accounts[2].key(), market.oracle, ErrorCode::InvalidOracleAccount);
+
+        let price = oracle_price(&accounts[2])?;
+        positions.push(position_health(&market, &position, price)?);
+    }
+
+    Ok(positions)
+}
@@ -5,6 +5,8 @@ use crate::errors::ErrorCode;
 pub enum OrderType {
     Market,
     Limit,
+    TakeProfit,
+    StopLoss,
 }
 
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Copy, Debug)]
@@ -13,24 +15,55 @@ pub enum Side {
     Short,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Copy, Debug)]
+impl Side {
+    /// Side that trades against this one, e.g. to close a position
+    pub fn opposite(&self) -> Side {
+        match self {
+            Side::Long => Side::Short,
+            Side::Short => Side::Long,
+        }
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Copy, Debug, Default)]
+pub enum SelfTradePrevention {
+    #[default]
+    CancelTaker,
+    CancelMaker,
+    CancelBoth,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Copy, Debug, Default)]
 pub enum MarginType {
+    #[default]
     Isolated,
     Cross,
 }
 
-impl Default for MarginType {
-    fn default() -> Self {
-        MarginType::Isolated
-    }
+/// Initial margin, maintenance margin and liquidation fee for positions up to `max_size`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct MarginTier {
+    pub max_size: u64,                    // Largest position size in this tier
+    pub initial_margin_ratio: u64,        // Initial margin ratio for the tier (in basis points)
+    pub maintenance_margin_ratio: u64,    // Maintenance margin ratio for the tier (in basis points)
+    pub maintenance_margin_offset: u64,   // Size * basis points deducted from the tier's maintenance margin, derived by set_margin_tiers
+    pub liquidation_fee_ratio: u64,       // Liquidation fee ratio for the tier (in basis points)
 }
 
+impl MarginTier {
+    pub const SPACE: usize = 8 + 8 + 8 + 8 + 8;
+}
+
+/// Most margin tiers a market can configure
+pub const MAX_MARGIN_TIERS: usize = 8;
+
 #[account]
+#[cfg_attr(test, derive(Default))]
 pub struct Market {
     pub authority: Pubkey,                // Admin authority
     pub market_symbol: String,            // Market identifier (e.g., "SOL-PERP")
-    pub base_asset_reserve: u64,          // Total base asset (SOL) in the market
-    pub quote_asset_reserve: u64,         // Total quote asset (USD) in the market
+    pub base_asset_reserve: u64,          // vAMM base reserve (x in x * y = k)
+    pub quote_asset_reserve: u64,         // vAMM quote reserve (y in x * y = k)
     pub funding_rate: i64,                // Current funding rate (can be positive or negative)
     pub last_funding_time: i64,           // Last time funding was paid/collected
     pub funding_interval: i64,            // Interval between funding payments (e.g., 1 hour)
@@ -44,6 +77,39 @@ pub struct Market {
     pub vault: Pubkey,                    // Token account that holds all user collateral
     pub is_active: bool,                  // Whether the market is active
     pub bump: u8,                         // PDA bump
+    pub cumulative_funding_rate: i64,     // Sum of funding rates applied so far (in basis points)
+    pub min_order_size: u64,              // Smallest order size accepted
+    pub order_step_size: u64,             // Lot size; order sizes must be a multiple of it
+    pub max_position_size: u64,           // Largest position size a single position can reach
+    pub long_open_interest: u64,          // Total size of open long positions
+    pub short_open_interest: u64,         // Total size of open short positions
+    pub max_open_interest: u64,           // Open interest cap, applied to each side separately
+    pub long_bad_debt: u64,               // Losses of bankrupt shorts beyond the insurance fund, recovered from longs
+    pub short_bad_debt: u64,              // Losses of bankrupt longs beyond the insurance fund, recovered from shorts
+    pub long_bankruptcy_price: u64,       // Price longs are deleveraged at: bankruptcy price behind long_bad_debt
+    pub short_bankruptcy_price: u64,      // Price shorts are deleveraged at: bankruptcy price behind short_bad_debt
+    pub auction_size_threshold: u64,      // Positions of at least this size are liquidated by auction (0 = off)
+    pub auction_duration_slots: u64,      // Slots over which the auction discount grows
+    pub auction_start_discount: u64,      // Discount to the oracle price when an auction starts (bps)
+    pub auction_max_discount: u64,        // Discount reached at the end of an auction (bps)
+    pub margin_tiers: [MarginTier; MAX_MARGIN_TIERS], // Size tiers overriding the margin ratios above, by ascending max_size
+    pub margin_tier_count: u8,            // Number of margin_tiers in use
+    pub peg_multiplier: u64,              // Converts quote reserves to prices: price = y * peg / x
+    pub amm_max_adjustment_cost: u64,     // Most fee_pool a single repeg or k adjustment may spend
+    pub amm_fee_pool_share: u64,          // Share of fee_pool a single adjustment may spend (bps)
+    pub base_spread: u64,                 // Half spread every fill pays around the vAMM price (bps)
+    pub max_spread: u64,                  // Cap on the dynamic half spread (bps)
+    pub skew_spread: u64,                 // Extra half spread at fully one-sided open interest (bps)
+    pub oracle_volatility: u64,           // Moving average of oracle price moves between fills (bps)
+    pub last_oracle_price: u64,           // Oracle price at the last fill
+    pub long_entry_notional: u128,        // Sum of size * entry price over open long positions
+    pub short_entry_notional: u128,       // Sum of size * entry price over open short positions
+    pub counterparty_pnl: i64,            // Realized trader losses and funding minus profits, taken by the LP pool
+    pub max_oracle_deviation: u64,        // Largest distance of a vAMM fill from the oracle price (bps), 0 disables the band
+    pub amm_net_base: i64,                // Base the vAMM holds from fills: negative when users are net long against it
+    pub last_volatility_update: i64,      // Oracle timestamp of last_oracle_price
+    pub lp_vault: Pubkey,                 // Vault of the LP pool backing trader profits (default until a pool exists)
+    pub lp_balance: u64,                  // Collateral held in lp_vault
 }
 
 impl Market {
@@ -64,7 +130,83 @@ impl Market {
         32 + // oracle: Pubkey
         32 + // vault: Pubkey
         1 + // is_active: bool
-        1; // bump: u8
+        1 + // bump: u8
+        8 + // cumulative_funding_rate: i64
+        8 + // min_order_size: u64
+        8 + // order_step_size: u64
+        8 + // max_position_size: u64
+        8 + // long_open_interest: u64
+        8 + // short_open_interest: u64
+        8 + // max_open_interest: u64
+        8 + // long_bad_debt: u64
+        8 + // short_bad_debt: u64
+        8 + // long_bankruptcy_price: u64
+        8 + // short_bankruptcy_price: u64
+        8 + // auction_size_threshold: u64
+        8 + // auction_duration_slots: u64
+        8 + // auction_start_discount: u64
+        8 + // auction_max_discount: u64
+        MarginTier::SPACE * MAX_MARGIN_TIERS + // margin_tiers: [MarginTier; MAX_MARGIN_TIERS]
+        1 + // margin_tier_count: u8
+        8 + // peg_multiplier: u64
+        8 + // amm_max_adjustment_cost: u64
+        8 + // amm_fee_pool_share: u64
+        8 + // base_spread: u64
+        8 + // max_spread: u64
+        8 + // skew_spread: u64
+        8 + // oracle_volatility: u64
+        8 + // last_oracle_price: u64
+        16 + // long_entry_notional: u128
+        16 + // short_entry_notional: u128
+        8 + // counterparty_pnl: i64
+        8 + // max_oracle_deviation: u64
+        8 + // amm_net_base: i64
+        8 + // last_volatility_update: i64
+        32 + // lp_vault: Pubkey
+        8; // lp_balance: u64
+
+    /// Tier of a position of `size`. Positions above the largest tier use the largest tier.
+    pub fn margin_tier(&self, size: u64) -> MarginTier {
+        let tiers = self.active_margin_tiers();
+        tiers
+            .iter()
+            .find(|tier| size <= tier.max_size)
+            .or(tiers.last())
+            .copied()
+            .unwrap_or(MarginTier {
+                max_size: u64::MAX,
+                initial_margin_ratio: self.initial_margin_ratio,
+                maintenance_margin_ratio: self.maintenance_margin_ratio,
+                maintenance_margin_offset: 0,
+                liquidation_fee_ratio: self.liquidation_fee_ratio,
+            })
+    }
+
+    /// Margin tiers the market has configured
+    pub fn active_margin_tiers(&self) -> &[MarginTier] {
+        &self.margin_tiers[..self.margin_tier_count as usize]
+    }
+
+    /// Initial margin ratio of a position of `size`
+    pub fn initial_margin_ratio_for(&self, size: u64) -> u64 {
+        self.margin_tier(size).initial_margin_ratio
+    }
+
+    /// Maintenance margin ratio of a position of `size`. The tier's offset is spread over the
+    /// size, so the maintenance margin grows continuously across tier boundaries.
+    pub fn maintenance_margin_ratio_for(&self, size: u64) -> u64 {
+        let tier = self.margin_tier(size);
+        if size == 0 {
+            return tier.maintenance_margin_ratio;
+        }
+        tier.maintenance_margin_ratio
+            .saturating_sub(tier.maintenance_margin_offset / size)
+    }
+
+    /// Liquidation fee ratio of a position of `size`
+    pub fn liquidation_fee_ratio_for(&self, size: u64) -> u64 {
+        self.margin_tier(size).liquidation_fee_ratio
+    }
 }
 
 #[account]
@@ -86,6 +228,7 @@ pub struct Position {
     pub is_open: bool,                    // Whether the position is open
     pub created_at: i64,                  // Timestamp when position was created
     pub bump: u8,                         // PDA bump
+    pub client_order_id: u64,             // Client order ID of the order that opened the position
 }
 
 impl Position {
@@ -106,13 +249,15 @@ impl Position {
         8 + // last_cumulative_funding: i64
         1 + // is_open: bool
         8 + // created_at: i64
-        1; // bump: u8
+        1 + // bump: u8
+        8; // client_order_id: u64
 }
 
 #[account]
 pub struct Order {
     pub trader: Pubkey,                   // Owner of the order
     pub market: Pubkey,                   // Market this order belongs to
+    pub margin_account: Pubkey,           // Margin account the collateral is reserved from
     pub side: Side,                       // Buy or Sell
     pub order_type: OrderType,            // Limit, Market, etc.
     pub price: u64,                       // Order price
@@ -122,11 +267,105 @@ pub struct Order {
     pub collateral: u64,                  // Collateral locked for this order
     pub created_at: i64,                  // Timestamp when order was created
     pub is_active: bool,                  // Whether the order is active
+    pub client_order_id: u64,             // Client-assigned order ID (also seeds the order PDA)
+    pub stp_mode: SelfTradePrevention,    // What to cancel when this order would trade against itself
+    pub stp_group: u64,                   // Optional self-trade group shared across accounts (0 = none)
+    pub trigger_price: u64,               // Oracle price that executes a take-profit/stop-loss order
+    pub parent_order: Pubkey,             // Entry order of a bracket (default for standalone orders)
+    pub linked_order: Pubkey,             // Other leg of a bracket, cancelled when this one executes (take-profit leg for an entry)
     pub bump: u8,                         // PDA bump
 }
 
 impl Order {
-    pub const SPACE: usize = 8 + 32 + 32 + 1 + 1 + 8 + 8 + 8 + 8 + 8 + 8 + 1 + 1;
+    pub const SPACE: usize = 8 + // discriminator
+        32 + // trader: Pubkey
+        32 + // market: Pubkey
+        32 + // margin_account: Pubkey
+        1 + // side: Side
+        1 + // order_type: OrderType
+        8 + // price: u64
+        8 + // size: u64
+        8 + // filled_size: u64
+        8 + // leverage: u64
+        8 + // collateral: u64
+        8 + // created_at: i64
+        1 + // is_active: bool
+        8 + // client_order_id: u64
+        1 + // stp_mode: SelfTradePrevention
+        8 + // stp_group: u64
+        8 + // trigger_price: u64
+        32 + // parent_order: Pubkey
+        32 + // linked_order: Pubkey
+        1; // bump: u8
+}
+
+#[account]
+pub struct LiquidationAuction {
+    pub market: Pubkey,                   // Market of the auctioned position
+    pub position: Pubkey,                 // Position being auctioned
+    pub margin_account: Pubkey,           // Margin account holding the position
+    pub keeper: Pubkey,                   // Keeper that started the auction and paid its rent
+    pub size: u64,                        // Position size when the auction started
+    pub start_slot: u64,                  // Slot the auction started at
+    pub bump: u8,                         // PDA bump
+}
+
+impl LiquidationAuction {
+    pub const SPACE: usize = 8 + // discriminator
+        32 + // market: Pubkey
+        32 + // position: Pubkey
+        32 + // margin_account: Pubkey
+        32 + // keeper: Pubkey
+        8 + // size: u64
+        8 + // start_slot: u64
+        1; // bump: u8
+}
+
+#[account]
+#[cfg_attr(test, derive(Default))]
+pub struct LpPool {
+    pub market: Pubkey,                   // Market whose trader PnL the pool takes the other side of
+    pub share_mint: Pubkey,               // Mint of the pool's share tokens
+    pub net_deposits: i64,                // Collateral deposited minus collateral withdrawn
+    pub realized_pnl: i64,                // Counterparty PnL settled into (or out of) the pool vault
+    pub withdrawal_cooldown: i64,         // Seconds between a withdrawal request and its payout
+    pub bump: u8,                         // PDA bump
+    pub withdrawal_window: i64,           // Seconds after the cooldown during which a request can be redeemed
+    pub fee_pool_baseline: u64,           // Market fee pool after the last settlement
+    pub fee_income: u64,                  // Fees settled into the pool vault
+    pub vault: Pubkey,                    // Token account holding the pool's collateral, apart from the market vault
+}
+
+impl LpPool {
+    pub const SPACE: usize = 8 + // discriminator
+        32 + // market: Pubkey
+        32 + // share_mint: Pubkey
+        8 + // net_deposits: i64
+        8 + // realized_pnl: i64
+        8 + // withdrawal_cooldown: i64
+        1 + // bump: u8
+        8 + // withdrawal_window: i64
+        8 + // fee_pool_baseline: u64
+        8 + // fee_income: u64
+        32; // vault: Pubkey
+}
+
+#[account]
+pub struct LpWithdrawal {
+    pub pool: Pubkey,                     // Pool the shares are withdrawn from
+    pub owner: Pubkey,                    // Owner of the shares
+    pub shares: u64,                      // Shares to redeem once the cooldown has passed
+    pub requested_at: i64,                // Time of the request, restarting the cooldown
+    pub bump: u8,                         // PDA bump
+}
+
+impl LpWithdrawal {
+    pub const SPACE: usize = 8 + // discriminator
+        32 + // pool: Pubkey
+        32 + // owner: Pubkey
+        8 + // shares: u64
+        8 + // requested_at: i64
+        1; // bump: u8
 }
 
 #[account]
@@ -136,9 +375,10 @@ pub struct MarginAccount {
     pub margin_type: MarginType,  // New field to specify margin type
     pub collateral_mint: Pubkey,  // Token mint this margin account is tied to (e.g., USDC)
     pub collateral: u64,
-    pub allocated_margin: u64,    // For isolated margin tracking
+    pub allocated_margin: u64,    // Isolated: collateral of positions and resting orders; cross: of resting orders
     pub positions: Vec<Pubkey>,   // Only track positions now
     pub bump: u8,
+    pub collateral_market: Pubkey, // Market whose vault holds the collateral, set on first deposit
 }
 
 impl MarginAccount {
@@ -149,7 +389,8 @@ impl MarginAccount {
         8 + // collateral: u64
         8 + // allocated_margin: u64
         4 + (32 * 10) + // positions: Vec<Pubkey> (max 10 positions)
-        1; // bump: u8
+        1 + // bump: u8
+        32; // collateral_market: Pubkey
 
     pub fn available_margin(&self) -> Result<u64> {
         match self.margin_type {
@@ -159,8 +400,9 @@ impl MarginAccount {
                     .ok_or(ErrorCode::MathOverflow.into())
             },
             MarginType::Cross => {
-                // For cross margin, all collateral is available
-                Ok(self.collateral)
+                // For cross margin, all collateral not reserved by resting orders is available.
+                // Realized losses can leave less collateral than the orders reserved.
+                Ok(self.collateral.saturating_sub(self.allocated_margin))
             }
         }
     }
@@ -0,0 +1,545 @@
+// Virtual AMM pricing on a constant-product curve.
+// Trades move the market's base and quote reserves along x * y = k, and the peg multiplier
+// converts quote reserves into prices: mark price = quote_asset_reserve * peg / base_asset_reserve.
+// Every fill also pays a dynamic half spread around the curve price, which accrues to fee_pool.
+use anchor_lang::prelude::*;
+use mock_oracle::Oracle;
+use crate::{errors::ErrorCode, events::SpreadChargedEvent, risk::div_ceil, Market, Side};
+
+/// Seconds between oracle observations at which a new move fully replaces the volatility
+/// average. Closer observations are weighted in proportion to the time between them.
+pub const VOLATILITY_EMA_WINDOW: i64 = 3600;
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default)]
+pub struct AmmQuote {
+    pub size: u64,
+    pub execution_price: u64,     // Average price of the whole trade
+    pub quote_amount: u64,        // Quote paid for a long, received for a short
+    pub mark_price: u64,          // Mark price before the trade
+    pub new_mark_price: u64,      // Mark price after the trade
+    pub price_impact: u64,        // Distance of the execution price from the mark price (bps)
+    pub base_asset_reserve: u64,  // Reserves after the trade
+    pub quote_asset_reserve: u64,
+}
+
+/// Constant-product invariant k = x * y
+pub fn invariant(market: &Market) -> Result<u128> {
+    (market.base_asset_reserve as u128)
+        .checked_mul(market.quote_asset_reserve as u128)
+        .ok_or(ErrorCode::MathOverflow.into())
+}
+
+/// Current price of the curve
+pub fn mark_price(market: &Market) -> Result<u64> {
+    reserve_price(market.base_asset_reserve, market.quote_asset_reserve, market.peg_multiplier)
+}
+
+fn reserve_price(base_asset_reserve: u64, quote_asset_reserve: u64, peg_multiplier: u64) -> Result<u64> {
+    require!(base_asset_reserve > 0, ErrorCode::InsufficientAmmLiquidity);
+    (quote_asset_reserve as u128)
+        .checked_mul(peg_multiplier as u128)
+        .and_then(|value| value.checked_div(base_asset_reserve as u128))
+        .and_then(|price| u64::try_from(price).ok())
+        .ok_or(ErrorCode::MathOverflow.into())
+}
+
+/// Price a trade of `size` base units against the curve without executing it. Longs buy base
+/// out of the pool and shorts sell base into it. Rounding always favors the AMM.
+pub fn quote_trade(market: &Market, side: Side, size: u64) -> Result<AmmQuote> {
+    let mark_price = mark_price(market)?;
+    if size == 0 {
+        return Ok(AmmQuote {
+            execution_price: mark_price,
+            mark_price,
+            new_mark_price: mark_price,
+            base_asset_reserve: market.base_asset_reserve,
+            quote_asset_reserve: market.quote_asset_reserve,
+            ..AmmQuote::default()
+        });
+    }
+
+    let k = invariant(market)?;
+    let peg = market.peg_multiplier as u128;
+    let new_base_asset_reserve = match side {
+        Side::Long => {
+            require!(size < market.base_asset_reserve, ErrorCode::InsufficientAmmLiquidity);
+            market.base_asset_reserve - size
+        }
+        Side::Short => market.base_asset_reserve
+            .checked_add(size)
+            .ok_or(ErrorCode::MathOverflow)?,
+    };
+
+    // Quote is priced at full precision (k * peg / x) before it is stored in reserve units
+    let quote_value = div_ceil(
+        k.checked_mul(peg).ok_or(ErrorCode::MathOverflow)?,
+        new_base_asset_reserve as u128,
+    )?;
+    let old_quote_value = (market.quote_asset_reserve as u128)
+        .checked_mul(peg)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let (quote_amount, execution_price) = match side {
+        Side::Long => {
+            let amount = quote_value.saturating_sub(old_quote_value);
+            (amount, div_ceil(amount, size as u128)?)
+        }
+        Side::Short => {
+            let amount = old_quote_value.saturating_sub(quote_value);
+            (amount, amount / size as u128)
+        }
+    };
+    // The stored reserve rounds against whoever trades back: down after a long so closing it
+    // sells into a lower curve, up after a short so closing it buys from a higher one
+    let new_quote_asset_reserve = match side {
+        Side::Long => k / new_base_asset_reserve as u128,
+        Side::Short => div_ceil(k, new_base_asset_reserve as u128)?,
+    };
+    let new_quote_asset_reserve = u64::try_from(new_quote_asset_reserve)
+        .map_err(|_| ErrorCode::MathOverflow)?;
+
+    let execution_price = u64::try_from(execution_price).map_err(|_| ErrorCode::MathOverflow)?;
+
+    Ok(AmmQuote {
+        size,
+        execution_price,
+        quote_amount: u64::try_from(quote_amount).map_err(|_| ErrorCode::MathOverflow)?,
+        mark_price,
+        new_mark_price: reserve_price(new_base_asset_reserve, new_quote_asset_reserve, market.peg_multiplier)?,
+        price_impact: price_impact(execution_price, mark_price)?,
+        base_asset_reserve: new_base_asset_reserve,
+        quote_asset_reserve: new_quote_asset_reserve,
+    })
+}
+
+/// Quote a trade the way `execute_trade` would fill it now: the curve price plus the half
+/// spread, with the oracle's latest observation folded into the volatility average
+pub fn quote_trade_with_spread(market: &Market, side: Side, size: u64, oracle: &Oracle) -> Result<AmmQuote> {
+    let mut market = market.clone();
+    update_oracle_volatility(&mut market, oracle.price, oracle.timestamp)?;
+    let half_spread = half_spread(&market, side, oracle)?;
+
+    let mut quote = quote_trade(&market, side, size)?;
+    quote.execution_price = apply_spread(side, quote.execution_price, half_spread)?;
+    quote.price_impact = price_impact(quote.execution_price, quote.mark_price)?;
+    Ok(quote)
+}
+
+/// Distance of an execution price from the mark price, in basis points
+fn price_impact(execution_price: u64, mark_price: u64) -> Result<u64> {
+    (execution_price.abs_diff(mark_price) as u128)
+        .checked_mul(10000)
+        .and_then(|value| value.checked_div(mark_price.max(1) as u128))
+        .and_then(|value| u64::try_from(value).ok())
+        .ok_or(ErrorCode::MathOverflow.into())
+}
+
+/// Execute a trade against the curve, moving its reserves, and return the execution price
+/// including the half spread. The spread's revenue is credited to the fee pool.
+///
+/// Fills that open exposure are rejected outside the oracle band. Fills that only reduce or
+/// liquidate positions must not be blocked by it, so their curve price is clamped to the band.
+pub fn execute_trade(market: &mut Account<Market>, side: Side, size: u64, oracle: &Oracle, reducing: bool) -> Result<u64> {
+    update_oracle_volatility(market, oracle.price, oracle.timestamp)?;
+    let half_spread = half_spread(market, side, oracle)?;
+
+    let mut quote = quote_trade(market, side, size)?;
+    if reducing {
+        quote.execution_price = clamp_to_oracle_band(market, quote.execution_price, oracle.price)?;
+    } else {
+        check_oracle_band(market, quote.execution_price, oracle.price)?;
+    }
+    market.base_asset_reserve = quote.base_asset_reserve;
+    market.quote_asset_reserve = quote.quote_asset_reserve;
+    // The vAMM takes the other side of every fill
+    let size_delta = i64::try_from(size).map_err(|_| ErrorCode::MathOverflow)?;
+    market.amm_net_base = match side {
+        Side::Long => market.amm_net_base.checked_sub(size_delta),
+        Side::Short => market.amm_net_base.checked_add(size_delta),
+    }
+    .ok_or(ErrorCode::MathOverflow)?;
+
+    let execution_price = apply_spread(side, quote.execution_price, half_spread)?;
+    let revenue = execution_price
+        .abs_diff(quote.execution_price)
+        .checked_mul(size)
+        .ok_or(ErrorCode::MathOverflow)?;
+    market.fee_pool = market.fee_pool
+        .checked_add(revenue)
+        .ok_or(ErrorCode::MathOverflow)?;
+    // Trader PnL is measured at spread-inclusive prices, so the spread would reach the LP pool
+    // through counterparty_pnl as well. Take it back out; it belongs to the fee pool only.
+    market.counterparty_pnl = market.counterparty_pnl
+        .checked_sub(i64::try_from(revenue).map_err(|_| ErrorCode::MathOverflow)?)
+        .ok_or(ErrorCode::MathOverflow)?;
+    msg!(
+        "vAMM {:?} {} at {} (curve {}, mark {} -> {}, impact {} bps, half spread {} bps)",
+        side,
+        size,
+        execution_price,
+        quote.execution_price,
+        quote.mark_price,
+        quote.new_mark_price,
+        quote.price_impact,
+        half_spread
+    );
+
+    emit!(SpreadChargedEvent {
+        market: market.key(),
+        side,
+        size,
+        reference_price: quote.execution_price,
+        execution_price,
+        half_spread,
+        revenue,
+        fee_pool: market.fee_pool,
+    });
+
+    Ok(execution_price)
+}
+
+/// Require the curve price of a fill to lie within the market's band around the oracle price.
+/// The spread is bounded separately by `max_spread`, so it is not counted here.
+fn check_oracle_band(market: &Market, price: u64, oracle_price: u64) -> Result<()> {
+    if market.max_oracle_deviation == 0 {
+        return Ok(());
+    }
+    let deviation = (price.abs_diff(oracle_price) as u128)
+        .checked_mul(10000)
+        .and_then(|value| value.checked_div(oracle_price.max(1) as u128))
+        .ok_or(ErrorCode::MathOverflow)?;
+    if deviation > market.max_oracle_deviation as u128 {
+        msg!("Fill price {} is {} bps from oracle price {}", price, deviation, oracle_price);
+        return err!(ErrorCode::OracleDeviationExceeded);
+    }
+
+    Ok(())
+}
+
+/// Move a fill price to the nearest edge of the market's band around the oracle price
+fn clamp_to_oracle_band(market: &Market, price: u64, oracle_price: u64) -> Result<u64> {
+    if market.max_oracle_deviation == 0 {
+        return Ok(price);
+    }
+    let width = (oracle_price as u128)
+        .checked_mul(market.max_oracle_deviation as u128)
+        .ok_or(ErrorCode::MathOverflow)?
+        / 10000;
+    let width = u64::try_from(width).map_err(|_| ErrorCode::MathOverflow)?;
+
+    Ok(price.clamp(oracle_price.saturating_sub(width), oracle_price.saturating_add(width)))
+}
+
+/// Half spread a trade on `side` pays around the curve price, in basis points: the base spread,
+/// plus recent oracle volatility, plus the oracle's confidence interval, plus a skew charge for
+/// trades that add to the heavier side of open interest. Capped at the market's maximum.
+pub fn half_spread(market: &Market, side: Side, oracle: &Oracle) -> Result<u64> {
+    let confidence = (oracle.confidence as u128)
+        .checked_mul(10000)
+        .and_then(|value| value.checked_div(oracle.price.max(1) as u128))
+        .map(|value| value.min(u64::MAX as u128) as u64)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    let (same_side, other_side) = match side {
+        Side::Long => (market.long_open_interest, market.short_open_interest),
+        Side::Short => (market.short_open_interest, market.long_open_interest),
+    };
+    let skew = if same_side > other_side {
+        (market.skew_spread as u128)
+            .checked_mul((same_side - other_side) as u128)
+            .and_then(|value| value.checked_div(same_side as u128 + other_side as u128))
+            .ok_or(ErrorCode::MathOverflow)? as u64
+    } else {
+        0
+    };
+
+    Ok(market.base_spread
+        .saturating_add(market.oracle_volatility)
+        .saturating_add(confidence)
+        .saturating_add(skew)
+        .min(market.max_spread))
+}
+
+/// Fold the move since the last observed oracle price into the market's volatility average,
+/// weighted by the time the oracle took to make it. Repeated observations of the same oracle
+/// update carry no weight, so many fills in one slot cannot wash out the average.
+pub fn update_oracle_volatility(market: &mut Market, price: u64, timestamp: i64) -> Result<()> {
+    if market.last_oracle_price > 0 {
+        let elapsed = timestamp.saturating_sub(market.last_volatility_update);
+        if elapsed <= 0 {
+            return Ok(());
+        }
+        let weight = (elapsed.min(VOLATILITY_EMA_WINDOW) as u128)
+            .checked_mul(10000)
+            .and_then(|value| value.checked_div(VOLATILITY_EMA_WINDOW as u128))
+            .ok_or(ErrorCode::MathOverflow)?;
+        let change = (price.abs_diff(market.last_oracle_price) as u128)
+            .checked_mul(10000)
+            .and_then(|value| value.checked_div(market.last_oracle_price as u128))
+            .ok_or(ErrorCode::MathOverflow)?;
+        let volatility = (market.oracle_volatility as u128)
+            .checked_mul(10000 - weight)
+            .and_then(|value| value.checked_add(change.checked_mul(weight)?))
+            .map(|value| value / 10000)
+            .ok_or(ErrorCode::MathOverflow)?;
+        market.oracle_volatility = volatility.min(u64::MAX as u128) as u64;
+    }
+    market.last_oracle_price = price;
+    market.last_volatility_update = timestamp;
+
+    Ok(())
+}
+
+/// Longs pay above the reference price and shorts receive below it
+fn apply_spread(side: Side, price: u64, half_spread: u64) -> Result<u64> {
+    let price = price as u128;
+    let spread_price = match side {
+        Side::Long => price
+            .checked_mul(10000 + half_spread as u128)
+            .and_then(|value| div_ceil(value, 10000).ok()),
+        Side::Short => price
+            .checked_mul(10000u128.saturating_sub(half_spread as u128))
+            .map(|value| value / 10000),
+    };
+
+    spread_price
+        .and_then(|price| u64::try_from(price).ok())
+        .ok_or(ErrorCode::MathOverflow.into())
+}
+
+/// Net base position users hold against the vAMM. This is the opposite of what the vAMM has
+/// filled, not of open interest, since takeovers and deleveraging move positions off the curve.
+pub fn net_user_base(market: &Market) -> i128 {
+    -(market.amm_net_base as i128)
+}
+
+/// Quote the vAMM would pay out if users closed their whole net position against a curve
+/// with the given reserves and peg. Negative when users are net short.
+fn terminal_payout(base_asset_reserve: u64, quote_asset_reserve: u64, peg_multiplier: u64, net_user_base: i128) -> Result<i128> {
+    let k = (base_asset_reserve as u128)
+        .checked_mul(quote_asset_reserve as u128)
+        .ok_or(ErrorCode::MathOverflow)?;
+    // Closing longs sells base back into the pool, closing shorts buys it out
+    let terminal_base = (base_asset_reserve as i128)
+        .checked_add(net_user_base)
+        .ok_or(ErrorCode::MathOverflow)?;
+    require!(terminal_base > 0, ErrorCode::InsufficientAmmLiquidity);
+    let terminal_quote = k
+        .checked_div(terminal_base as u128)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    (quote_asset_reserve as i128)
+        .checked_sub(terminal_quote as i128)
+        .and_then(|quote| quote.checked_mul(peg_multiplier as i128))
+        .ok_or(ErrorCode::MathOverflow.into())
+}
+
+/// Cost to the vAMM of moving to new reserves and peg: how much more it would owe users
+/// closing their net position afterwards. Negative values are a profit.
+pub fn adjustment_cost(market: &Market, base_asset_reserve: u64, quote_asset_reserve: u64, peg_multiplier: u64) -> Result<i64> {
+    let net = net_user_base(market);
+    let before = terminal_payout(market.base_asset_reserve, market.quote_asset_reserve, market.peg_multiplier, net)?;
+    let after = terminal_payout(base_asset_reserve, quote_asset_reserve, peg_multiplier, net)?;
+
+    after
+        .checked_sub(before)
+        .and_then(|cost| i64::try_from(cost).ok())
+        .ok_or(ErrorCode::MathOverflow.into())
+}
+
+/// Charge an adjustment's cost to the fee pool, or credit its profit. A cost may not exceed the
+/// market's per-adjustment budget nor its share of the fee pool, so the pool cannot be drained.
+pub fn settle_adjustment_cost(market: &mut Market, cost: i64) -> Result<()> {
+    if cost <= 0 {
+        market.fee_pool = market.fee_pool
+            .checked_add(cost.unsigned_abs())
+            .ok_or(ErrorCode::MathOverflow)?;
+        return Ok(());
+    }
+
+    let fee_pool_budget = (market.fee_pool as u128)
+        .checked_mul(market.amm_fee_pool_share as u128)
+        .map(|budget| budget / 10000)
+        .ok_or(ErrorCode::MathOverflow)? as u64;
+    let budget = market.amm_max_adjustment_cost.min(fee_pool_budget);
+    msg!("Adjustment cost: {}, budget: {}", cost, budget);
+    require!(cost as u64 <= budget, ErrorCode::AmmAdjustmentBudgetExceeded);
+
+    market.fee_pool -= cost as u64;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn market(base_asset_reserve: u64, quote_asset_reserve: u64, peg_multiplier: u64) -> Market {
+        Market {
+            base_asset_reserve,
+            quote_asset_reserve,
+            peg_multiplier,
+            ..Default::default()
+        }
+    }
+
+    fn apply(market: &mut Market, quote: &AmmQuote) {
+        market.base_asset_reserve = quote.base_asset_reserve;
+        market.quote_asset_reserve = quote.quote_asset_reserve;
+    }
+
+    #[test]
+    fn reserve_price_scales_quote_by_peg() {
+        assert_eq!(reserve_price(1_000, 2_000, 50).unwrap(), 100);
+        // Integer division rounds the mark price down
+        assert_eq!(reserve_price(3, 10, 1).unwrap(), 3);
+        assert_eq!(
+            reserve_price(0, 2_000, 50).unwrap_err(),
+            ErrorCode::InsufficientAmmLiquidity.into()
+        );
+    }
+
+    #[test]
+    fn long_round_trip_never_pays_out_more_than_it_took() {
+        let mut market = market(1_000_000, 1_000_000, 1_000_000);
+
+        let open = quote_trade(&market, Side::Long, 1_234).unwrap();
+        assert!(open.execution_price >= open.mark_price);
+        assert!(open.new_mark_price > open.mark_price);
+        assert!(open.execution_price as u128 * 1_234 >= open.quote_amount as u128);
+        apply(&mut market, &open);
+        // The pool's reserve never books more quote than the long paid in
+        assert!(market.quote_asset_reserve as u128 * 1_000_000 <= 1_000_000u128 * 1_000_000 + open.quote_amount as u128);
+
+        let close = quote_trade(&market, Side::Short, 1_234).unwrap();
+        assert!(close.execution_price <= close.mark_price);
+        assert!(close.execution_price as u128 * 1_234 <= close.quote_amount as u128);
+        apply(&mut market, &close);
+
+        assert_eq!(market.base_asset_reserve, 1_000_000);
+        assert!(close.quote_amount <= open.quote_amount);
+    }
+
+    #[test]
+    fn short_round_trip_never_pays_out_more_than_it_took() {
+        let mut market = market(1_000_000, 1_000_000, 1_000_000);
+        let k = invariant(&market).unwrap();
+
+        let open = quote_trade(&market, Side::Short, 777).unwrap();
+        assert!(open.execution_price <= open.mark_price);
+        assert!(open.new_mark_price < open.mark_price);
+        apply(&mut market, &open);
+        assert!(invariant(&market).unwrap() >= k);
+
+        let close = quote_trade(&market, Side::Long, 777).unwrap();
+        assert!(close.execution_price >= close.mark_price);
+        apply(&mut market, &close);
+
+        assert_eq!(market.base_asset_reserve, 1_000_000);
+        assert!(close.quote_amount >= open.quote_amount);
+        assert!(market.quote_asset_reserve >= 1_000_000);
+    }
+
+    #[test]
+    fn rounding_favors_the_amm_on_uneven_trades() {
+        let market = market(7, 10, 3);
+
+        let long = quote_trade(&market, Side::Long, 3).unwrap();
+        // k * peg / x' = 70 * 3 / 4 = 52.5 rounds up to 53, so the long pays 53 - 30 = 23
+        assert_eq!(long.quote_amount, 23);
+        assert_eq!(long.execution_price, 8);
+        // 70 / 4 = 17.5 rounds down so selling back prices off a lower curve
+        assert_eq!(long.quote_asset_reserve, 17);
+
+        let short = quote_trade(&market, Side::Short, 2).unwrap();
+        // 70 * 3 / 9 = 23.33 rounds up to 24, so the short receives 30 - 24 = 6
+        assert_eq!(short.quote_amount, 6);
+        assert_eq!(short.execution_price, 3);
+        // 70 / 9 = 7.78 rounds up so buying back prices off a higher curve
+        assert_eq!(short.quote_asset_reserve, 8);
+    }
+
+    #[test]
+    fn longs_cannot_buy_out_the_whole_base_reserve() {
+        let market = market(1_000, 1_000, 1);
+
+        assert!(quote_trade(&market, Side::Long, 999).is_ok());
+        assert_eq!(
+            quote_trade(&market, Side::Long, 1_000).unwrap_err(),
+            ErrorCode::InsufficientAmmLiquidity.into()
+        );
+        assert_eq!(
+            quote_trade(&market, Side::Long, 1_001).unwrap_err(),
+            ErrorCode::InsufficientAmmLiquidity.into()
+        );
+        // Shorts add base to the pool and are only bounded by overflow
+        assert!(quote_trade(&market, Side::Short, 1_000_000).is_ok());
+    }
+
+    #[test]
+    fn volatility_average_is_weighted_by_elapsed_time() {
+        let mut market = market(1_000, 1_000, 1);
+        update_oracle_volatility(&mut market, 100, 1_000).unwrap();
+        assert_eq!(market.oracle_volatility, 0);
+
+        // A 10% move a quarter of the window later carries a quarter of the weight
+        update_oracle_volatility(&mut market, 110, 1_000 + VOLATILITY_EMA_WINDOW / 4).unwrap();
+        assert_eq!(market.oracle_volatility, 250);
+
+        // The same oracle update seen again, or an older one, leaves the average alone
+        update_oracle_volatility(&mut market, 121, 1_000 + VOLATILITY_EMA_WINDOW / 4).unwrap();
+        update_oracle_volatility(&mut market, 121, 1_000).unwrap();
+        assert_eq!(market.oracle_volatility, 250);
+        assert_eq!(market.last_oracle_price, 110);
+
+        // A move after a full window replaces the average
+        update_oracle_volatility(&mut market, 99, 1_000 + 2 * VOLATILITY_EMA_WINDOW).unwrap();
+        assert_eq!(market.oracle_volatility, 1000);
+    }
+
+    #[test]
+    fn oracle_band_rejects_fills_outside_it() {
+        let mut market = market(1_000, 1_000, 1);
+        assert!(check_oracle_band(&market, 200, 100).is_ok());
+
+        market.max_oracle_deviation = 500;
+        assert!(check_oracle_band(&market, 105, 100).is_ok());
+        assert!(check_oracle_band(&market, 95, 100).is_ok());
+        assert_eq!(
+            check_oracle_band(&market, 106, 100).unwrap_err(),
+            ErrorCode::OracleDeviationExceeded.into()
+        );
+    }
+
+    #[test]
+    fn execution_price_quotes_include_the_half_spread() {
+        let mut market = market(1_000_000, 1_000_000, 1_000_000);
+        market.base_spread = 50;
+        market.max_spread = 500;
+        let oracle = Oracle { price: 1_000_000, authority: Pubkey::default(), timestamp: 0, confidence: 1_000 };
+
+        // 50 bps base spread plus 10 bps of oracle confidence
+        let long = quote_trade_with_spread(&market, Side::Long, 1_000, &oracle).unwrap();
+        let curve = quote_trade(&market, Side::Long, 1_000).unwrap();
+        assert_eq!(long.execution_price, apply_spread(Side::Long, curve.execution_price, 60).unwrap());
+        assert!(long.price_impact > curve.price_impact);
+
+        let short = quote_trade_with_spread(&market, Side::Short, 1_000, &oracle).unwrap();
+        let curve = quote_trade(&market, Side::Short, 1_000).unwrap();
+        assert_eq!(short.execution_price, apply_spread(Side::Short, curve.execution_price, 60).unwrap());
+    }
+
+    #[test]
+    fn reducing_fills_are_clamped_to_the_oracle_band() {
+        let mut market = market(1_000, 1_000, 1);
+        assert_eq!(clamp_to_oracle_band(&market, 200, 100).unwrap(), 200);
+
+        market.max_oracle_deviation = 500;
+        assert_eq!(clamp_to_oracle_band(&market, 103, 100).unwrap(), 103);
+        assert_eq!(clamp_to_oracle_band(&market, 130, 100).unwrap(), 105);
+        assert_eq!(clamp_to_oracle_band(&market, 70, 100).unwrap(), 95);
+        // A clamped price always passes the band check
+        for price in [0, 94, 106, u64::MAX] {
+            let clamped = clamp_to_oracle_band(&market, price, 1_234).unwrap();
+            assert!(check_oracle_band(&market, clamped, 1_234).is_ok());
+        }
+    }
+}
@@ -1,3 +1,5 @@
+#![allow(unexpected_cfgs)]
+
 use anchor_lang::prelude::*;
 declare_id!("7ufLxFvoeg7MukzjBEcs6MqpgEV9Yo6gGBXkPei14WpU");
 
@@ -10,6 +12,7 @@ pub mod mock_oracle {
         oracle.price = initial_price;
         oracle.authority = ctx.accounts.authority.key();
         oracle.timestamp = Clock::get()?.unix_timestamp;
+        oracle.confidence = 0;
         Ok(())
     }
 
@@ -20,6 +23,14 @@ pub mod mock_oracle {
         oracle.timestamp = Clock::get()?.unix_timestamp;
         Ok(())
     }
+
+    pub fn update_confidence(ctx: Context<UpdatePrice>, confidence: u64) -> Result<()> {
+        let oracle = &mut ctx.accounts.oracle;
+        require!(oracle.authority == ctx.accounts.authority.key(), ErrorCode::Unauthorized);
+        oracle.confidence = confidence;
+        oracle.timestamp = Clock::get()?.unix_timestamp;
+        Ok(())
+    }
 }
 
 #[derive(Accounts)]
@@ -28,7 +39,7 @@ pub struct Initialize<'info> {
     #[account(
         init,
         payer = authority,
-        space = 8 + 8 + 32 + 8,
+        space = 8 + 8 + 32 + 8 + 8,
         seeds = [b"oracle", market_symbol.as_bytes()],
         bump
     )]
@@ -50,6 +61,7 @@ pub struct Oracle {
     pub price: u64,
     pub authority: Pubkey,
     pub timestamp: i64,
+    pub confidence: u64,
 }
 
 #[error_code]
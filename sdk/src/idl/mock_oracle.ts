@@ -139,6 +139,10 @@ export type MockOracle = {
           {
             "name": "timestamp",
             "type": "i64"
+          },
+          {
+            "name": "confidence",
+            "type": "u64"
           }
         ]
       }
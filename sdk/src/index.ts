@@ -4,12 +4,26 @@ import { ASSOCIATED_TOKEN_PROGRAM_ID, TOKEN_PROGRAM_ID } from '@solana/spl-token
 import type { Contracts } from "./idl/index"
 import { IDL } from "./idl/index"
 
-import { Market, InitializeMarketParams } from './types/market';
-import { MarginAccount, CreateMarginAccountParams, DepositCollateralParams, WithdrawCollateralParams } from './types/margin-account';
+import { Market, InitializeMarketParams, MarginTier } from './types/market';
+import {
+  MarginAccount,
+  CreateMarginAccountParams,
+  CreateSubMarginAccountParams,
+  DepositCollateralParams,
+  WithdrawCollateralParams
+} from './types/margin-account';
 import { Position, OpenPositionParams, ClosePositionParams, Side } from './types/position';
+import {
+  Order,
+  PlaceLimitOrderParams,
+  PlaceBracketOrderParams,
+  SelfTradePrevention,
+  LiquidationAuction
+} from './types/order';
 import { 
   findMarketPda,
   findMarginAccountPda,
+  findSubMarginAccountPda,
   findPositionPda,
   findMarketVaultPda
 } from './utils';
@@ -212,6 +226,13 @@ export class PerpetualSwapSDK {
         new BN(params.initialMarginRatio),
         new BN(params.maxLeverage),
         new BN(params.liquidationFeeRatio),
+        new BN(params.minOrderSize),
+        new BN(params.orderStepSize),
+        new BN(params.maxPositionSize),
+        new BN(params.maxOpenInterest),
+        new BN(params.baseAssetReserve),
+        new BN(params.quoteAssetReserve),
+        new BN(params.pegMultiplier),
         marketBump
       )
       .accountsStrict({
@@ -321,6 +342,36 @@ export class PerpetualSwapSDK {
     return transaction;
   }
 
+  /**
+   * Build a transaction to create an additional margin account for the same collateral mint
+   */
+  async buildCreateSubMarginAccountTransaction(
+    params: CreateSubMarginAccountParams,
+    userPublicKey: PublicKey
+  ): Promise<Transaction> {
+    const [marginAccountPda] = findSubMarginAccountPda(
+      this.program.programId,
+      userPublicKey,
+      params.collateralMint,
+      params.subAccountId
+    );
+
+    const instruction = await this.program.methods
+      .createSubMarginAccount(params.subAccountId, params.marginType)
+      .accountsStrict({
+        owner: userPublicKey,
+        marginAccount: marginAccountPda,
+        collateralMint: params.collateralMint,
+        systemProgram: SystemProgram.programId,
+      })
+      .instruction();
+
+    const transaction = new Transaction();
+    transaction.add(instruction);
+    
+    return transaction;
+  }
+
   /**
    * Build a transaction to deposit collateral
    */
@@ -378,17 +429,8 @@ export class PerpetualSwapSDK {
       return transaction;
     }
 
-    // If there are positions, get all position PDAs and include them
-    const positionAccounts = await Promise.all(
-      marginAccount.positions.map(async (positionKey) => {
-        const position = await this.getPosition(positionKey);
-        return {
-          pubkey: positionKey,
-          isWritable: true,
-          isSigner: false
-        };
-      })
-    );
+    // If there are positions, include each one with its market and oracle
+    const positionAccounts = await this.getPositionHealthAccounts(marginAccount.positions);
 
     const instruction = await this.program.methods
       .withdrawCollateral(new BN(params.amount))
@@ -437,6 +479,20 @@ export class PerpetualSwapSDK {
     return await this.program.account.position.fetch(positionPda) as unknown as Position;
   }
 
+  /**
+   * Get order details
+   */
+  async getOrder(orderPda: PublicKey): Promise<Order> {
+    return await this.program.account.order.fetch(orderPda) as unknown as Order;
+  }
+
+  /**
+   * Get liquidation auction details
+   */
+  async getLiquidationAuction(auctionPda: PublicKey): Promise<LiquidationAuction> {
+    return await this.program.account.liquidationAuction.fetch(auctionPda) as unknown as LiquidationAuction;
+  }
+
   // ===== UTILITY METHODS =====
 
   /**
@@ -463,6 +519,17 @@ export class PerpetualSwapSDK {
     return findMarginAccountPda(this.program.programId, owner, collateralMint);
   }
 
+  /**
+   * Find the PDA for a sub margin account
+   */
+  async findSubMarginAccountPda(
+    owner: PublicKey,
+    collateralMint: PublicKey,
+    subAccountId: number
+  ): Promise<[PublicKey, number]> {
+    return findSubMarginAccountPda(this.program.programId, owner, collateralMint, subAccountId);
+  }
+
   /**
    * Find the PDA for a position
    */
@@ -478,6 +545,74 @@ export class PerpetualSwapSDK {
     );
   }
 
+  /**
+   * Find the PDA for a position opened by a limit order's fills
+   */
+  async findOrderPositionPda(
+    market: PublicKey,
+    trader: PublicKey,
+    clientOrderId: number
+  ): Promise<[PublicKey, number]> {
+    return PublicKey.findProgramAddress(
+      [
+        Buffer.from("order_position"),
+        market.toBuffer(),
+        trader.toBuffer(),
+        new BN(clientOrderId).toArrayLike(Buffer, "le", 8),
+      ],
+      this.program.programId
+    );
+  }
+
+  /**
+   * Find the PDA for a position a liquidator takes over from an auction or a takeover
+   */
+  async findLiquidationPositionPda(
+    market: PublicKey,
+    liquidator: PublicKey,
+    clientOrderId: number
+  ): Promise<[PublicKey, number]> {
+    return PublicKey.findProgramAddress(
+      [
+        Buffer.from("liquidation_position"),
+        market.toBuffer(),
+        liquidator.toBuffer(),
+        new BN(clientOrderId).toArrayLike(Buffer, "le", 8),
+      ],
+      this.program.programId
+    );
+  }
+
+  /**
+   * Find the PDA for the take-profit leg of a bracket order
+   */
+  async findTakeProfitOrderPda(entryOrder: PublicKey): Promise<[PublicKey, number]> {
+    return PublicKey.findProgramAddress(
+      [Buffer.from("take_profit"), entryOrder.toBuffer()],
+      this.program.programId
+    );
+  }
+
+  /**
+   * Find the PDA for the stop-loss leg of a bracket order
+   */
+  async findStopLossOrderPda(entryOrder: PublicKey): Promise<[PublicKey, number]> {
+    return PublicKey.findProgramAddress(
+      [Buffer.from("stop_loss"), entryOrder.toBuffer()],
+      this.program.programId
+    );
+  }
+
+  /**
+   * Find the PDA for the liquidation auction of a position
+   */
+  async findLiquidationAuctionPda(position: PublicKey): Promise<[PublicKey, number]> {
+    return PublicKey.findProgramAddress(
+      [Buffer.from("liquidation_auction"), position.toBuffer()],
+      this.program.programId
+    );
+  }
+
   /**
    * Get all markets from the program
    */
@@ -590,6 +725,10 @@ export class PerpetualSwapSDK {
       initialMarginRatio?: number;
       fundingInterval?: number;
       maxLeverage?: number;
+      minOrderSize?: number;
+      orderStepSize?: number;
+      maxPositionSize?: number;
+      maxOpenInterest?: number;
     },
     authority: PublicKey
   ): Promise<Transaction> {
@@ -598,7 +737,11 @@ export class PerpetualSwapSDK {
         params.maintenanceMarginRatio ? new BN(params.maintenanceMarginRatio) : null,
         params.initialMarginRatio ? new BN(params.initialMarginRatio) : null,
         params.fundingInterval ? new BN(params.fundingInterval) : null,
-        params.maxLeverage ? new BN(params.maxLeverage) : null
+        params.maxLeverage ? new BN(params.maxLeverage) : null,
+        params.minOrderSize ? new BN(params.minOrderSize) : null,
+        params.orderStepSize ? new BN(params.orderStepSize) : null,
+        params.maxPositionSize ? new BN(params.maxPositionSize) : null,
+        params.maxOpenInterest ? new BN(params.maxOpenInterest) : null
       )
       .accountsStrict({
         market: params.market,
@@ -631,15 +774,26 @@ export class PerpetualSwapSDK {
     return tx;
   }
 
-  buildLiquidateMarketOrderTransaction(
+  async buildLiquidateMarketOrderTransaction(
     params: {
       market: PublicKey;
       position: PublicKey;
       marginAccount: PublicKey;
       oracleAccount: PublicKey;
+      liquidatorTokenAccount: PublicKey;
+      // Vault of the margin account's collateral market, which pays the liquidator fee
+      vault: PublicKey;
+      // Ended liquidation auction, required for positions above the auction size threshold
+      auction?: PublicKey;
     },
     signer: PublicKey
   ): Promise<Transaction> {
+    // Cross accounts are liquidated against the health of all of their positions
+    const marginAccount = await this.program.account.marginAccount.fetch(params.marginAccount) as unknown as MarginAccount;
+    const positionAccounts = 'cross' in marginAccount.marginType
+      ? await this.getPositionHealthAccounts(marginAccount.positions)
+      : [];
+
     return this.program.methods
       .liquidateMarketOrder()
       .accountsStrict({
@@ -647,16 +801,487 @@ export class PerpetualSwapSDK {
         position: params.position,
         marginAccount: params.marginAccount,
         liquidator: signer,
+        liquidatorTokenAccount: params.liquidatorTokenAccount,
+        collateralMarket: marginAccount.collateralMarket,
+        marketVault: params.vault,
+        priceUpdate: params.oracleAccount,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        auction: params.auction ?? null,
+      })
+      .remainingAccounts(positionAccounts)
+      .transaction();
+  }
+
+  async buildPlaceLimitOrderTransaction(
+    params: PlaceLimitOrderParams,
+    signer: PublicKey
+  ): Promise<Transaction> {
+    const [orderPda] = await this.findOrderPda(params.market, signer, params.clientOrderId);
+
+    return this.program.methods
+      .placeLimitOrder(
+        params.side === 'long' ? { long: {} } : { short: {} },
+        params.price,
+        params.size,
+        params.leverage,
+        new BN(params.clientOrderId),
+        this.toStpMode(params.stpMode),
+        params.stpGroup ?? new BN(0)
+      )
+      .accountsStrict({
+        market: params.market,
+        order: orderPda,
+        marginAccount: params.marginAccount,
+        trader: signer,
+        systemProgram: SystemProgram.programId,
+      })
+      .transaction();
+  }
+
+  async buildCancelOrderTransaction(
+    params: {
+      market: PublicKey;
+      marginAccount: PublicKey;
+      clientOrderId: number;
+    },
+    signer: PublicKey
+  ): Promise<Transaction> {
+    const [orderPda] = await this.findOrderPda(params.market, signer, params.clientOrderId);
+
+    // Bracket entries are cancelled together with their legs
+    const order = await this.getOrder(orderPda);
+    const isBracketEntry = !order.linkedOrder.equals(PublicKey.default);
+    const [takeProfitPda] = await this.findTakeProfitOrderPda(orderPda);
+    const [stopLossPda] = await this.findStopLossOrderPda(orderPda);
+
+    return this.program.methods
+      .cancelOrderByClientId(new BN(params.clientOrderId))
+      .accountsStrict({
+        market: params.market,
+        order: orderPda,
+        takeProfitOrder: isBracketEntry ? takeProfitPda : null,
+        stopLossOrder: isBracketEntry ? stopLossPda : null,
+        marginAccount: params.marginAccount,
+        trader: signer,
+      })
+      .transaction();
+  }
+
+  async buildMatchOrdersTransaction(
+    params: {
+      makerOrder: PublicKey;
+      takerOrder: PublicKey;
+    },
+    signer: PublicKey
+  ): Promise<Transaction> {
+    const makerOrder = await this.getOrder(params.makerOrder);
+    const takerOrder = await this.getOrder(params.takerOrder);
+    const [makerPositionPda] = await this.findOrderPositionPda(
+      makerOrder.market,
+      makerOrder.trader,
+      makerOrder.clientOrderId.toNumber()
+    );
+    const [takerPositionPda] = await this.findOrderPositionPda(
+      takerOrder.market,
+      takerOrder.trader,
+      takerOrder.clientOrderId.toNumber()
+    );
+
+    return this.program.methods
+      .matchOrders()
+      .accountsStrict({
+        market: makerOrder.market,
+        makerOrder: params.makerOrder,
+        takerOrder: params.takerOrder,
+        makerMarginAccount: makerOrder.marginAccount,
+        takerMarginAccount: takerOrder.marginAccount,
+        makerPosition: makerPositionPda,
+        takerPosition: takerPositionPda,
+        maker: makerOrder.trader,
+        taker: takerOrder.trader,
+        matcher: signer,
+        systemProgram: SystemProgram.programId,
+      })
+      .transaction();
+  }
+
+  async buildPlaceBracketOrderTransaction(
+    params: PlaceBracketOrderParams,
+    signer: PublicKey
+  ): Promise<Transaction> {
+    const [entryOrderPda] = await this.findOrderPda(params.market, signer, params.clientOrderId);
+    const [takeProfitPda] = await this.findTakeProfitOrderPda(entryOrderPda);
+    const [stopLossPda] = await this.findStopLossOrderPda(entryOrderPda);
+
+    return this.program.methods
+      .placeBracketOrder(
+        params.side === 'long' ? { long: {} } : { short: {} },
+        params.price,
+        params.size,
+        params.leverage,
+        new BN(params.clientOrderId),
+        params.takeProfitPrice,
+        params.stopLossPrice,
+        this.toStpMode(params.stpMode),
+        params.stpGroup ?? new BN(0)
+      )
+      .accountsStrict({
+        market: params.market,
+        entryOrder: entryOrderPda,
+        takeProfitOrder: takeProfitPda,
+        stopLossOrder: stopLossPda,
+        marginAccount: params.marginAccount,
+        trader: signer,
+        systemProgram: SystemProgram.programId,
+      })
+      .transaction();
+  }
+
+  async buildExecuteTriggerOrderTransaction(
+    params: {
+      // Take-profit or stop-loss leg to execute
+      order: PublicKey;
+      oracleAccount: PublicKey;
+    },
+    signer: PublicKey
+  ): Promise<Transaction> {
+    const order = await this.getOrder(params.order);
+    const [positionPda] = await this.findOrderPositionPda(
+      order.market,
+      order.trader,
+      order.clientOrderId.toNumber()
+    );
+
+    return this.program.methods
+      .executeTriggerOrder()
+      .accountsStrict({
+        market: order.market,
+        order: params.order,
+        linkedOrder: order.linkedOrder,
+        entryOrder: order.parentOrder,
+        position: positionPda,
+        marginAccount: order.marginAccount,
+        trader: order.trader,
+        keeper: signer,
+        priceUpdate: params.oracleAccount,
+      })
+      .transaction();
+  }
+
+  async buildCancelBracketLegsTransaction(
+    params: {
+      market: PublicKey;
+      marginAccount: PublicKey;
+      clientOrderId: number;
+    },
+    signer: PublicKey
+  ): Promise<Transaction> {
+    const [entryOrderPda] = await this.findOrderPda(params.market, signer, params.clientOrderId);
+    const [takeProfitPda] = await this.findTakeProfitOrderPda(entryOrderPda);
+    const [stopLossPda] = await this.findStopLossOrderPda(entryOrderPda);
+
+    return this.program.methods
+      .cancelBracketLegs(new BN(params.clientOrderId))
+      .accountsStrict({
+        market: params.market,
+        entryOrder: entryOrderPda,
+        takeProfitOrder: takeProfitPda,
+        stopLossOrder: stopLossPda,
+        marginAccount: params.marginAccount,
+        trader: signer,
+      })
+      .transaction();
+  }
+
+  async buildTransferPositionTransaction(
+    params: {
+      position: PublicKey;
+      fromMarginAccount: PublicKey;
+      toMarginAccount: PublicKey;
+      oracleAccount: PublicKey;
+    },
+    signer: PublicKey
+  ): Promise<Transaction> {
+    const position = await this.getPosition(params.position);
+    const [orderPda] = await this.findOrderPda(position.market, signer, position.clientOrderId.toNumber());
+
+    // Every other position of the source account, followed by every position of the destination
+    const fromMarginAccount = await this.program.account.marginAccount.fetch(params.fromMarginAccount) as unknown as MarginAccount;
+    const toMarginAccount = await this.program.account.marginAccount.fetch(params.toMarginAccount) as unknown as MarginAccount;
+    const positionAccounts = [
+      ...await this.getPositionHealthAccounts(
+        fromMarginAccount.positions.filter((key) => !key.equals(params.position))
+      ),
+      ...await this.getPositionHealthAccounts(toMarginAccount.positions),
+    ];
+
+    return this.program.methods
+      .transferPosition()
+      .accountsStrict({
+        market: position.market,
+        position: params.position,
+        fromMarginAccount: params.fromMarginAccount,
+        toMarginAccount: params.toMarginAccount,
+        order: orderPda,
+        trader: signer,
+        priceUpdate: params.oracleAccount,
+      })
+      .remainingAccounts(positionAccounts)
+      .transaction();
+  }
+
+  async buildLiquidateAccountTransaction(
+    params: {
+      // Cross margin account to liquidate
+      marginAccount: PublicKey;
+      liquidatorTokenAccount: PublicKey;
+      // Vault of the margin account's collateral market, which pays the liquidator fee
+      vault: PublicKey;
+    },
+    signer: PublicKey
+  ): Promise<Transaction> {
+    const marginAccount = await this.program.account.marginAccount.fetch(params.marginAccount) as unknown as MarginAccount;
+    const positionAccounts = await this.getPositionHealthAccounts(marginAccount.positions);
+
+    return this.program.methods
+      .liquidateAccount()
+      .accountsStrict({
+        marginAccount: params.marginAccount,
+        liquidator: signer,
+        liquidatorTokenAccount: params.liquidatorTokenAccount,
+        collateralMarket: marginAccount.collateralMarket,
+        marketVault: params.vault,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .remainingAccounts(positionAccounts)
+      .transaction();
+  }
+
+  async buildUpdateLiquidationAuctionParamsTransaction(
+    params: {
+      market: PublicKey;
+      // Positions of at least this size are liquidated through auctions (0 disables auctions)
+      sizeThreshold: number;
+      durationSlots: number;
+      // Discounts to the oracle price in basis points
+      startDiscount: number;
+      maxDiscount: number;
+    },
+    authority: PublicKey
+  ): Promise<Transaction> {
+    const tx = await this.program.methods
+      .updateLiquidationAuctionParams(
+        new BN(params.sizeThreshold),
+        new BN(params.durationSlots),
+        new BN(params.startDiscount),
+        new BN(params.maxDiscount)
+      )
+      .accountsStrict({
+        market: params.market,
+        authority,
+      })
+      .transaction();
+
+    return tx;
+  }
+
+  async buildStartLiquidationAuctionTransaction(
+    params: {
+      market: PublicKey;
+      position: PublicKey;
+      marginAccount: PublicKey;
+      oracleAccount: PublicKey;
+    },
+    signer: PublicKey
+  ): Promise<Transaction> {
+    const [auctionPda] = await this.findLiquidationAuctionPda(params.position);
+    const marginAccount = await this.program.account.marginAccount.fetch(params.marginAccount) as unknown as MarginAccount;
+    const positionAccounts = 'cross' in marginAccount.marginType
+      ? await this.getPositionHealthAccounts(marginAccount.positions)
+      : [];
+
+    return this.program.methods
+      .startLiquidationAuction()
+      .accountsStrict({
+        market: params.market,
+        position: params.position,
+        marginAccount: params.marginAccount,
+        auction: auctionPda,
+        keeper: signer,
+        priceUpdate: params.oracleAccount,
+        systemProgram: SystemProgram.programId,
+      })
+      .remainingAccounts(positionAccounts)
+      .transaction();
+  }
+
+  async buildTakeLiquidationAuctionTransaction(
+    params: {
+      market: PublicKey;
+      position: PublicKey;
+      marginAccount: PublicKey;
+      liquidatorMarginAccount: PublicKey;
+      oracleAccount: PublicKey;
+      leverage: BN;
+      clientOrderId: number;
+    },
+    signer: PublicKey
+  ): Promise<Transaction> {
+    const [auctionPda] = await this.findLiquidationAuctionPda(params.position);
+    const auction = await this.getLiquidationAuction(auctionPda);
+    const [liquidatorPositionPda] = await this.findLiquidationPositionPda(params.market, signer, params.clientOrderId);
+    const positionAccounts = await this.getTakeoverHealthAccounts(params.marginAccount, params.liquidatorMarginAccount);
+
+    return this.program.methods
+      .takeLiquidationAuction(params.leverage, new BN(params.clientOrderId))
+      .accountsStrict({
+        market: params.market,
+        position: params.position,
+        marginAccount: params.marginAccount,
+        auction: auctionPda,
+        keeper: auction.keeper,
+        liquidator: signer,
+        liquidatorMarginAccount: params.liquidatorMarginAccount,
+        liquidatorPosition: liquidatorPositionPda,
+        priceUpdate: params.oracleAccount,
+        systemProgram: SystemProgram.programId,
+      })
+      .remainingAccounts(positionAccounts)
+      .transaction();
+  }
+
+  async buildCancelLiquidationAuctionTransaction(
+    params: {
+      auction: PublicKey;
+      oracleAccount: PublicKey;
+    },
+    signer: PublicKey
+  ): Promise<Transaction> {
+    const auction = await this.getLiquidationAuction(params.auction);
+
+    // Cross accounts whose position is still open are re-checked against all of their positions
+    const marginAccount = await this.program.account.marginAccount.fetchNullable(auction.marginAccount) as unknown as MarginAccount | null;
+    const positionAccounts = marginAccount && 'cross' in marginAccount.marginType
+      ? await this.getPositionHealthAccounts(marginAccount.positions)
+      : [];
+
+    return this.program.methods
+      .cancelLiquidationAuction()
+      .accountsStrict({
+        market: auction.market,
+        position: auction.position,
+        marginAccount: auction.marginAccount,
+        auction: params.auction,
+        keeper: auction.keeper,
+        signer,
         priceUpdate: params.oracleAccount,
       })
+      .remainingAccounts(positionAccounts)
       .transaction();
   }
+
+  async buildLiquidateWithTakeoverTransaction(
+    params: {
+      market: PublicKey;
+      position: PublicKey;
+      marginAccount: PublicKey;
+      liquidatorMarginAccount: PublicKey;
+      oracleAccount: PublicKey;
+      size: BN;
+      leverage: BN;
+      clientOrderId: number;
+    },
+    signer: PublicKey
+  ): Promise<Transaction> {
+    const [liquidatorPositionPda] = await this.findLiquidationPositionPda(params.market, signer, params.clientOrderId);
+    const positionAccounts = await this.getTakeoverHealthAccounts(params.marginAccount, params.liquidatorMarginAccount);
+
+    return this.program.methods
+      .liquidateWithTakeover(params.size, params.leverage, new BN(params.clientOrderId))
+      .accountsStrict({
+        market: params.market,
+        position: params.position,
+        marginAccount: params.marginAccount,
+        liquidator: signer,
+        liquidatorMarginAccount: params.liquidatorMarginAccount,
+        liquidatorPosition: liquidatorPositionPda,
+        priceUpdate: params.oracleAccount,
+        systemProgram: SystemProgram.programId,
+      })
+      .remainingAccounts(positionAccounts)
+      .transaction();
+  }
+
+  async buildSetMarginTiersTransaction(
+    params: {
+      market: PublicKey;
+      // Ordered by maxSize; maintenanceMarginOffset is computed on chain
+      tiers: MarginTier[];
+    },
+    authority: PublicKey
+  ): Promise<Transaction> {
+    const tx = await this.program.methods
+      .setMarginTiers(params.tiers)
+      .accountsStrict({
+        market: params.market,
+        authority,
+      })
+      .transaction();
+
+    return tx;
+  }
+
+  private toStpMode(stpMode: SelfTradePrevention = 'cancelTaker') {
+    switch (stpMode) {
+      case 'cancelMaker':
+        return { cancelMaker: {} };
+      case 'cancelBoth':
+        return { cancelBoth: {} };
+      default:
+        return { cancelTaker: {} };
+    }
+  }
+
+  /**
+   * Build the health accounts of a takeover: every position of a cross liquidated account,
+   * followed by every position of a cross liquidator account
+   */
+  private async getTakeoverHealthAccounts(marginAccountKey: PublicKey, liquidatorMarginAccountKey: PublicKey) {
+    const marginAccount = await this.program.account.marginAccount.fetch(marginAccountKey) as unknown as MarginAccount;
+    const liquidatorMarginAccount = await this.program.account.marginAccount.fetch(liquidatorMarginAccountKey) as unknown as MarginAccount;
+    return [
+      ...('cross' in marginAccount.marginType
+        ? await this.getPositionHealthAccounts(marginAccount.positions)
+        : []),
+      ...('cross' in liquidatorMarginAccount.marginType
+        ? await this.getPositionHealthAccounts(liquidatorMarginAccount.positions)
+        : []),
+    ];
+  }
+
+  /**
+   * Build (position, market, oracle) account triples used by on-chain health checks
+   */
+  private async getPositionHealthAccounts(positions: PublicKey[]) {
+    return (await Promise.all(
+      positions.map(async (positionKey) => {
+        const position = await this.getPosition(positionKey);
+        const market = await this.getMarket(position.market);
+        return [
+          { pubkey: positionKey, isWritable: false, isSigner: false },
+          { pubkey: position.market, isWritable: false, isSigner: false },
+          { pubkey: market.oracle, isWritable: false, isSigner: false },
+        ];
+      })
+    )).flat();
+  }
 }
 
 // Export types
 export * from './types/market';
 export * from './types/margin-account';
 export * from './types/position';
+export * from './types/order';
 
 // Export utility functions
 export * from './utils';
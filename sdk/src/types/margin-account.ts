@@ -10,6 +10,7 @@ export interface MarginAccount {
   positions: PublicKey[];
   orders: PublicKey[];
   bump: number;
+  collateralMarket: PublicKey;
 }
 
 export interface CreateMarginAccountParams {
@@ -17,6 +18,11 @@ export interface CreateMarginAccountParams {
   collateralMint: PublicKey;
 }
 
+export interface CreateSubMarginAccountParams extends CreateMarginAccountParams {
+  // Sub-account 0 is a separate account from the one created by create_margin_account
+  subAccountId: number;
+}
+
 export interface DepositCollateralParams {
   marginAccount: PublicKey;
   market: PublicKey;
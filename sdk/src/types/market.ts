@@ -1,6 +1,14 @@
 import { PublicKey } from '@solana/web3.js';
 import { BN } from '@coral-xyz/anchor';
 
+export interface MarginTier {
+  maxSize: BN;
+  initialMarginRatio: BN;
+  maintenanceMarginRatio: BN;
+  maintenanceMarginOffset: BN;
+  liquidationFeeRatio: BN;
+}
+
 export interface Market {
   authority: PublicKey;
   marketSymbol: string;
@@ -11,6 +19,7 @@ export interface Market {
   fundingInterval: BN;
   maintenanceMarginRatio: BN;
   initialMarginRatio: BN;
+  liquidationFeeRatio: BN;
   feePool: BN;
   insuranceFund: BN;
   maxLeverage: BN;
@@ -18,6 +27,40 @@ export interface Market {
   vault: PublicKey;
   isActive: boolean;
   bump: number;
+  cumulativeFundingRate: BN;
+  minOrderSize: BN;
+  orderStepSize: BN;
+  maxPositionSize: BN;
+  longOpenInterest: BN;
+  shortOpenInterest: BN;
+  maxOpenInterest: BN;
+  longBadDebt: BN;
+  shortBadDebt: BN;
+  longBankruptcyPrice: BN;
+  shortBankruptcyPrice: BN;
+  auctionSizeThreshold: BN;
+  auctionDurationSlots: BN;
+  auctionStartDiscount: BN;
+  auctionMaxDiscount: BN;
+  // Fixed-size; only the first marginTierCount entries are in use
+  marginTiers: MarginTier[];
+  marginTierCount: number;
+  pegMultiplier: BN;
+  ammMaxAdjustmentCost: BN;
+  ammFeePoolShare: BN;
+  baseSpread: BN;
+  maxSpread: BN;
+  skewSpread: BN;
+  oracleVolatility: BN;
+  lastOraclePrice: BN;
+  longEntryNotional: BN;
+  shortEntryNotional: BN;
+  counterpartyPnl: BN;
+  maxOracleDeviation: BN;
+  ammNetBase: BN;
+  lastVolatilityUpdate: BN;
+  lpVault: PublicKey;
+  lpBalance: BN;
 }
 
 export interface InitializeMarketParams {
@@ -28,6 +71,14 @@ export interface InitializeMarketParams {
   initialMarginRatio: number;
   maxLeverage: number;
   liquidationFeeRatio: number;
+  minOrderSize: number;
+  orderStepSize: number;
+  maxPositionSize: number;
+  maxOpenInterest: number;
+  // vAMM reserves; the initial price is quoteAssetReserve * pegMultiplier / baseAssetReserve
+  baseAssetReserve: number | BN;
+  quoteAssetReserve: number | BN;
+  pegMultiplier: number;
   oracleAccount: PublicKey;
   mint: PublicKey;
 } 
\ No newline at end of file
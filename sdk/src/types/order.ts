@@ -1,3 +1,60 @@
+import { PublicKey } from '@solana/web3.js';
+import { BN } from '@coral-xyz/anchor';
+
 export type OrderType = 'market' | 'limit' | 'stopLoss' | 'takeProfit';
 
-export type Side = 'long' | 'short'; 
\ No newline at end of file
+export type Side = 'long' | 'short';
+
+export type SelfTradePrevention = 'cancelTaker' | 'cancelMaker' | 'cancelBoth';
+
+export interface Order {
+  trader: PublicKey;
+  market: PublicKey;
+  marginAccount: PublicKey;
+  side: { long: {} } | { short: {} };
+  orderType: { market: {} } | { limit: {} } | { stopLoss: {} } | { takeProfit: {} };
+  price: BN;
+  size: BN;
+  filledSize: BN;
+  leverage: BN;
+  collateral: BN;
+  createdAt: BN;
+  isActive: boolean;
+  clientOrderId: BN;
+  stpMode: { cancelTaker: {} } | { cancelMaker: {} } | { cancelBoth: {} };
+  stpGroup: BN;
+  triggerPrice: BN;
+  // Entry order of a bracket (default for standalone orders)
+  parentOrder: PublicKey;
+  // Other leg of a bracket (take-profit leg for an entry)
+  linkedOrder: PublicKey;
+  bump: number;
+}
+
+export interface PlaceLimitOrderParams {
+  market: PublicKey;
+  marginAccount: PublicKey;
+  side: Side;
+  price: BN;
+  size: BN;
+  leverage: BN;
+  clientOrderId: number;
+  stpMode?: SelfTradePrevention;
+  // Self-trade group shared across accounts (0 = none)
+  stpGroup?: BN;
+}
+
+export interface PlaceBracketOrderParams extends PlaceLimitOrderParams {
+  takeProfitPrice: BN;
+  stopLossPrice: BN;
+}
+
+export interface LiquidationAuction {
+  market: PublicKey;
+  position: PublicKey;
+  marginAccount: PublicKey;
+  keeper: PublicKey;
+  size: BN;
+  startSlot: BN;
+  bump: number;
+}
@@ -22,6 +22,7 @@ export interface Position {
   isOpen: boolean;
   createdAt: BN;
   bump: number;
+  clientOrderId: BN;
 }
 
 export interface OpenPositionParams {
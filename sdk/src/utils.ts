@@ -39,6 +39,31 @@ export function findMarginAccountPda(
   );
 }
 
+/**
+ * Find the PDA for a sub margin account
+ * @param programId The program ID
+ * @param owner The owner's public key
+ * @param collateralMint The collateral token mint
+ * @param subAccountId The sub-account ID
+ * @returns A tuple containing the sub margin account PDA and the bump seed
+ */
+export function findSubMarginAccountPda(
+  programId: PublicKey,
+  owner: PublicKey,
+  collateralMint: PublicKey,
+  subAccountId: number
+): [PublicKey, number] {
+  return PublicKey.findProgramAddressSync(
+    [
+      Buffer.from("margin_account"),
+      owner.toBuffer(),
+      collateralMint.toBuffer(),
+      new BN(subAccountId).toArrayLike(Buffer, 'le', 2)
+    ],
+    programId
+  );
+}
+
 /**
  * Find the PDA for a position
  * @param programId The program ID
@@ -3,7 +3,7 @@ import * as anchor from "@coral-xyz/anchor";
 import { Wallet } from "@coral-xyz/anchor";
 import { Connection, Keypair, LAMPORTS_PER_SOL, PublicKey } from "@solana/web3.js";
 import { BN } from "@coral-xyz/anchor";
-import { TOKEN_PROGRAM_ID, createMint, createAccount, mintTo } from "@solana/spl-token";
+import { TOKEN_PROGRAM_ID, createMint, createAccount, mintTo, getOrCreateAssociatedTokenAccount } from "@solana/spl-token";
 import { assert } from "chai";
 import { PerpetualSwapSDK, Network } from "../sdk/src/index";
 import * as fs from 'fs';
@@ -27,6 +27,13 @@ describe("Contract Tests", () => {
   const initialMarginRatio = 200; // 2% (changed from 1000 to support up to 50x leverage)
   const maxLeverage = 30; // Increased from 10 to 50
   const liquidationFeeRatio = 250; // 2.5%
+  const minOrderSize = 1;
+  const orderStepSize = 1;
+  const maxPositionSize = 1_000_000_000;
+  const maxOpenInterest = 1_000_000_000;
+  // Deep vAMM pegged to the starting oracle price of 1000
+  const ammReserve = new BN(1_000_000_000_000);
+  const pegMultiplier = 1000;
 
   // PDAs and accounts
   let marketPda: PublicKey;
@@ -44,6 +51,124 @@ describe("Contract Tests", () => {
   let mockOracleBump: number;
   let connection: Connection;
 
+  // Second trader, also acting as liquidator and keeper
+  let liquidator: Keypair;
+  let liquidatorProvider: anchor.AnchorProvider;
+  let liquidatorTokenAccount: PublicKey;
+  let liquidatorMarginAccountPda: PublicKey;
+
+  // Load the liquidator keypair and fund its token and margin accounts
+  const setUpLiquidator = async () => {
+    if (liquidator) {
+      return;
+    }
+
+    const liquidatorKeypairPath = path.join(__dirname, 'liquidator-keypair.json');
+    if (fs.existsSync(liquidatorKeypairPath)) {
+      const secretKey = new Uint8Array(JSON.parse(fs.readFileSync(liquidatorKeypairPath, 'utf-8')));
+      liquidator = Keypair.fromSecretKey(secretKey);
+    } else {
+      const newLiquidator = Keypair.generate();
+      fs.writeFileSync(liquidatorKeypairPath, JSON.stringify(Array.from(newLiquidator.secretKey)));
+      console.log('\nNew liquidator keypair generated and saved.');
+      console.log('Please fund this wallet with SOL:');
+      console.log('Liquidator Public Key:', newLiquidator.publicKey.toBase58());
+      console.log('\nTo transfer SOL, use this command:');
+      console.log(`solana transfer ${newLiquidator.publicKey.toBase58()} 1 --allow-unfunded-recipient`);
+      throw new Error('Please fund the liquidator wallet and run the test again');
+    }
+
+    const liquidatorWallet = new Wallet(liquidator);
+    liquidatorProvider = new anchor.AnchorProvider(connection as any, liquidatorWallet, {});
+
+    // Token account receiving liquidator fees and funding the liquidator's collateral
+    const tokenAccount = await getOrCreateAssociatedTokenAccount(
+      connection,
+      keypair,
+      tokenMint,
+      liquidator.publicKey
+    );
+    liquidatorTokenAccount = tokenAccount.address;
+
+    [liquidatorMarginAccountPda] = await sdk.findMarginAccountPda(liquidator.publicKey, tokenMint);
+    try {
+      await sdk.getMarginAccount(liquidator.publicKey, tokenMint);
+    } catch (error) {
+      console.log('Creating liquidator margin account...');
+      const createTx = await sdk.buildCreateMarginAccountTransaction({
+        marginType: { isolated: {} },
+        collateralMint: tokenMint
+      }, liquidator.publicKey);
+      await liquidatorProvider.sendAndConfirm(createTx);
+    }
+
+    // Keep enough collateral to take over positions and trade against the main account
+    const targetCollateral = 10_000_000 * 1_000_000;
+    const marginAccount = await sdk.getMarginAccount(liquidator.publicKey, tokenMint);
+    if (marginAccount.collateral.toNumber() < targetCollateral) {
+      const needed = targetCollateral - marginAccount.collateral.toNumber();
+      await mintTo(connection, keypair, tokenMint, liquidatorTokenAccount, mintAuthority, needed);
+
+      const depositTx = await sdk.buildDepositCollateralTransaction({
+        marginAccount: liquidatorMarginAccountPda,
+        market: marketPda,
+        userTokenAccount: liquidatorTokenAccount,
+        vault: marketVaultPda,
+        mint: tokenMint,
+        amount: new BN(needed)
+      }, liquidator.publicKey);
+      await liquidatorProvider.sendAndConfirm(depositTx);
+    }
+  };
+
+  // Create a cross sub margin account of the main wallet holding at least minCollateral
+  const setUpCrossSubAccount = async (subAccountId: number, minCollateral: number) => {
+    const [subAccountPda] = await sdk.findSubMarginAccountPda(keypair.publicKey, tokenMint, subAccountId);
+
+    if (!(await connection.getAccountInfo(subAccountPda))) {
+      console.log(`Creating cross sub margin account ${subAccountId}...`);
+      const createTx = await sdk.buildCreateSubMarginAccountTransaction({
+        subAccountId,
+        marginType: { cross: {} },
+        collateralMint: tokenMint
+      }, keypair.publicKey);
+      await provider.sendAndConfirm(createTx);
+    }
+
+    const subAccount = await sdk.getProgram().account.marginAccount.fetch(subAccountPda);
+    if (subAccount.collateral.toNumber() < minCollateral) {
+      const depositTx = await sdk.buildDepositCollateralTransaction({
+        marginAccount: subAccountPda,
+        market: marketPda,
+        userTokenAccount,
+        vault: marketVaultPda,
+        mint: tokenMint,
+        amount: new BN(minCollateral - subAccount.collateral.toNumber())
+      }, keypair.publicKey);
+      await provider.sendAndConfirm(depositTx);
+    }
+
+    return subAccountPda;
+  };
+
+  // Oracle price at which a long's equity is half of its maintenance margin
+  const longLiquidationPrice = (entryPrice: number, size: number, collateral: number) => {
+    const maintenanceRatio = maintenanceMarginRatio / 10000;
+    return Math.floor((size * entryPrice - collateral) / (size * (1 - maintenanceRatio / 2)));
+  };
+
+  // Assert that a transaction fails with the given program error
+  const expectError = async (promise: Promise<unknown>, errorCode: string) => {
+    try {
+      await promise;
+    } catch (error) {
+      const logs: string[] = error.logs ?? [];
+      assert.include(error.toString() + logs.join('\n'), errorCode);
+      return;
+    }
+    assert.fail(`Expected the transaction to fail with ${errorCode}`);
+  };
+
   before(async () => {
     // Initialize SDK with network configuration
     sdk = PerpetualSwapSDK.createForNetwork(network);
@@ -245,6 +370,13 @@ describe("Contract Tests", () => {
         initialMarginRatio,
         maxLeverage,
         liquidationFeeRatio,
+        minOrderSize,
+        orderStepSize,
+        maxPositionSize,
+        maxOpenInterest,
+        baseAssetReserve: ammReserve,
+        quoteAssetReserve: ammReserve,
+        pegMultiplier,
         oracleAccount: mockOraclePda,
         mint: tokenMint
       });
@@ -952,27 +1084,7 @@ describe("Contract Tests", () => {
       newPrice: 500 // 50% price drop
     });
     
-    // Load or create liquidator keypair
-    const liquidatorKeypairPath = path.join(__dirname, 'liquidator-keypair.json');
-    let liquidator: Keypair;
-    
-    if (fs.existsSync(liquidatorKeypairPath)) {
-      const secretKey = new Uint8Array(JSON.parse(fs.readFileSync(liquidatorKeypairPath, 'utf-8')));
-      liquidator = Keypair.fromSecretKey(secretKey);
-    } else {
-      liquidator = Keypair.generate();
-      fs.writeFileSync(liquidatorKeypairPath, JSON.stringify(Array.from(liquidator.secretKey)));
-      console.log('\nNew liquidator keypair generated and saved.');
-      console.log('Please fund this wallet with SOL:');
-      console.log('Liquidator Public Key:', liquidator.publicKey.toBase58());
-      console.log('\nTo transfer SOL, use this command:');
-      console.log(`solana transfer ${liquidator.publicKey.toBase58()} 1 --allow-unfunded-recipient`);
-      throw new Error('Please fund the liquidator wallet and run the test again');
-    }
-    
-    // Create a new wallet for the liquidator
-    const liquidatorWallet = new Wallet(liquidator);
-    const liquidatorProvider = new anchor.AnchorProvider(connection as any, liquidatorWallet, {});
+    await setUpLiquidator();
     
     // Get the order PDA
     const [orderPda] = await sdk.findOrderPda(marketPda, keypair.publicKey, position.createdAt.toNumber());
@@ -982,7 +1094,9 @@ describe("Contract Tests", () => {
       market: marketPda,
       position: positionPda,
       marginAccount: marginAccountPda,
-      oracleAccount: mockOraclePda
+      oracleAccount: mockOraclePda,
+      liquidatorTokenAccount,
+      vault: marketVaultPda
     }, liquidator.publicKey);
 
     await liquidatorProvider.sendAndConfirm(liquidateTx);
@@ -1094,6 +1208,780 @@ describe("Contract Tests", () => {
     console.log('=== MARKET PARAMETERS UPDATE TEST COMPLETED ===\n');
   });
 
+  it("Matches a resting limit order against a crossing order", async () => {
+    await setUpLiquidator();
+    console.log('\n=== LIMIT ORDER MATCHING TEST ===');
+
+    const price = new BN(1100);
+    const size = new BN(1_000_000);
+    const leverage = new BN(5);
+    const makerClientOrderId = Date.now();
+    const takerClientOrderId = makerClientOrderId + 1;
+
+    // The main wallet rests a long limit order...
+    const makerTx = await sdk.buildPlaceLimitOrderTransaction({
+      market: marketPda,
+      marginAccount: marginAccountPda,
+      side: 'long',
+      price,
+      size,
+      leverage,
+      clientOrderId: makerClientOrderId
+    }, keypair.publicKey);
+    await provider.sendAndConfirm(makerTx);
+
+    // ... which the liquidator's short order crosses
+    const takerTx = await sdk.buildPlaceLimitOrderTransaction({
+      market: marketPda,
+      marginAccount: liquidatorMarginAccountPda,
+      side: 'short',
+      price,
+      size,
+      leverage,
+      clientOrderId: takerClientOrderId
+    }, liquidator.publicKey);
+    await liquidatorProvider.sendAndConfirm(takerTx);
+
+    const [makerOrderPda] = await sdk.findOrderPda(marketPda, keypair.publicKey, makerClientOrderId);
+    const [takerOrderPda] = await sdk.findOrderPda(marketPda, liquidator.publicKey, takerClientOrderId);
+    const makerOrder = await sdk.getOrder(makerOrderPda);
+    console.log('Maker order collateral:', makerOrder.collateral.toNumber());
+    assert.isTrue(makerOrder.isActive);
+
+    const matchTx = await sdk.buildMatchOrdersTransaction({
+      makerOrder: makerOrderPda,
+      takerOrder: takerOrderPda
+    }, keypair.publicKey);
+    await provider.sendAndConfirm(matchTx);
+
+    // Both orders filled completely and were closed
+    assert.isNull(await connection.getAccountInfo(makerOrderPda));
+    assert.isNull(await connection.getAccountInfo(takerOrderPda));
+
+    // Both sides hold a position at the maker's price
+    const [makerPositionPda] = await sdk.findOrderPositionPda(marketPda, keypair.publicKey, makerClientOrderId);
+    const [takerPositionPda] = await sdk.findOrderPositionPda(marketPda, liquidator.publicKey, takerClientOrderId);
+    const makerPosition = await sdk.getPosition(makerPositionPda);
+    const takerPosition = await sdk.getPosition(takerPositionPda);
+    console.log('Maker position:', makerPosition.size.toNumber(), '@', makerPosition.entryPrice.toNumber());
+    console.log('Taker position:', takerPosition.size.toNumber(), '@', takerPosition.entryPrice.toNumber());
+
+    assert.deepEqual(makerPosition.side, { long: {} } as any);
+    assert.deepEqual(takerPosition.side, { short: {} } as any);
+    assert.equal(makerPosition.size.toNumber(), size.toNumber());
+    assert.equal(takerPosition.size.toNumber(), size.toNumber());
+    assert.equal(makerPosition.entryPrice.toNumber(), price.toNumber());
+    assert.equal(takerPosition.entryPrice.toNumber(), price.toNumber());
+
+    const marginAccount = await sdk.getMarginAccount(keypair.publicKey, tokenMint);
+    assert.isTrue(marginAccount.positions.some(p => p.equals(makerPositionPda)));
+
+    // The liquidator closes its side; the main wallet's position is closed by the cleanup
+    const closeTx = await sdk.buildCloseMarketOrderTransaction({
+      market: marketPda,
+      position: takerPositionPda,
+      marginAccount: liquidatorMarginAccountPda,
+      oracleAccount: mockOraclePda
+    }, liquidator.publicKey);
+    await liquidatorProvider.sendAndConfirm(closeTx);
+
+    console.log('=== LIMIT ORDER MATCHING TEST COMPLETED ===\n');
+  });
+
+  it("Cancels the taker of a self-trade instead of filling it", async () => {
+    const price = new BN(1000);
+    const size = new BN(1_000_000);
+    const leverage = new BN(5);
+    const makerClientOrderId = Date.now();
+    const takerClientOrderId = makerClientOrderId + 1;
+
+    const marginAccountBefore = await sdk.getMarginAccount(keypair.publicKey, tokenMint);
+
+    const makerTx = await sdk.buildPlaceLimitOrderTransaction({
+      market: marketPda,
+      marginAccount: marginAccountPda,
+      side: 'long',
+      price,
+      size,
+      leverage,
+      clientOrderId: makerClientOrderId
+    }, keypair.publicKey);
+    await provider.sendAndConfirm(makerTx);
+
+    const takerTx = await sdk.buildPlaceLimitOrderTransaction({
+      market: marketPda,
+      marginAccount: marginAccountPda,
+      side: 'short',
+      price,
+      size,
+      leverage,
+      clientOrderId: takerClientOrderId,
+      stpMode: 'cancelTaker'
+    }, keypair.publicKey);
+    await provider.sendAndConfirm(takerTx);
+
+    const [makerOrderPda] = await sdk.findOrderPda(marketPda, keypair.publicKey, makerClientOrderId);
+    const [takerOrderPda] = await sdk.findOrderPda(marketPda, keypair.publicKey, takerClientOrderId);
+
+    const matchTx = await sdk.buildMatchOrdersTransaction({
+      makerOrder: makerOrderPda,
+      takerOrder: takerOrderPda
+    }, keypair.publicKey);
+    await provider.sendAndConfirm(matchTx);
+
+    // Only the taker was cancelled and nothing was filled
+    assert.isNull(await connection.getAccountInfo(takerOrderPda));
+    const makerOrder = await sdk.getOrder(makerOrderPda);
+    assert.isTrue(makerOrder.isActive);
+    assert.equal(makerOrder.filledSize.toNumber(), 0);
+
+    const [makerPositionPda] = await sdk.findOrderPositionPda(marketPda, keypair.publicKey, makerClientOrderId);
+    assert.isNull(await connection.getAccountInfo(makerPositionPda));
+
+    // Cancelling the maker releases all reserved collateral
+    const cancelTx = await sdk.buildCancelOrderTransaction({
+      market: marketPda,
+      marginAccount: marginAccountPda,
+      clientOrderId: makerClientOrderId
+    }, keypair.publicKey);
+    await provider.sendAndConfirm(cancelTx);
+
+    const marginAccountAfter = await sdk.getMarginAccount(keypair.publicKey, tokenMint);
+    assert.isNull(await connection.getAccountInfo(makerOrderPda));
+    assert.equal(marginAccountAfter.positions.length, marginAccountBefore.positions.length);
+    assert.equal(marginAccountAfter.allocatedMargin.toNumber(), marginAccountBefore.allocatedMargin.toNumber());
+  });
+
+  it("Executes the take-profit leg of a filled bracket order", async () => {
+    await setUpLiquidator();
+    console.log('\n=== BRACKET ORDER TEST ===');
+
+    const price = new BN(1100);
+    const size = new BN(1_000_000);
+    const leverage = new BN(5);
+    const entryClientOrderId = Date.now();
+    const counterpartyClientOrderId = entryClientOrderId + 1;
+
+    const bracketTx = await sdk.buildPlaceBracketOrderTransaction({
+      market: marketPda,
+      marginAccount: marginAccountPda,
+      side: 'long',
+      price,
+      size,
+      leverage,
+      clientOrderId: entryClientOrderId,
+      takeProfitPrice: new BN(1200),
+      stopLossPrice: new BN(1000)
+    }, keypair.publicKey);
+    await provider.sendAndConfirm(bracketTx);
+
+    const [entryOrderPda] = await sdk.findOrderPda(marketPda, keypair.publicKey, entryClientOrderId);
+    const [takeProfitPda] = await sdk.findTakeProfitOrderPda(entryOrderPda);
+    const [stopLossPda] = await sdk.findStopLossOrderPda(entryOrderPda);
+
+    const takeProfit = await sdk.getOrder(takeProfitPda);
+    const stopLoss = await sdk.getOrder(stopLossPda);
+    assert.deepEqual(takeProfit.orderType, { takeProfit: {} } as any);
+    assert.deepEqual(stopLoss.orderType, { stopLoss: {} } as any);
+    assert.equal(takeProfit.linkedOrder.toBase58(), stopLossPda.toBase58());
+    assert.equal(stopLoss.parentOrder.toBase58(), entryOrderPda.toBase58());
+
+    // Legs cannot execute before the entry has filled
+    const unfilledTx = await sdk.buildExecuteTriggerOrderTransaction({
+      order: takeProfitPda,
+      oracleAccount: mockOraclePda
+    }, liquidator.publicKey);
+    await expectError(liquidatorProvider.sendAndConfirm(unfilledTx), 'BracketNotActive');
+
+    // Fill the entry against the liquidator
+    const counterpartyTx = await sdk.buildPlaceLimitOrderTransaction({
+      market: marketPda,
+      marginAccount: liquidatorMarginAccountPda,
+      side: 'short',
+      price,
+      size,
+      leverage,
+      clientOrderId: counterpartyClientOrderId
+    }, liquidator.publicKey);
+    await liquidatorProvider.sendAndConfirm(counterpartyTx);
+
+    const [counterpartyOrderPda] = await sdk.findOrderPda(marketPda, liquidator.publicKey, counterpartyClientOrderId);
+    const matchTx = await sdk.buildMatchOrdersTransaction({
+      makerOrder: entryOrderPda,
+      takerOrder: counterpartyOrderPda
+    }, keypair.publicKey);
+    await provider.sendAndConfirm(matchTx);
+
+    const [positionPda] = await sdk.findOrderPositionPda(marketPda, keypair.publicKey, entryClientOrderId);
+    const position = await sdk.getPosition(positionPda);
+    assert.isTrue(position.isOpen);
+    assert.equal(position.size.toNumber(), size.toNumber());
+
+    // Not triggered below the take-profit price
+    const earlyTx = await sdk.buildExecuteTriggerOrderTransaction({
+      order: takeProfitPda,
+      oracleAccount: mockOraclePda
+    }, liquidator.publicKey);
+    await expectError(liquidatorProvider.sendAndConfirm(earlyTx), 'TriggerNotReached');
+
+    // Triggered once the oracle reaches it
+    await sdk.updateOraclePrice({
+      marketSymbol,
+      newPrice: 1250
+    });
+    const marginAccountBefore = await sdk.getMarginAccount(keypair.publicKey, tokenMint);
+
+    const executeTx = await sdk.buildExecuteTriggerOrderTransaction({
+      order: takeProfitPda,
+      oracleAccount: mockOraclePda
+    }, liquidator.publicKey);
+    await liquidatorProvider.sendAndConfirm(executeTx);
+
+    // The position, both legs and the entry are closed
+    assert.isNull(await connection.getAccountInfo(positionPda));
+    assert.isNull(await connection.getAccountInfo(takeProfitPda));
+    assert.isNull(await connection.getAccountInfo(stopLossPda));
+    assert.isNull(await connection.getAccountInfo(entryOrderPda));
+
+    const marginAccountAfter = await sdk.getMarginAccount(keypair.publicKey, tokenMint);
+    console.log('- Realized PnL:', marginAccountAfter.collateral.toNumber() - marginAccountBefore.collateral.toNumber());
+    assert.isTrue(marginAccountAfter.collateral.gt(marginAccountBefore.collateral));
+    assert.isFalse(marginAccountAfter.positions.some(p => p.equals(positionPda)));
+
+    // Close the liquidator's side of the fill
+    const [counterpartyPositionPda] = await sdk.findOrderPositionPda(marketPda, liquidator.publicKey, counterpartyClientOrderId);
+    const closeTx = await sdk.buildCloseMarketOrderTransaction({
+      market: marketPda,
+      position: counterpartyPositionPda,
+      marginAccount: liquidatorMarginAccountPda,
+      oracleAccount: mockOraclePda
+    }, liquidator.publicKey);
+    await liquidatorProvider.sendAndConfirm(closeTx);
+
+    console.log('=== BRACKET ORDER TEST COMPLETED ===\n');
+  });
+
+  it("Cancels the legs of a resting bracket order", async () => {
+    const clientOrderId = Date.now();
+    const marginAccountBefore = await sdk.getMarginAccount(keypair.publicKey, tokenMint);
+
+    const bracketTx = await sdk.buildPlaceBracketOrderTransaction({
+      market: marketPda,
+      marginAccount: marginAccountPda,
+      side: 'short',
+      price: new BN(1200),
+      size: new BN(1_000_000),
+      leverage: new BN(5),
+      clientOrderId,
+      takeProfitPrice: new BN(1000),
+      stopLossPrice: new BN(1300)
+    }, keypair.publicKey);
+    await provider.sendAndConfirm(bracketTx);
+
+    const [entryOrderPda] = await sdk.findOrderPda(marketPda, keypair.publicKey, clientOrderId);
+    const [takeProfitPda] = await sdk.findTakeProfitOrderPda(entryOrderPda);
+    const [stopLossPda] = await sdk.findStopLossOrderPda(entryOrderPda);
+
+    const cancelLegsTx = await sdk.buildCancelBracketLegsTransaction({
+      market: marketPda,
+      marginAccount: marginAccountPda,
+      clientOrderId
+    }, keypair.publicKey);
+    await provider.sendAndConfirm(cancelLegsTx);
+
+    // The entry keeps resting as a plain limit order
+    assert.isNull(await connection.getAccountInfo(takeProfitPda));
+    assert.isNull(await connection.getAccountInfo(stopLossPda));
+    const entryOrder = await sdk.getOrder(entryOrderPda);
+    assert.isTrue(entryOrder.isActive);
+    assert.equal(entryOrder.linkedOrder.toBase58(), PublicKey.default.toBase58());
+
+    const cancelTx = await sdk.buildCancelOrderTransaction({
+      market: marketPda,
+      marginAccount: marginAccountPda,
+      clientOrderId
+    }, keypair.publicKey);
+    await provider.sendAndConfirm(cancelTx);
+
+    assert.isNull(await connection.getAccountInfo(entryOrderPda));
+    const marginAccountAfter = await sdk.getMarginAccount(keypair.publicKey, tokenMint);
+    assert.equal(marginAccountAfter.allocatedMargin.toNumber(), marginAccountBefore.allocatedMargin.toNumber());
+  });
+
+  it("Liquidates an undercollateralized cross margin account", async () => {
+    await setUpLiquidator();
+    console.log('\n=== CROSS ACCOUNT LIQUIDATION TEST ===');
+
+    const crossAccountPda = await setUpCrossSubAccount(1, 250_000_000);
+    const crossAccountBefore = await sdk.getProgram().account.marginAccount.fetch(crossAccountPda);
+    const collateral = crossAccountBefore.collateral.toNumber();
+
+    // Use half of the collateral the account could open at 10x
+    const size = Math.floor(collateral / 220);
+    const placeOrderTx = await sdk.buildPlaceMarketOrderTransaction({
+      market: marketPda,
+      marginAccount: crossAccountPda,
+      side: 'long',
+      size: new BN(size),
+      leverage: new BN(10),
+      oracleAccount: mockOraclePda
+    }, keypair.publicKey);
+    await provider.sendAndConfirm(placeOrderTx);
+
+    const crossAccount = await sdk.getProgram().account.marginAccount.fetch(crossAccountPda);
+    const positionPda = crossAccount.positions[crossAccount.positions.length - 1];
+    const position = await sdk.getPosition(positionPda);
+
+    // Healthy accounts cannot be liquidated
+    const healthyTx = await sdk.buildLiquidateAccountTransaction({
+      marginAccount: crossAccountPda,
+      liquidatorTokenAccount,
+      vault: marketVaultPda
+    }, liquidator.publicKey);
+    await expectError(liquidatorProvider.sendAndConfirm(healthyTx), 'PositionNotLiquidatable');
+
+    // Cross accounts are liquidated on their total collateral, not the position's own
+    const liquidationPrice = longLiquidationPrice(
+      position.entryPrice.toNumber(),
+      position.size.toNumber(),
+      crossAccount.collateral.toNumber()
+    );
+    console.log('- Collateral:', crossAccount.collateral.toNumber());
+    console.log('- Entry Price:', position.entryPrice.toNumber());
+    console.log('- Liquidation Price:', liquidationPrice);
+    await sdk.updateOraclePrice({
+      marketSymbol,
+      newPrice: liquidationPrice
+    });
+
+    const liquidatorBalanceBefore = await connection.getTokenAccountBalance(liquidatorTokenAccount);
+    const liquidateTx = await sdk.buildLiquidateAccountTransaction({
+      marginAccount: crossAccountPda,
+      liquidatorTokenAccount,
+      vault: marketVaultPda
+    }, liquidator.publicKey);
+    await liquidatorProvider.sendAndConfirm(liquidateTx);
+
+    const crossAccountAfter = await sdk.getProgram().account.marginAccount.fetch(crossAccountPda);
+    const liquidatorBalanceAfter = await connection.getTokenAccountBalance(liquidatorTokenAccount);
+    console.log('- Collateral After:', crossAccountAfter.collateral.toNumber());
+
+    assert.equal(crossAccountAfter.positions.length, 0);
+    assert.isNull(await connection.getAccountInfo(positionPda));
+    assert.isTrue(crossAccountAfter.collateral.lt(crossAccount.collateral));
+    assert.isTrue(new BN(liquidatorBalanceAfter.value.amount).gt(new BN(liquidatorBalanceBefore.value.amount)));
+
+    console.log('=== CROSS ACCOUNT LIQUIDATION TEST COMPLETED ===\n');
+  });
+
+  it("Liquidates a large position through a liquidation auction", async () => {
+    await setUpLiquidator();
+    console.log('\n=== LIQUIDATION AUCTION TEST ===');
+
+    const size = 5_000_000;
+    const auctionParamsTx = await sdk.buildUpdateLiquidationAuctionParamsTransaction({
+      market: marketPda,
+      sizeThreshold: size,
+      durationSlots: 1_000,
+      startDiscount: 100,
+      maxDiscount: 500
+    }, keypair.publicKey);
+    await provider.sendAndConfirm(auctionParamsTx);
+
+    try {
+      const placeOrderTx = await sdk.buildPlaceMarketOrderTransaction({
+        market: marketPda,
+        marginAccount: marginAccountPda,
+        side: 'long',
+        size: new BN(size),
+        leverage: new BN(10),
+        oracleAccount: mockOraclePda
+      }, keypair.publicKey);
+      await provider.sendAndConfirm(placeOrderTx);
+
+      const marginAccount = await sdk.getMarginAccount(keypair.publicKey, tokenMint);
+      const positionPda = marginAccount.positions[0];
+      const position = await sdk.getPosition(positionPda);
+
+      await sdk.updateOraclePrice({
+        marketSymbol,
+        newPrice: longLiquidationPrice(
+          position.entryPrice.toNumber(),
+          position.size.toNumber(),
+          position.collateral.toNumber()
+        )
+      });
+
+      // Positions above the threshold cannot be liquidated outright
+      const liquidateTx = await sdk.buildLiquidateMarketOrderTransaction({
+        market: marketPda,
+        position: positionPda,
+        marginAccount: marginAccountPda,
+        oracleAccount: mockOraclePda,
+        liquidatorTokenAccount,
+        vault: marketVaultPda
+      }, liquidator.publicKey);
+      await expectError(liquidatorProvider.sendAndConfirm(liquidateTx), 'LiquidationAuctionRequired');
+
+      const startTx = await sdk.buildStartLiquidationAuctionTransaction({
+        market: marketPda,
+        position: positionPda,
+        marginAccount: marginAccountPda,
+        oracleAccount: mockOraclePda
+      }, liquidator.publicKey);
+      await liquidatorProvider.sendAndConfirm(startTx);
+
+      const [auctionPda] = await sdk.findLiquidationAuctionPda(positionPda);
+      const auction = await sdk.getLiquidationAuction(auctionPda);
+      console.log('Auction started at slot', auction.startSlot.toNumber(), 'for size', auction.size.toNumber());
+      assert.equal(auction.size.toNumber(), size);
+      assert.equal(auction.keeper.toBase58(), liquidator.publicKey.toBase58());
+
+      // The liquidator takes over the whole position at the current discount
+      const clientOrderId = Date.now();
+      const takeTx = await sdk.buildTakeLiquidationAuctionTransaction({
+        market: marketPda,
+        position: positionPda,
+        marginAccount: marginAccountPda,
+        liquidatorMarginAccount: liquidatorMarginAccountPda,
+        oracleAccount: mockOraclePda,
+        leverage: new BN(5),
+        clientOrderId
+      }, liquidator.publicKey);
+      await liquidatorProvider.sendAndConfirm(takeTx);
+
+      assert.isNull(await connection.getAccountInfo(auctionPda));
+      const marginAccountAfter = await sdk.getMarginAccount(keypair.publicKey, tokenMint);
+      assert.isFalse(marginAccountAfter.positions.some(p => p.equals(positionPda)));
+
+      const [liquidatorPositionPda] = await sdk.findLiquidationPositionPda(marketPda, liquidator.publicKey, clientOrderId);
+      const liquidatorPosition = await sdk.getPosition(liquidatorPositionPda);
+      console.log('Liquidator position:', liquidatorPosition.size.toNumber(), '@', liquidatorPosition.entryPrice.toNumber());
+      assert.equal(liquidatorPosition.size.toNumber(), size);
+      assert.deepEqual(liquidatorPosition.side, { long: {} } as any);
+      assert.isTrue(liquidatorPosition.entryPrice.lt(position.entryPrice));
+
+      const closeTx = await sdk.buildCloseMarketOrderTransaction({
+        market: marketPda,
+        position: liquidatorPositionPda,
+        marginAccount: liquidatorMarginAccountPda,
+        oracleAccount: mockOraclePda
+      }, liquidator.publicKey);
+      await liquidatorProvider.sendAndConfirm(closeTx);
+    } finally {
+      // Disable auctions again for the other liquidation tests
+      const resetTx = await sdk.buildUpdateLiquidationAuctionParamsTransaction({
+        market: marketPda,
+        sizeThreshold: 0,
+        durationSlots: 0,
+        startDiscount: 0,
+        maxDiscount: 0
+      }, keypair.publicKey);
+      await provider.sendAndConfirm(resetTx);
+    }
+
+    console.log('=== LIQUIDATION AUCTION TEST COMPLETED ===\n');
+  });
+
+  it("Cancels a liquidation auction once the position is healthy again", async () => {
+    await setUpLiquidator();
+
+    const size = 5_000_000;
+    const auctionParamsTx = await sdk.buildUpdateLiquidationAuctionParamsTransaction({
+      market: marketPda,
+      sizeThreshold: size,
+      durationSlots: 1_000,
+      startDiscount: 100,
+      maxDiscount: 500
+    }, keypair.publicKey);
+    await provider.sendAndConfirm(auctionParamsTx);
+
+    try {
+      const placeOrderTx = await sdk.buildPlaceMarketOrderTransaction({
+        market: marketPda,
+        marginAccount: marginAccountPda,
+        side: 'long',
+        size: new BN(size),
+        leverage: new BN(10),
+        oracleAccount: mockOraclePda
+      }, keypair.publicKey);
+      await provider.sendAndConfirm(placeOrderTx);
+
+      const marginAccount = await sdk.getMarginAccount(keypair.publicKey, tokenMint);
+      const positionPda = marginAccount.positions[0];
+      const position = await sdk.getPosition(positionPda);
+
+      await sdk.updateOraclePrice({
+        marketSymbol,
+        newPrice: longLiquidationPrice(
+          position.entryPrice.toNumber(),
+          position.size.toNumber(),
+          position.collateral.toNumber()
+        )
+      });
+
+      const startTx = await sdk.buildStartLiquidationAuctionTransaction({
+        market: marketPda,
+        position: positionPda,
+        marginAccount: marginAccountPda,
+        oracleAccount: mockOraclePda
+      }, liquidator.publicKey);
+      await liquidatorProvider.sendAndConfirm(startTx);
+      const [auctionPda] = await sdk.findLiquidationAuctionPda(positionPda);
+
+      // A running auction of a liquidatable position cannot be cancelled
+      const earlyCancelTx = await sdk.buildCancelLiquidationAuctionTransaction({
+        auction: auctionPda,
+        oracleAccount: mockOraclePda
+      }, keypair.publicKey);
+      await expectError(provider.sendAndConfirm(earlyCancelTx), 'InvalidParameter');
+
+      // The price recovers, so anyone can close the auction
+      await sdk.updateOraclePrice({
+        marketSymbol,
+        newPrice: 1100
+      });
+      const cancelTx = await sdk.buildCancelLiquidationAuctionTransaction({
+        auction: auctionPda,
+        oracleAccount: mockOraclePda
+      }, keypair.publicKey);
+      await provider.sendAndConfirm(cancelTx);
+
+      assert.isNull(await connection.getAccountInfo(auctionPda));
+      assert.isTrue((await sdk.getPosition(positionPda)).isOpen);
+    } finally {
+      const resetTx = await sdk.buildUpdateLiquidationAuctionParamsTransaction({
+        market: marketPda,
+        sizeThreshold: 0,
+        durationSlots: 0,
+        startDiscount: 0,
+        maxDiscount: 0
+      }, keypair.publicKey);
+      await provider.sendAndConfirm(resetTx);
+    }
+  });
+
+  it("Lets a liquidator take over an undercollateralized position", async () => {
+    await setUpLiquidator();
+    console.log('\n=== TAKEOVER LIQUIDATION TEST ===');
+
+    const placeOrderTx = await sdk.buildPlaceMarketOrderTransaction({
+      market: marketPda,
+      marginAccount: marginAccountPda,
+      side: 'long',
+      size: new BN(2_000_000),
+      leverage: new BN(10),
+      oracleAccount: mockOraclePda
+    }, keypair.publicKey);
+    await provider.sendAndConfirm(placeOrderTx);
+
+    const marginAccount = await sdk.getMarginAccount(keypair.publicKey, tokenMint);
+    const positionPda = marginAccount.positions[0];
+    const position = await sdk.getPosition(positionPda);
+
+    // Healthy positions cannot be taken over
+    const clientOrderId = Date.now();
+    const healthyTx = await sdk.buildLiquidateWithTakeoverTransaction({
+      market: marketPda,
+      position: positionPda,
+      marginAccount: marginAccountPda,
+      liquidatorMarginAccount: liquidatorMarginAccountPda,
+      oracleAccount: mockOraclePda,
+      size: position.size,
+      leverage: new BN(5),
+      clientOrderId
+    }, liquidator.publicKey);
+    await expectError(liquidatorProvider.sendAndConfirm(healthyTx), 'PositionNotLiquidatable');
+
+    const liquidationPrice = longLiquidationPrice(
+      position.entryPrice.toNumber(),
+      position.size.toNumber(),
+      position.collateral.toNumber()
+    );
+    await sdk.updateOraclePrice({
+      marketSymbol,
+      newPrice: liquidationPrice
+    });
+
+    const takeoverTx = await sdk.buildLiquidateWithTakeoverTransaction({
+      market: marketPda,
+      position: positionPda,
+      marginAccount: marginAccountPda,
+      liquidatorMarginAccount: liquidatorMarginAccountPda,
+      oracleAccount: mockOraclePda,
+      size: position.size,
+      leverage: new BN(5),
+      clientOrderId
+    }, liquidator.publicKey);
+    await liquidatorProvider.sendAndConfirm(takeoverTx);
+
+    // The whole position moved to the liquidator, below the oracle price
+    assert.isNull(await connection.getAccountInfo(positionPda));
+    const marginAccountAfter = await sdk.getMarginAccount(keypair.publicKey, tokenMint);
+    assert.isFalse(marginAccountAfter.positions.some(p => p.equals(positionPda)));
+
+    const [liquidatorPositionPda] = await sdk.findLiquidationPositionPda(marketPda, liquidator.publicKey, clientOrderId);
+    const liquidatorPosition = await sdk.getPosition(liquidatorPositionPda);
+    console.log('Liquidator position:', liquidatorPosition.size.toNumber(), '@', liquidatorPosition.entryPrice.toNumber());
+    assert.equal(liquidatorPosition.size.toNumber(), position.size.toNumber());
+    assert.isBelow(liquidatorPosition.entryPrice.toNumber(), liquidationPrice);
+
+    const liquidatorAccount = await sdk.getMarginAccount(liquidator.publicKey, tokenMint);
+    assert.isTrue(liquidatorAccount.positions.some(p => p.equals(liquidatorPositionPda)));
+
+    const closeTx = await sdk.buildCloseMarketOrderTransaction({
+      market: marketPda,
+      position: liquidatorPositionPda,
+      marginAccount: liquidatorMarginAccountPda,
+      oracleAccount: mockOraclePda
+    }, liquidator.publicKey);
+    await liquidatorProvider.sendAndConfirm(closeTx);
+
+    console.log('=== TAKEOVER LIQUIDATION TEST COMPLETED ===\n');
+  });
+
+  it("Applies margin tiers to large positions", async () => {
+    const tiers = [
+      {
+        maxSize: new BN(5_000_000),
+        initialMarginRatio: new BN(200),
+        maintenanceMarginRatio: new BN(100),
+        maintenanceMarginOffset: new BN(0),
+        liquidationFeeRatio: new BN(250),
+      },
+      {
+        maxSize: new BN(1_000_000_000),
+        initialMarginRatio: new BN(1000), // 10x at most
+        maintenanceMarginRatio: new BN(500),
+        maintenanceMarginOffset: new BN(0),
+        liquidationFeeRatio: new BN(250),
+      },
+    ];
+
+    const setTiersTx = await sdk.buildSetMarginTiersTransaction({
+      market: marketPda,
+      tiers
+    }, keypair.publicKey);
+    await provider.sendAndConfirm(setTiersTx);
+
+    try {
+      const market = await sdk.getMarket(marketPda);
+      assert.equal(market.marginTierCount, 2);
+      // Offsets keep the maintenance margin continuous at each tier boundary
+      assert.equal(market.marginTiers[1].maintenanceMarginOffset.toNumber(), 5_000_000 * (500 - 100));
+
+      // 20x is allowed within the first tier...
+      const smallOrderTx = await sdk.buildPlaceMarketOrderTransaction({
+        market: marketPda,
+        marginAccount: marginAccountPda,
+        side: 'long',
+        size: new BN(1_000_000),
+        leverage: new BN(20),
+        oracleAccount: mockOraclePda
+      }, keypair.publicKey);
+      await provider.sendAndConfirm(smallOrderTx);
+
+      // ... but not for sizes in the second tier
+      const largeOrderTx = await sdk.buildPlaceMarketOrderTransaction({
+        market: marketPda,
+        marginAccount: marginAccountPda,
+        side: 'long',
+        size: new BN(10_000_000),
+        leverage: new BN(20),
+        oracleAccount: mockOraclePda
+      }, keypair.publicKey);
+      await expectError(provider.sendAndConfirm(largeOrderTx), 'LeverageTooHigh');
+
+      const allowedOrderTx = await sdk.buildPlaceMarketOrderTransaction({
+        market: marketPda,
+        marginAccount: marginAccountPda,
+        side: 'long',
+        size: new BN(10_000_000),
+        leverage: new BN(5),
+        oracleAccount: mockOraclePda
+      }, keypair.publicKey);
+      await provider.sendAndConfirm(allowedOrderTx);
+
+      const marginAccount = await sdk.getMarginAccount(keypair.publicKey, tokenMint);
+      assert.equal(marginAccount.positions.length, 2);
+    } finally {
+      // Positions are closed by the cleanup; remove the tiers for the other tests
+      const clearTiersTx = await sdk.buildSetMarginTiersTransaction({
+        market: marketPda,
+        tiers: []
+      }, keypair.publicKey);
+      await provider.sendAndConfirm(clearTiersTx);
+    }
+
+    const market = await sdk.getMarket(marketPda);
+    assert.equal(market.marginTierCount, 0);
+  });
+
+  it("Creates a cross sub margin account and transfers a position into it", async () => {
+    console.log('\n=== SUB ACCOUNT TEST ===');
+
+    const subAccountPda = await setUpCrossSubAccount(2, 0);
+    const subAccountBefore = await sdk.getProgram().account.marginAccount.fetch(subAccountPda);
+    assert.equal(subAccountBefore.owner.toBase58(), keypair.publicKey.toBase58());
+    assert.deepEqual(subAccountBefore.marginType, { cross: {} } as any);
+    // Sub-accounts are separate from the main margin account
+    assert.notEqual(subAccountPda.toBase58(), marginAccountPda.toBase58());
+
+    const placeOrderTx = await sdk.buildPlaceMarketOrderTransaction({
+      market: marketPda,
+      marginAccount: marginAccountPda,
+      side: 'long',
+      size: new BN(1_000_000),
+      leverage: new BN(5),
+      oracleAccount: mockOraclePda
+    }, keypair.publicKey);
+    await provider.sendAndConfirm(placeOrderTx);
+
+    const marginAccountBefore = await sdk.getMarginAccount(keypair.publicKey, tokenMint);
+    const positionPda = marginAccountBefore.positions[0];
+    const position = await sdk.getPosition(positionPda);
+
+    const transferTx = await sdk.buildTransferPositionTransaction({
+      position: positionPda,
+      fromMarginAccount: marginAccountPda,
+      toMarginAccount: subAccountPda,
+      oracleAccount: mockOraclePda
+    }, keypair.publicKey);
+    await provider.sendAndConfirm(transferTx);
+
+    // The position and its collateral moved without realizing PnL
+    const marginAccountAfter = await sdk.getMarginAccount(keypair.publicKey, tokenMint);
+    const subAccountAfter = await sdk.getProgram().account.marginAccount.fetch(subAccountPda);
+    console.log('- Position Collateral:', position.collateral.toNumber());
+    console.log('- Sub-account Collateral:', subAccountAfter.collateral.toNumber());
+
+    assert.isFalse(marginAccountAfter.positions.some(p => p.equals(positionPda)));
+    assert.isTrue(subAccountAfter.positions.some(p => p.equals(positionPda)));
+    assert.equal(
+      marginAccountBefore.collateral.toNumber() - marginAccountAfter.collateral.toNumber(),
+      position.collateral.toNumber()
+    );
+    assert.equal(
+      subAccountAfter.collateral.toNumber() - subAccountBefore.collateral.toNumber(),
+      position.collateral.toNumber()
+    );
+    assert.equal((await sdk.getPosition(positionPda)).entryPrice.toNumber(), position.entryPrice.toNumber());
+
+    // The position is now closed from the sub-account
+    const closeTx = await sdk.buildCloseMarketOrderTransaction({
+      market: marketPda,
+      position: positionPda,
+      marginAccount: subAccountPda,
+      oracleAccount: mockOraclePda
+    }, keypair.publicKey);
+    await provider.sendAndConfirm(closeTx);
+
+    const subAccountClosed = await sdk.getProgram().account.marginAccount.fetch(subAccountPda);
+    assert.equal(subAccountClosed.positions.length, 0);
+
+    console.log('=== SUB ACCOUNT TEST COMPLETED ===\n');
+  });
+
   beforeEach(async function() {
     try {
       // Reset oracle price